@@ -0,0 +1,57 @@
+//! Allowlists of known mismatches, passed with `--allowlist`.
+//!
+//! An allowlist names operation files, one per line, as paths relative to the operations
+//! directory (with `/` separators). Blank lines and `#` comments are ignored.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Allowlist {
+    operations: BTreeSet<String>,
+}
+
+impl Allowlist {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        std::fs::read_to_string(path)
+            .map(|source| Self::parse(&source))
+            .map_err(|err| format!("Failed to read allowlist {}: {err}", path.display()))
+    }
+
+    pub fn parse(source: &str) -> Self {
+        let operations = source
+            .lines()
+            .map(|line| line.split_once('#').map_or(line, |(entry, _)| entry).trim())
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| entry.trim_start_matches("./").to_string())
+            .collect();
+        Self { operations }
+    }
+
+    /// Returns true if the operation file, relative to the operations directory, is allowlisted.
+    pub fn contains(&self, operation: &Path) -> bool {
+        let components: Vec<_> = operation
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect();
+        self.operations.contains(&components.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_allowlist() {
+        let allowlist = Allowlist::parse(
+            "# Known mismatches\n\
+             products/top.graphql  # typename placement\n\
+             \n\
+             ./me.graphql\n",
+        );
+        assert!(allowlist.contains(Path::new("products/top.graphql")));
+        assert!(allowlist.contains(Path::new("me.graphql")));
+        assert!(!allowlist.contains(Path::new("top.graphql")));
+    }
+}
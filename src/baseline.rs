@@ -0,0 +1,172 @@
+//! Baselines of batch run outcomes, written with `--baseline` and compared with
+//! `--compare-baseline`.
+//!
+//! A baseline records the outcome of each operation of a run as JSON, keyed by operation name.
+//! Comparing a later run with it reports the operations fixed and regressed since, to track the
+//! progress of a migration over time. Unlike an allowlist, a baseline doesn't affect whether a run
+//! fails.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// The outcome of an operation, as recorded in a baseline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Matched,
+    /// The plans mismatch, allowlisted or not.
+    Mismatch,
+    /// Either planner failed to plan the operation, or it couldn't be read.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    pub outcome: Outcome,
+    /// The fingerprint of the native plan in hexadecimal, if planned (see `plan_fingerprint`).
+    /// Informational only, since fingerprints are only stable within a single build.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+}
+
+/// The outcomes of the operations of a run, by operation name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Baseline {
+    operations: BTreeMap<String, BaselineEntry>,
+}
+
+impl Baseline {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read baseline {}: {err}", path.display()))?;
+        Self::parse(&source).map_err(|err| format!("Invalid baseline {}: {err}", path.display()))
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        serde_json::from_str(source).map_err(|err| err.to_string())
+    }
+
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string_pretty(self).expect("baselines always serialize to JSON")
+    }
+
+    pub fn insert(&mut self, operation: String, entry: BaselineEntry) {
+        self.operations.insert(operation, entry);
+    }
+
+    /// Compares the outcomes of a later run with the baseline. Operations missing from either run
+    /// (e.g. left out by `--sample`) are neither fixed nor regressed.
+    pub fn compare(&self, current: &Baseline) -> BaselineComparison {
+        let mut comparison = BaselineComparison::default();
+        for (operation, entry) in &current.operations {
+            let Some(previous) = self.operations.get(operation) else {
+                continue;
+            };
+            match (previous.outcome, entry.outcome) {
+                (Outcome::Matched, Outcome::Matched) => {}
+                (Outcome::Matched, _) => comparison.regressed.push(operation.clone()),
+                (_, Outcome::Matched) => comparison.fixed.push(operation.clone()),
+                _ => {}
+            }
+        }
+        comparison
+    }
+}
+
+/// The operations whose outcome changed since a baseline, in name order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BaselineComparison {
+    /// Operations matching now, but not in the baseline.
+    pub fixed: Vec<String>,
+    /// Operations matching in the baseline, but not anymore.
+    pub regressed: Vec<String>,
+}
+
+impl BaselineComparison {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "fixed": self.fixed,
+            "regressed": self.regressed,
+        })
+    }
+}
+
+impl fmt::Display for BaselineComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Since the baseline: {} fixed, {} regressed",
+            self.fixed.len(),
+            self.regressed.len()
+        )?;
+        if !self.regressed.is_empty() {
+            write!(f, " ({})", self.regressed.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn baseline(outcomes: &[(&str, Outcome)]) -> Baseline {
+        let mut baseline = Baseline::default();
+        for (operation, outcome) in outcomes {
+            baseline.insert(
+                operation.to_string(),
+                BaselineEntry {
+                    outcome: *outcome,
+                    fingerprint: None,
+                },
+            );
+        }
+        baseline
+    }
+
+    #[test]
+    fn test_compare_baseline() {
+        let previous = baseline(&[
+            ("Me", Outcome::Matched),
+            ("TopProducts", Outcome::Mismatch),
+            ("Reviews", Outcome::Error),
+            ("Inventory", Outcome::Matched),
+            ("Removed", Outcome::Mismatch),
+        ]);
+        let current = baseline(&[
+            ("Me", Outcome::Matched),
+            ("TopProducts", Outcome::Matched),
+            ("Reviews", Outcome::Matched),
+            ("Inventory", Outcome::Error),
+            ("Added", Outcome::Mismatch),
+        ]);
+        let comparison = previous.compare(&current);
+        assert_eq!(comparison.fixed, ["Reviews", "TopProducts"]);
+        assert_eq!(comparison.regressed, ["Inventory"]);
+        assert_eq!(
+            comparison.to_string(),
+            "Since the baseline: 2 fixed, 1 regressed (Inventory)"
+        );
+    }
+
+    #[test]
+    fn test_baseline_round_trip() {
+        let mut previous = baseline(&[("Me", Outcome::Mismatch)]);
+        previous.insert(
+            "TopProducts".to_string(),
+            BaselineEntry {
+                outcome: Outcome::Matched,
+                fingerprint: Some("00000000deadbeef".to_string()),
+            },
+        );
+        let json = previous.to_json_string();
+        assert!(json.contains(r#""outcome": "mismatch""#), "{json}");
+        assert_eq!(Baseline::parse(&json), Ok(previous));
+        assert!(Baseline::parse(r#"{ "Me": { "outcome": "passed" } }"#).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! A reusable comparison session: both planners built once for a schema, and the comparison
+//! options, to compare many operations efficiently.
+//!
+//! `outcome::compare_operation` builds both planners (and a runtime for the legacy one) for every
+//! operation, which dominates the cost of comparing small operations. A `Comparator` builds them
+//! once, in `Comparator::new`, and reuses them for every `Comparator::compare`.
+//!
+//! ```no_run
+//! use qp_compare::CompareOptions;
+//! use qp_compare::comparator::Comparator;
+//!
+//! let schema = std::fs::read_to_string("supergraph.graphql").unwrap();
+//! let comparator = Comparator::new(
+//!     &schema,
+//!     Default::default(),
+//!     Default::default(),
+//!     CompareOptions::default(),
+//! )
+//! .unwrap();
+//! for path in ["top_products.graphql", "me.graphql"] {
+//!     let operation = std::fs::read_to_string(path).unwrap();
+//!     let outcome = comparator.compare(&operation, None);
+//!     println!("{path}: {}", if outcome.is_match() { "PASS" } else { "FAIL" });
+//! }
+//! ```
+
+use std::path::Path;
+use std::time::Instant;
+
+use apollo_compiler::Name;
+use apollo_compiler::Schema;
+use apollo_compiler::validation::Valid;
+
+use crate::CompareOptions;
+use crate::FederationError;
+use crate::LegacyQueryPlanResult;
+use crate::NativeQueryPlan;
+use crate::convert_native_plan;
+use crate::legacy_planner;
+use crate::native_planner;
+use crate::outcome::ComparisonOutcome;
+use crate::outcome::DetailedComparison;
+use crate::plan_with_legacy_planner;
+use crate::plan_with_native_planner;
+use crate::preflight::validate_against_api_schema;
+
+pub struct Comparator {
+    native_planner: native_planner::QueryPlanner,
+    legacy_planner: legacy_planner::Planner<LegacyQueryPlanResult>,
+    // Runs the legacy planner, which is async.
+    runtime: tokio::runtime::Runtime,
+    options: CompareOptions,
+}
+
+impl Comparator {
+    /// Builds both planners for the supergraph schema. Fails if either planner rejects the schema
+    /// or its config.
+    pub fn new(
+        schema_str: &str,
+        native_config: native_planner::QueryPlannerConfig,
+        legacy_config: legacy_planner::QueryPlannerConfig,
+        options: CompareOptions,
+    ) -> Result<Self, String> {
+        let supergraph = apollo_federation::Supergraph::new_with_router_specs(schema_str)
+            .map_err(|err| format!("Native planner error: {err}"))?;
+        let native_planner = native_planner::QueryPlanner::new(&supergraph, native_config)
+            .map_err(|err| format!("Native planner error: {err}"))?;
+        let runtime = tokio::runtime::Runtime::new().map_err(|err| err.to_string())?;
+        let legacy_planner = runtime
+            .block_on(legacy_planner::Planner::new(
+                schema_str.to_string(),
+                legacy_config,
+            ))
+            .map_err(|errors| {
+                let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                format!("Legacy planner error: {}", errors.join("\n"))
+            })?;
+        Ok(Self {
+            native_planner,
+            legacy_planner,
+            runtime,
+            options,
+        })
+    }
+
+    /// Plans an operation with both planners and compares the plans. `operation_name` selects the
+    /// operation of documents with several. Operations invalid against the API schema aren't
+    /// planned.
+    pub fn compare(&self, operation: &str, operation_name: Option<Name>) -> ComparisonOutcome {
+        self.compare_detailed(operation, operation_name).outcome
+    }
+
+    /// Like `compare`, but also returns the plan trees of both planners and their planning times,
+    /// to render the plans without planning the operation again.
+    pub fn compare_detailed(
+        &self,
+        operation: &str,
+        operation_name: Option<Name>,
+    ) -> DetailedComparison {
+        if let Err(errors) = validate_against_api_schema(self.api_schema(), operation) {
+            return DetailedComparison::unplanned(ComparisonOutcome::InvalidOperation(errors));
+        }
+        let native_start = Instant::now();
+        let rust_plan = self.plan_native(
+            operation,
+            operation_name.clone(),
+            "operation.graphql",
+            Default::default(),
+        );
+        let native_time = native_start.elapsed();
+        let legacy_start = Instant::now();
+        let js_plan = self.plan_legacy(
+            operation,
+            operation_name.map(|name| name.to_string()),
+            Default::default(),
+        );
+        let legacy_time = legacy_start.elapsed();
+        let native_plan = rust_plan.as_ref().ok().and_then(convert_native_plan);
+        let legacy_plan = js_plan
+            .as_ref()
+            .ok()
+            .and_then(|js_plan| js_plan.root_node().cloned());
+        DetailedComparison {
+            outcome: ComparisonOutcome::from_results(js_plan, rust_plan, &self.options),
+            native_plan,
+            legacy_plan,
+            native_time,
+            legacy_time,
+        }
+    }
+
+    /// Plans an operation with the native planner only, without validating it first. Like
+    /// `run_native_planner`, with the planner built by `Comparator::new`.
+    pub fn plan_native(
+        &self,
+        operation: &str,
+        operation_name: Option<Name>,
+        operation_path: impl AsRef<Path>,
+        plan_options: native_planner::QueryPlanOptions,
+    ) -> Result<NativeQueryPlan, FederationError> {
+        plan_with_native_planner(
+            &self.native_planner,
+            operation,
+            operation_name,
+            operation_path,
+            plan_options,
+        )
+    }
+
+    /// Plans an operation with the legacy planner only, without validating it first. Like
+    /// `run_legacy_planner`, with the planner built by `Comparator::new`.
+    pub fn plan_legacy(
+        &self,
+        operation: &str,
+        operation_name: Option<String>,
+        plan_options: legacy_planner::PlanOptions,
+    ) -> Result<LegacyQueryPlanResult, Vec<String>> {
+        plan_with_legacy_planner(
+            &self.runtime,
+            &self.legacy_planner,
+            operation,
+            operation_name,
+            plan_options,
+        )
+    }
+
+    /// The API schema of the native planner, which operations are validated against.
+    pub fn api_schema(&self) -> &Valid<Schema> {
+        self.native_planner.api_schema().schema()
+    }
+
+    pub fn options(&self) -> &CompareOptions {
+        &self.options
+    }
+}
@@ -0,0 +1,124 @@
+//! Composition of subgraph schemas into a supergraph, to check that subgraphs compose cleanly
+//! before planning against their supergraph.
+
+use apollo_federation::composition::compose;
+use apollo_federation::subgraph::typestate::Subgraph;
+use serde_json::json;
+
+/// The errors and hints of composing subgraphs. Composition succeeded if there are no errors.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompositionReport {
+    /// Subgraphs failing to parse (naming the subgraph), or composition errors. Messages carry the
+    /// locations of the errors in the subgraph schemas where composition reports them.
+    pub errors: Vec<String>,
+    /// Hints about composed but suspicious schemas, e.g. inconsistent field types across
+    /// subgraphs. Empty when composition failed.
+    pub hints: Vec<String>,
+}
+
+impl CompositionReport {
+    pub fn is_success(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "success": self.is_success(),
+            "errors": self.errors,
+            "hints": self.hints,
+        })
+    }
+}
+
+/// Composes subgraph schemas, given as `(name, SDL)` pairs, without keeping the supergraph.
+///
+/// Every subgraph is parsed first, reporting all those failing to. Subgraphs are given the
+/// placeholder URL `http://<name>`, which composition only copies into the supergraph.
+pub fn check_composition(subgraphs: &[(String, String)]) -> CompositionReport {
+    let mut report = CompositionReport::default();
+    let mut parsed = Vec::new();
+    for (name, schema_str) in subgraphs {
+        match Subgraph::parse(name, &format!("http://{name}"), schema_str) {
+            Ok(subgraph) => parsed.push(subgraph),
+            Err(err) => report.errors.push(format!("subgraph `{name}`: {err}")),
+        }
+    }
+    if !report.errors.is_empty() {
+        return report;
+    }
+    match compose(parsed) {
+        Ok(supergraph) => {
+            report.hints = supergraph
+                .hints()
+                .iter()
+                .map(|hint| hint.message().to_string())
+                .collect();
+        }
+        Err(errors) => report.errors = errors.iter().map(ToString::to_string).collect(),
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subgraphs(schemas: &[(&str, &str)]) -> Vec<(String, String)> {
+        schemas
+            .iter()
+            .map(|(name, schema)| (name.to_string(), schema.to_string()))
+            .collect()
+    }
+
+    const PRODUCTS: &str = r#"
+        extend schema @link(url: "https://specs.apollo.dev/federation/v2.3", import: ["@key"])
+        type Query { topProducts: [Product] }
+        type Product @key(fields: "upc") { upc: String! name: String }
+    "#;
+
+    const REVIEWS: &str = r#"
+        extend schema @link(url: "https://specs.apollo.dev/federation/v2.3", import: ["@key"])
+        type Product @key(fields: "upc") { upc: String! reviews: [Review] }
+        type Review { body: String }
+    "#;
+
+    #[test]
+    fn test_subgraphs_compose() {
+        let report = check_composition(&subgraphs(&[("products", PRODUCTS), ("reviews", REVIEWS)]));
+        assert!(report.is_success(), "{:?}", report.errors);
+        assert_eq!(report.to_json()["success"], true);
+    }
+
+    #[test]
+    fn test_unparsable_subgraphs_are_named() {
+        let report = check_composition(&subgraphs(&[
+            ("products", PRODUCTS),
+            ("reviews", "type Review {"),
+        ]));
+        assert!(!report.is_success());
+        assert_eq!(report.errors.len(), 1);
+        assert!(
+            report.errors[0].starts_with("subgraph `reviews`: "),
+            "{:?}",
+            report.errors
+        );
+    }
+
+    #[test]
+    fn test_composition_errors() {
+        // `Query.topProducts` is resolved by both subgraphs without being shareable.
+        let report = check_composition(&subgraphs(&[
+            ("products", PRODUCTS),
+            (
+                "inventory",
+                r#"
+                extend schema @link(url: "https://specs.apollo.dev/federation/v2.3", import: ["@key"])
+                type Query { topProducts: [Product] }
+                type Product @key(fields: "upc") { upc: String! }
+                "#,
+            ),
+        ]));
+        assert!(!report.is_success());
+        assert!(report.hints.is_empty());
+    }
+}
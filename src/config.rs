@@ -0,0 +1,274 @@
+//! Planner configuration files, passed with `--config`, and full planner config overrides, passed
+//! with `--native-config`/`--legacy-config`.
+//!
+//! A config file holds the planner options that would otherwise be repeated on every invocation.
+//! It is written in TOML, or in JSON if the file name ends with `.json`. Command-line flags take
+//! precedence over config-file values.
+
+use std::num::NonZeroU32;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::legacy_planner;
+use crate::native_planner;
+
+/// Planner options read from a config file. Options left out keep their command-line value, or
+/// their default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub generate_fragments: Option<bool>,
+    pub type_conditioned_fetching: Option<bool>,
+    pub enable_defer: Option<bool>,
+}
+
+impl Config {
+    /// Reads and validates a config file. Unknown keys are rejected.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read config file {}: {err}", path.display()))?;
+        let config = if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+        {
+            Self::from_json(&source)
+        } else {
+            Self::from_toml(&source)
+        };
+        config.map_err(|err| format!("Invalid config file {}: {err}", path.display()))
+    }
+
+    pub fn from_toml(source: &str) -> Result<Self, String> {
+        toml::from_str(source).map_err(|err| err.to_string())
+    }
+
+    pub fn from_json(source: &str) -> Result<Self, String> {
+        serde_json::from_str(source).map_err(|err| err.to_string())
+    }
+}
+
+//==================================================================================================
+// Planner config overrides
+
+// Mirrors the fields of `native_planner::QueryPlannerConfig`, which is not deserializable. Unknown
+// fields are rejected, so that options the installed federation version lacks are reported.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NativeConfigOverrides {
+    generate_query_fragments: Option<bool>,
+    type_conditioned_fetching: Option<bool>,
+    incremental_delivery: Option<NativeIncrementalDeliveryOverrides>,
+    debug: Option<NativeDebugOverrides>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NativeIncrementalDeliveryOverrides {
+    enable_defer: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NativeDebugOverrides {
+    max_evaluated_plans: Option<NonZeroU32>,
+    paths_limit: Option<u32>,
+}
+
+/// Merges a JSON object of native `QueryPlannerConfig` fields (in snake case) over `base`.
+pub fn merge_native_config(
+    mut base: native_planner::QueryPlannerConfig,
+    json: &str,
+) -> Result<native_planner::QueryPlannerConfig, String> {
+    let NativeConfigOverrides {
+        generate_query_fragments,
+        type_conditioned_fetching,
+        incremental_delivery,
+        debug,
+    } = serde_json::from_str(json)
+        .map_err(|err| format!("Invalid native planner config: {err}"))?;
+    if let Some(value) = generate_query_fragments {
+        base.generate_query_fragments = value;
+    }
+    if let Some(value) = type_conditioned_fetching {
+        base.type_conditioned_fetching = value;
+    }
+    if let Some(value) = incremental_delivery.and_then(|overrides| overrides.enable_defer) {
+        base.incremental_delivery.enable_defer = value;
+    }
+    if let Some(debug) = debug {
+        if let Some(value) = debug.max_evaluated_plans {
+            base.debug.max_evaluated_plans = value;
+        }
+        if let Some(value) = debug.paths_limit {
+            base.debug.paths_limit = Some(value);
+        }
+    }
+    Ok(base)
+}
+
+/// Merges a JSON object of legacy `QueryPlannerConfig` fields (in camel case, as sent to the JS
+/// planner) over `base`.
+pub fn merge_legacy_config(
+    base: legacy_planner::QueryPlannerConfig,
+    json: &str,
+) -> Result<legacy_planner::QueryPlannerConfig, String> {
+    let invalid = |err: String| format!("Invalid legacy planner config: {err}");
+    let overrides: Value = serde_json::from_str(json).map_err(|err| invalid(err.to_string()))?;
+    let mut merged =
+        serde_json::to_value(base).expect("legacy planner configs always serialize to JSON");
+    merge_json(&mut merged, overrides, "").map_err(invalid)?;
+    serde_json::from_value(merged).map_err(|err| invalid(err.to_string()))
+}
+
+// Objects are merged recursively. Keys missing from `base` are rejected, since the legacy config
+// silently ignores unknown fields.
+fn merge_json(base: &mut Value, overrides: Value, path: &str) -> Result<(), String> {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                let path = format!("{path}{key}");
+                let Some(base_value) = base.get_mut(&key) else {
+                    return Err(format!("unknown field `{path}`"));
+                };
+                merge_json(base_value, value, &format!("{path}."))?;
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+    Ok(())
+}
+
+//==================================================================================================
+// Fairness checks
+
+/// Returns a warning if the two planner configs use fragments differently in subgraph operations.
+///
+/// The comparison is still meaningful, but subgraph operations may then differ cosmetically (named
+/// fragments vs. inlined selections), which shows up as mismatches.
+pub fn fragment_settings_warning(
+    native: &native_planner::QueryPlannerConfig,
+    legacy: &legacy_planner::QueryPlannerConfig,
+) -> Option<String> {
+    // The JS planner defaults to `generateQueryFragments: false` and `reuseQueryFragments: true`,
+    // and ignores the latter when generating fragments. The native planner never reuses fragments.
+    let legacy_generates = legacy.generate_query_fragments.unwrap_or(false);
+    let legacy_reuses = !legacy_generates && legacy.reuse_query_fragments.unwrap_or(true);
+    let mismatch = if native.generate_query_fragments != legacy_generates {
+        format!(
+            "the native planner {} fragments but the legacy planner {}",
+            if native.generate_query_fragments {
+                "generates"
+            } else {
+                "doesn't generate"
+            },
+            if legacy_generates { "does" } else { "doesn't" },
+        )
+    } else if legacy_reuses {
+        "the legacy planner reuses the operation's fragments but the native planner doesn't"
+            .to_string()
+    } else {
+        return None;
+    };
+    Some(format!(
+        "The planner configs don't align on fragments: {mismatch}. Subgraph operations may \
+         differ cosmetically as a result. Align `generate_query_fragments` (and set the legacy \
+         `reuseQueryFragments` to false) for a like-for-like comparison."
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toml_config() {
+        let config = Config::from_toml("generate_fragments = false").unwrap();
+        assert_eq!(
+            config,
+            Config {
+                generate_fragments: Some(false),
+                type_conditioned_fetching: None,
+                enable_defer: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_json_config() {
+        let config = Config::from_json(r#"{ "type_conditioned_fetching": true }"#).unwrap();
+        assert_eq!(config.type_conditioned_fetching, Some(true));
+        assert_eq!(config.generate_fragments, None);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let error = Config::from_toml("generate_fragment = false").unwrap_err();
+        assert!(error.contains("generate_fragment"), "{error}");
+        assert!(Config::from_json(r#"{ "typeConditionedFetching": true }"#).is_err());
+    }
+
+    #[test]
+    fn test_merge_native_config() {
+        let base = native_planner::QueryPlannerConfig {
+            generate_query_fragments: true,
+            ..Default::default()
+        };
+        let config = merge_native_config(
+            base,
+            r#"{ "type_conditioned_fetching": true, "debug": { "paths_limit": 10 } }"#,
+        )
+        .unwrap();
+        assert!(config.generate_query_fragments);
+        assert!(config.type_conditioned_fetching);
+        assert_eq!(config.debug.paths_limit, Some(10));
+        let error = merge_native_config(Default::default(), r#"{ "reuse_query_fragments": true }"#)
+            .unwrap_err();
+        assert!(error.contains("reuse_query_fragments"), "{error}");
+    }
+
+    #[test]
+    fn test_merge_legacy_config() {
+        let base = legacy_planner::QueryPlannerConfig {
+            reuse_query_fragments: Some(false),
+            ..Default::default()
+        };
+        let config = merge_legacy_config(base, r#"{ "generateQueryFragments": true }"#).unwrap();
+        assert_eq!(config.reuse_query_fragments, Some(false));
+        assert_eq!(config.generate_query_fragments, Some(true));
+        let error = merge_legacy_config(Default::default(), r#"{ "generateFragments": true }"#)
+            .unwrap_err();
+        assert!(error.contains("`generateFragments`"), "{error}");
+    }
+
+    #[test]
+    fn test_fragment_settings_warning() {
+        let native = native_planner::QueryPlannerConfig {
+            generate_query_fragments: true,
+            ..Default::default()
+        };
+        let aligned = legacy_planner::QueryPlannerConfig {
+            reuse_query_fragments: Some(false),
+            generate_query_fragments: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(fragment_settings_warning(&native, &aligned), None);
+
+        let not_generating = legacy_planner::QueryPlannerConfig {
+            generate_query_fragments: Some(false),
+            ..aligned.clone()
+        };
+        let warning = fragment_settings_warning(&native, &not_generating).unwrap();
+        assert!(warning.contains("legacy planner doesn't"), "{warning}");
+
+        let native = native_planner::QueryPlannerConfig {
+            generate_query_fragments: false,
+            ..Default::default()
+        };
+        let warning =
+            fragment_settings_warning(&native, &legacy_planner::QueryPlannerConfig::default())
+                .unwrap();
+        assert!(warning.contains("reuses"), "{warning}");
+    }
+}
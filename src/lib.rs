@@ -1,4 +1,49 @@
+pub mod allowlist;
+pub mod baseline;
+pub mod comparator;
+pub mod composition;
+pub mod config;
+pub mod memory;
+pub mod metrics;
+pub mod operation;
+pub mod outcome;
+pub mod planner_pool;
+pub mod preflight;
+pub mod render;
+pub mod rewrites;
 pub mod router;
+pub mod schema_diff;
+pub mod structure;
+pub mod subgraph;
+
+//=================================================================================================
+// Export the query plan tree shared by both planners
+
+/// The query plan representation shared by the legacy and native planners.
+///
+/// Legacy plans deserialize directly into these types (they mirror the router's plan JSON), and
+/// native plans are converted into them before comparison.
+pub mod plan {
+    pub use apollo_federation::query_plan::requires_selection::Selection;
+
+    pub use crate::router::path::Path;
+    pub use crate::router::path::PathElement;
+    pub use crate::router::plan::DataKeyRenamer;
+    pub use crate::router::plan::DataRewrite;
+    pub use crate::router::plan::DataValueSetter;
+    pub use crate::router::plan::DeferredNode;
+    pub use crate::router::plan::Depends;
+    pub use crate::router::plan::FetchNode;
+    pub use crate::router::plan::FlattenNode;
+    pub use crate::router::plan::OperationKind;
+    pub use crate::router::plan::PlanNode;
+    pub use crate::router::plan::Primary;
+    pub use crate::router::plan::QueryPlan;
+    pub use crate::router::plan::SubscriptionNode;
+
+    #[cfg(feature = "arbitrary")]
+    pub use crate::router::arbitrary;
+}
 
 //=================================================================================================
 // Re-export underlying crates
@@ -10,11 +55,41 @@ pub use router_bridge;
 //=================================================================================================
 // Export semantic diff functions
 
+pub use crate::router::convert_native_plan;
+pub use crate::router::format::format_plan;
+pub use crate::router::format::format_plan_explained;
+pub use crate::router::normalize::CanonicalPlan;
+pub use crate::router::normalize::canonicalize_operations;
+pub use crate::router::normalize::is_trivially_divergent;
+pub use crate::router::normalize::normalize;
+pub use crate::router::normalize::normalize_with_options;
+pub use crate::router::normalize::plan_fingerprint;
+pub use crate::router::plan_compare::CompareOptions;
+pub use crate::router::plan_compare::FetchDifferences;
+pub use crate::router::plan_compare::MatchFailure;
+pub use crate::router::plan_compare::MismatchCategory;
+pub use crate::router::plan_compare::Sides;
+pub use crate::router::plan_compare::classify_mismatch;
+pub use crate::router::plan_compare::compare_nodes;
+pub use crate::router::plan_compare::diff_legacy_plans_with_style;
+pub use crate::router::plan_compare::diff_native_plans_with_style;
+pub use crate::router::plan_compare::diff_nodes_with_style;
 pub use crate::router::plan_compare::diff_plan;
+pub use crate::router::plan_compare::diff_plan_with_style;
+pub use crate::router::plan_compare::fetch_differences;
+pub use crate::router::plan_compare::legacy_plans_match;
+pub use crate::router::plan_compare::native_plans_match;
 pub use crate::router::plan_compare::plan_matches;
+pub use crate::router::plan_compare::plan_matches_with_fingerprint;
+pub use crate::router::plan_compare::plan_matches_with_options;
 pub use crate::router::plan_compare::render_diff;
+pub use crate::router::plan_compare::render_diff_with_style;
+pub use crate::router::plan_compare::same_plan_fingerprint;
+pub use crate::router::plan_compare::trivially_divergent;
+pub use crate::router::plan_compare::unified_diff_with_style;
 pub use crate::router::render_legacy_plan;
 pub use crate::router::render_native_plan;
+pub use crate::router::render_plan;
 
 //=================================================================================================
 // Helper functions for running query planners
@@ -33,8 +108,32 @@ pub fn run_native_planner(
     config: native_planner::QueryPlannerConfig,
     plan_options: native_planner::QueryPlanOptions,
 ) -> Result<NativeQueryPlan, FederationError> {
-    let supergraph = apollo_federation::Supergraph::new_with_router_specs(schema_str).unwrap();
+    let supergraph = apollo_federation::Supergraph::new_with_router_specs(schema_str)?;
     let planner = native_planner::QueryPlanner::new(&supergraph, config)?;
+    plan_with_native_planner(&planner, query_str, query_name, query_path, plan_options)
+}
+
+/// Like `run_native_planner`, reusing the pool's planner for the schema and config.
+pub fn run_pooled_native_planner(
+    pool: &planner_pool::NativePlannerPool,
+    schema_str: &str,
+    query_str: &str,
+    query_name: Option<apollo_compiler::Name>,
+    query_path: impl AsRef<std::path::Path>,
+    config: &native_planner::QueryPlannerConfig,
+    plan_options: native_planner::QueryPlanOptions,
+) -> Result<NativeQueryPlan, FederationError> {
+    let planner = pool.get_or_build(schema_str, config)?;
+    plan_with_native_planner(&planner, query_str, query_name, query_path, plan_options)
+}
+
+pub(crate) fn plan_with_native_planner(
+    planner: &native_planner::QueryPlanner,
+    query_str: &str,
+    query_name: Option<apollo_compiler::Name>,
+    query_path: impl AsRef<std::path::Path>,
+    plan_options: native_planner::QueryPlanOptions,
+) -> Result<NativeQueryPlan, FederationError> {
     let query_doc = apollo_compiler::ExecutableDocument::parse_and_validate(
         planner.api_schema().schema(),
         query_str,
@@ -51,15 +150,27 @@ pub fn run_legacy_planner(
     config: legacy_planner::QueryPlannerConfig,
     plan_options: legacy_planner::PlanOptions,
 ) -> Result<LegacyQueryPlanResult, Vec<String>> {
-    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let runtime = tokio::runtime::Runtime::new().map_err(|e| vec![e.to_string()])?;
     let planner = runtime
         .block_on(legacy_planner::Planner::new(schema_str.to_string(), config))
-        .unwrap();
+        .map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>())?;
+    plan_with_legacy_planner(&runtime, &planner, query_str, query_name, plan_options)
+}
+
+pub(crate) fn plan_with_legacy_planner(
+    runtime: &tokio::runtime::Runtime,
+    planner: &legacy_planner::Planner<LegacyQueryPlanResult>,
+    query_str: &str,
+    query_name: Option<String>,
+    plan_options: legacy_planner::PlanOptions,
+) -> Result<LegacyQueryPlanResult, Vec<String>> {
     let result = runtime
         .block_on(planner.plan(query_str.to_string(), query_name, plan_options))
-        .unwrap();
+        .map_err(|e| vec![e.to_string()])?;
     if let Some(errors) = result.errors {
         return Err(errors.iter().map(|e| e.to_string()).collect());
     }
-    Ok(result.data.unwrap())
+    result
+        .data
+        .ok_or_else(|| vec!["The legacy planner returned neither a plan nor errors".to_string()])
 }
@@ -1,45 +1,626 @@
+use clap::CommandFactory;
 use clap::Parser;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::fs;
+use std::io::IsTerminal;
 use std::io::Write;
+use std::num::NonZeroUsize;
+use std::path::Path;
 use std::path::PathBuf;
 use std::process::ExitCode;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
 
-use qp_compare::diff_plan;
+use qp_compare::CanonicalPlan;
+use qp_compare::CompareOptions;
+use qp_compare::LegacyQueryPlanResult;
+use qp_compare::MatchFailure;
+use qp_compare::MismatchCategory;
+use qp_compare::NativeQueryPlan;
+use qp_compare::allowlist::Allowlist;
+use qp_compare::apollo_compiler::Name;
+use qp_compare::baseline::Baseline;
+use qp_compare::baseline::BaselineComparison;
+use qp_compare::baseline::BaselineEntry;
+use qp_compare::baseline::Outcome;
+use qp_compare::canonicalize_operations;
+use qp_compare::classify_mismatch;
+use qp_compare::comparator::Comparator;
+use qp_compare::composition::check_composition;
+use qp_compare::config::Config;
+use qp_compare::config::fragment_settings_warning;
+use qp_compare::config::merge_legacy_config;
+use qp_compare::config::merge_native_config;
+use qp_compare::convert_native_plan;
+use qp_compare::diff_nodes_with_style;
+use qp_compare::fetch_differences;
+use qp_compare::format_plan;
+use qp_compare::format_plan_explained;
 use qp_compare::legacy_planner;
+use qp_compare::legacy_plans_match;
+use qp_compare::memory::format_mib;
+use qp_compare::memory::peak_memory_growth;
+use qp_compare::metrics::CorpusStats;
+use qp_compare::metrics::MetricsComparison;
+use qp_compare::metrics::oversized_operations;
 use qp_compare::native_planner;
-use qp_compare::plan_matches;
+use qp_compare::native_plans_match;
+use qp_compare::normalize;
+use qp_compare::normalize_with_options;
+use qp_compare::operation::apply_conditions;
+use qp_compare::operation::extract_operation;
+use qp_compare::operation::is_introspection_only;
+use qp_compare::operation::merge_fragments;
+use qp_compare::operation::operation_display_name;
+use qp_compare::operation::select_operation;
+use qp_compare::operation::source_snippet;
+use qp_compare::plan::PlanNode;
+use qp_compare::plan_fingerprint;
+use qp_compare::plan_matches_with_fingerprint;
+use qp_compare::planner_pool::NativePlannerPool;
+use qp_compare::preflight::check_operation_not_schema;
+use qp_compare::preflight::check_schema_not_operation;
+use qp_compare::preflight::validate_against_api_schema;
+use qp_compare::render::ColorChoice;
+use qp_compare::render::DiffStyle;
+use qp_compare::render::Style;
 use qp_compare::render_legacy_plan;
-use qp_compare::render_native_plan;
+use qp_compare::render_plan;
+use qp_compare::rewrites::invalid_rewrites;
 use qp_compare::run_legacy_planner;
 use qp_compare::run_native_planner;
+use qp_compare::run_pooled_native_planner;
+use qp_compare::schema_diff::schema_changes;
+use qp_compare::structure::dangling_depends;
+use qp_compare::subgraph::SubgraphSchemas;
+use qp_compare::trivially_divergent;
 
+// The command line: plans compared per `PlanArgs`, unless a subcommand is given.
 #[derive(Debug, clap::Parser)]
+#[command(
+    name = "qp-compare",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    plan: Option<PlanArgs>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Print the canonical form of a legacy plan, i.e. what the comparator compares.
+    Normalize(NormalizeArgs),
+    /// Print aggregate statistics of the plans of a corpus of operations.
+    Stats(StatsArgs),
+    /// Check that subgraph schemas compose into a supergraph.
+    ComposeCheck(ComposeCheckArgs),
+}
+
+#[derive(Debug, clap::Parser)]
+#[command(group(clap::ArgGroup::new("randomized").args(["sample", "shuffle"]).multiple(true)))]
+#[command(group(clap::ArgGroup::new("dumps").args(["dump_plans", "dump_dir"]).multiple(true)))]
 pub struct PlanArgs {
-    /// Specify path to schema file(s) to plan operations against
+    /// Specify path to schema file(s) to plan operations against. A directory of schema variants
+    /// plans a single operation against each, with the native planner, to compare their plans.
     #[arg(short, long)]
     pub schema: PathBuf,
 
     /// Specify path to an operation file to plan.
     /// This can be either a directory of operations or a file.
+    #[arg(short, long, required_unless_present = "operations_from")]
+    pub operation: Option<PathBuf>,
+
+    /// A file listing the operation files to compare, in order, one per line, relative to the file.
+    /// Blank lines and `#` comments are ignored. Runs in directory mode, relative to the
+    /// directory of the file.
+    #[arg(long, value_name = "FILE", conflicts_with = "operation")]
+    pub operations_from: Option<PathBuf>,
+
+    /// The `--operations-from` entries, loaded by `resolve_options`.
+    #[arg(skip)]
+    listed_operations: Option<Vec<PathBuf>>,
+
+    /// File extensions of the operation files read from directories, matched case-insensitively.
+    #[arg(
+        long = "operation-ext",
+        value_name = "EXT",
+        value_delimiter = ',',
+        value_parser = parse_extension,
+        default_value = "graphql,graphqls,gql,sdl"
+    )]
+    pub operation_extensions: Vec<String>,
+
+    /// Name of the operation to plan, required when a document contains several operations.
+    #[arg(long)]
+    pub operation_name: Option<String>,
+
+    /// Index of the operation to plan, in declaration order. Unlike `--operation-name`, this can
+    /// select anonymous and duplicate-named operations.
+    #[arg(long, conflicts_with = "operation_name")]
+    pub operation_index: Option<usize>,
+
+    /// Shared fragment definitions (a file, or a directory of operation files) to add to each
+    /// operation using them before planning.
+    #[arg(long, value_name = "FILE_OR_DIR")]
+    pub fragments: Option<PathBuf>,
+
+    /// Variable values of the operation, as a JSON object.
+    #[arg(long, value_name = "JSON", value_parser = parse_variables)]
+    pub variables: Option<serde_json::Map<String, serde_json::Value>>,
+
+    /// Evaluate the `@skip`/`@include` directives pinned by `--variables` before planning, so
+    /// that the plans are compared for the selections these variables realize. Directives
+    /// depending on other variables are left intact.
+    #[arg(long, requires = "variables")]
+    pub apply_conditions: bool,
+
+    /// The contents of the `--fragments` files, loaded by `resolve_options`.
+    #[arg(skip)]
+    shared_fragments: Option<String>,
+
+    /// Read planner options from a TOML (or `.json`) file. Flags given on the command line take
+    /// precedence.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Native `QueryPlannerConfig` fields as a JSON object (e.g. copied from a router config),
+    /// merged over the options above.
+    #[arg(long, value_name = "JSON")]
+    pub native_config: Option<String>,
+
+    /// Legacy `QueryPlannerConfig` fields as a JSON object (in camel case), merged over the
+    /// options above.
+    #[arg(long, value_name = "JSON")]
+    pub legacy_config: Option<String>,
+
+    /// Compare the legacy planner against itself instead of the native planner: the operation is
+    /// planned a second time with these legacy `QueryPlannerConfig` fields merged over the legacy
+    /// config (for A/B testing planner configs).
+    #[arg(long, value_name = "JSON", conflicts_with = "native_config")]
+    pub legacy_b_config: Option<String>,
+
+    /// Detect plan drift instead of comparing planners: the operation is planned with the native
+    /// planner against both `--schema` and this second schema version, and the plans are compared.
+    #[arg(long, value_name = "FILE", conflicts_with = "legacy_b_config")]
+    pub schema_b: Option<PathBuf>,
+
+    /// Generate fragments in subgraph operations [default: true]
+    #[arg(long)]
+    pub generate_fragments: Option<bool>,
+
+    /// Use type-conditioned fetching for abstract types [default: false]
+    #[arg(long)]
+    pub type_conditioned_fetching: Option<bool>,
+
+    /// Enable `@defer` support in both planners, so that deferred fragments produce `Defer`
+    /// plan nodes [default: false]
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub enable_defer: Option<bool>,
+
+    /// Enable a progressive `@override` label (repeatable). Both planners receive the same labels.
+    #[arg(long = "override-condition", value_name = "LABEL")]
+    pub override_conditions: Vec<String>,
+
+    /// Retry the legacy planner up to this many times when it fails, unless it fails twice in a
+    /// row with the same errors (which is then considered deterministic).
+    #[arg(long, default_value_t = 0)]
+    pub legacy_retries: usize,
+
+    /// In directory mode, compare a random sample of this percentage of the operations.
+    #[arg(long, value_name = "PCT", value_parser = parse_percentage)]
+    pub sample: Option<f64>,
+
+    /// In directory mode, process the operations in a random order, e.g. to surface planner state
+    /// leaking between operations.
+    #[arg(long)]
+    pub shuffle: bool,
+
+    /// Seed of the `--sample` selection and the `--shuffle` order (random by default). The summary
+    /// reports the seed used, so that a run can be reproduced.
+    #[arg(long, requires = "randomized")]
+    pub seed: Option<u64>,
+
+    /// Output format of directory mode and `--list` [default: `github` when the `GITHUB_ACTIONS`
+    /// environment variable is set, `text` otherwise]
+    #[arg(long, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// In directory mode, also print the result of each operation to stderr as it completes,
+    /// numbered and timed, for live feedback when stdout is redirected.
     #[arg(short, long)]
-    pub operation: PathBuf,
+    pub verbose: bool,
 
-    #[arg(long, default_value = "true")]
-    pub generate_fragments: bool,
+    /// With `--format json`, include the plans both planners produced as the comparator compared
+    /// them (normalized, with the ignored differences erased), to debug surprising comparisons.
+    #[arg(long)]
+    pub emit_normalized: bool,
 
-    #[arg(long, default_value = "false")]
-    pub type_conditioned_fetching: bool,
+    /// Plan the operations with only this planner, reporting whether each plans, without comparing
+    /// anything, e.g. to look for planner errors across a corpus.
+    #[arg(long, value_enum, conflicts_with_all = ["legacy_b_config", "schema_b", "dump_dir", "list"])]
+    pub planner_only: Option<SinglePlanner>,
+
+    /// List the operations that would be compared (after `--sample`, `--shuffle` and the operation
+    /// selection flags) and exit without planning.
+    #[arg(long)]
+    pub list: bool,
+
+    /// In directory mode and with `--list`, what to do when operations of several files share a
+    /// display name.
+    #[arg(long, value_enum, default_value_t = OnDuplicate::Disambiguate)]
+    pub on_duplicate: OnDuplicate,
+
+    /// In directory mode, a file listing operations with known mismatches, one per line (paths
+    /// relative to the operation directory). Their mismatches are still reported, but don't fail
+    /// the run.
+    #[arg(long, value_name = "FILE")]
+    pub allowlist: Option<PathBuf>,
+
+    /// The `--allowlist` entries, loaded by `resolve_options`.
+    #[arg(skip)]
+    allowlisted: Allowlist,
+
+    /// In directory mode, write each operation's plans, and its diff or errors, under a
+    /// subdirectory of this directory named after the operation file (mirroring the operations
+    /// directory), with an `index.json` listing the operations' statuses and artifacts.
+    #[arg(long, value_name = "DIR")]
+    pub dump_dir: Option<PathBuf>,
+
+    /// Only dump the plans (with `--dump-plans` or `--dump-dir`) of operations that mismatch or
+    /// fail to plan.
+    #[arg(long, requires = "dumps")]
+    pub only_failing_dumps: bool,
+
+    /// In directory mode, also write the summary as JSON to this file.
+    #[arg(long, value_name = "FILE")]
+    pub summary_json: Option<PathBuf>,
+
+    /// In directory mode, write the outcome of each operation (and the fingerprint of its native
+    /// plan) to this JSON file, keyed by operation name, as a baseline for `--compare-baseline`.
+    #[arg(long, value_name = "FILE")]
+    pub baseline: Option<PathBuf>,
+
+    /// In directory mode, compare the outcomes with the `--baseline` file of an earlier run, and
+    /// report the operations fixed and regressed since in the summary.
+    #[arg(long, value_name = "FILE")]
+    pub compare_baseline: Option<PathBuf>,
+
+    /// The `--compare-baseline` outcomes, loaded by `resolve_options`.
+    #[arg(skip)]
+    previous_baseline: Option<Baseline>,
+
+    /// In directory mode, abort when no operation completes for this many seconds, reporting the
+    /// operation and planner in flight.
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub watchdog: Option<u64>,
+
+    /// In directory mode, compare introspection-only operations (querying `__schema` or `__type`)
+    /// too. They plan to empty plans, so they are skipped by default.
+    #[arg(long)]
+    pub include_introspection: bool,
+
+    /// In directory mode, stop starting new operations after this many seconds. The operation in
+    /// flight is allowed to finish, and the summary reports the skipped operations.
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub time_budget: Option<u64>,
+
+    /// Sample the process memory while each planner runs, and report its peak growth per planner.
+    /// This slows planning down, and is unavailable on some platforms.
+    #[arg(long)]
+    pub report_memory: bool,
 
     /// Dump both legacy/native query plans in files.
     #[arg(long, default_value = "false")]
     pub dump_plans: bool,
+
+    /// Ignore differences in the given part of the plans (repeatable). This is a triage aid: an
+    /// operation matching with ignored fields may still be planned differently.
+    #[arg(long = "ignore", value_enum, value_name = "FIELD")]
+    pub ignored: Vec<IgnoredField>,
+
+    /// Check that the data rewrites of both plans are well-formed (valid GraphQL names, and paths
+    /// reading back the same), and fail on malformed ones even if the plans match.
+    #[arg(long)]
+    pub validate_rewrites: bool,
+
+    /// Check that the deferred nodes of both plans only depend on fetches of their primary node,
+    /// and fail on dangling `depends` ids even if the plans match.
+    #[arg(long)]
+    pub validate_structure: bool,
+
+    /// Report the subgraph operations of either plan longer than this many bytes, naming the
+    /// service and the size of each.
+    #[arg(long, value_name = "N")]
+    pub max_operation_bytes: Option<usize>,
+
+    /// Fail operations with subgraph operations over `--max-operation-bytes`, instead of only
+    /// reporting them.
+    #[arg(long, requires = "max_operation_bytes")]
+    pub fail_on_large_operations: bool,
+
+    /// Normalize subgraph operations before comparing them: expand fragments, merge duplicate
+    /// fields and sort selection sets. This is a heuristic that ignores the subgraph schemas, so it
+    /// may hide real differences.
+    #[arg(long)]
+    pub semantic_operations: bool,
+
+    /// With `--semantic-operations`, also ignore unaliased `__typename` selections in subgraph
+    /// operations, which executors add anyway. Unlike `--ignore typename`, `requires` selections
+    /// are still compared strictly.
+    #[arg(long, requires = "semantic_operations")]
+    pub ignore_operation_typename: bool,
+
+    /// With `--semantic-operations`, also treat a key nested in `requires` as equal whether it's
+    /// selected with an inline fragment on its type (and `__typename`) or with bare fields and
+    /// `__typename`.
+    #[arg(long, requires = "semantic_operations")]
+    pub semantic_requires: bool,
+
+    /// The schema of a subgraph, to compare the operations sent to it with their types
+    /// (repeatable). Operations of subgraphs without a schema are compared as usual.
+    #[arg(
+        long = "subgraph-schema",
+        value_name = "NAME=FILE",
+        value_parser = parse_subgraph_schema
+    )]
+    pub subgraph_schema_files: Vec<(String, PathBuf)>,
+
+    /// The `--subgraph-schema` schemas, loaded by `resolve_options`.
+    #[arg(skip)]
+    subgraph_schemas: SubgraphSchemas,
+
+    /// Only compare the shape of the plans (node kinds and positions), treating the contents of all
+    /// fetches as equal. This is a coarse triage mode, not a correctness check.
+    #[arg(long)]
+    pub shape_only: bool,
+
+    /// Compare sequences of independent fetches (query fetches without `requires`) in any order, as
+    /// if they were parallel. A heuristic that may hide real ordering differences.
+    #[arg(long)]
+    pub assume_independent_fetches: bool,
+
+    /// Read `Condition` nodes on `!`-prefixed variables as negated conditions, matching the
+    /// condition on the variable with swapped `if` and `else` branches.
+    #[arg(long)]
+    pub negated_conditions: bool,
+
+    /// Ignore fetch ids, defer labels and subgraph operation names, still checking that deferred
+    /// nodes depend on the same fetches.
+    #[arg(long)]
+    pub ignore_identifiers: bool,
+
+    /// When to colorize the diff output (honors `NO_COLOR` in `auto` mode).
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Wrap diff lines at this width (defaults to the terminal width on a terminal).
+    #[arg(long)]
+    pub width: Option<usize>,
+
+    /// Indent structural diffs by this many spaces per level (4 by default).
+    #[arg(long, value_name = "SPACES")]
+    pub indent: Option<usize>,
+
+    /// Show every node of structural diffs, instead of eliding the subtrees matching on both
+    /// sides.
+    #[arg(long)]
+    pub full: bool,
+
+    /// How to report the differences of mismatching plans.
+    #[arg(long, value_enum, default_value_t = DiffStyle::Structural)]
+    pub diff_style: DiffStyle,
+
+    /// With `--diff-style unified`, annotate each fetch with its operation kind, the entities it
+    /// resolves (with their keys) and the variables it uses.
+    #[arg(long)]
+    pub explain: bool,
+
+    /// Print subgraph operations in a canonical format in diffs and detailed plan dumps, so that
+    /// they only differ when the operations do. Operations that fail to parse are left as is.
+    #[arg(long)]
+    pub canonical_operations: bool,
+}
+
+/// Plan parts that `--ignore` can exclude from the comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum IgnoredField {
+    /// Unaliased `__typename` selections outside of entity representations.
+    Typename,
+    /// Fetch `requires` selections.
+    Requires,
+}
+
+/// What `--on-duplicate` does with operations of several files sharing a display name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnDuplicate {
+    /// Fail before planning, listing the duplicates.
+    Error,
+    /// Add the file to their names, e.g. `TopProducts (products/top.graphql)`.
+    #[default]
+    Disambiguate,
+}
+
+/// Output format of directory mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// A pass/fail line per operation, followed by a summary.
+    #[default]
+    Text,
+    /// A CSV row per operation, after a header row. The summary goes to stderr.
+    Csv,
+    /// A JSON object per operation, one per line. The summary goes to stderr. With `--list`, a
+    /// single JSON array.
+    Json,
+    /// Like `text`, with a GitHub Actions error annotation on the file of each operation failing
+    /// to match (a warning for allowlisted ones).
+    Github,
+}
+
+impl OutputFormat {
+    // The format when `--format` isn't given, from the value of `GITHUB_ACTIONS`.
+    fn default_for(github_actions: Option<&std::ffi::OsStr>) -> Self {
+        if github_actions.is_some_and(|value| !value.is_empty()) {
+            Self::Github
+        } else {
+            Self::Text
+        }
+    }
+}
+
+impl From<&PlanArgs> for CompareOptions {
+    fn from(args: &PlanArgs) -> Self {
+        Self {
+            ignore_typename: args.ignored.contains(&IgnoredField::Typename),
+            ignore_requires: args.ignored.contains(&IgnoredField::Requires),
+            semantic_operations: args.semantic_operations,
+            ignore_operation_typename: args.ignore_operation_typename,
+            semantic_requires: args.semantic_requires,
+            shape_only: args.shape_only,
+            assume_independent_fetches: args.assume_independent_fetches,
+            negated_conditions: args.negated_conditions,
+            ignore_identifiers: args.ignore_identifiers,
+            subgraph_schemas: args.subgraph_schemas.clone(),
+        }
+    }
+}
+
+impl PlanArgs {
+    /// The operation file or directory: `--operation`, or the directory of the `--operations-from`
+    /// file, which runs in directory mode.
+    fn operation(&self) -> &Path {
+        match (&self.operation, &self.operations_from) {
+            (Some(operation), _) => operation,
+            (None, Some(listfile)) => operation_list_dir(listfile),
+            (None, None) => unreachable!("clap requires --operation or --operations-from"),
+        }
+    }
+
+    /// Fills in the planner options not given on the command line from the `--config` file, and
+    /// validates the planner config overrides.
+    fn resolve_options(&mut self) -> Result<(), String> {
+        if let Some(path) = &self.operations_from {
+            let listfile = fs::read_to_string(path)
+                .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+            self.listed_operations =
+                Some(parse_operation_list(&listfile, operation_list_dir(path)));
+        }
+        if let Some(path) = &self.config {
+            let Config {
+                generate_fragments,
+                type_conditioned_fetching,
+                enable_defer,
+            } = Config::from_file(path)?;
+            self.generate_fragments = self.generate_fragments.or(generate_fragments);
+            self.type_conditioned_fetching =
+                self.type_conditioned_fetching.or(type_conditioned_fetching);
+            self.enable_defer = self.enable_defer.or(enable_defer);
+        }
+        if let Some(path) = &self.fragments {
+            self.shared_fragments = Some(read_shared_fragments(path, &self.operation_extensions)?);
+        }
+        if let Some(path) = &self.allowlist {
+            self.allowlisted = Allowlist::from_file(path)?;
+        }
+        if self.explain && self.diff_style != DiffStyle::Unified {
+            return Err("--explain requires --diff-style unified".to_string());
+        }
+        if self.emit_normalized && self.format() != OutputFormat::Json {
+            return Err("--emit-normalized requires --format json".to_string());
+        }
+        if let Some(path) = &self.compare_baseline {
+            self.previous_baseline = Some(Baseline::from_file(path)?);
+        }
+        for (name, path) in &self.subgraph_schema_files {
+            if self.subgraph_schemas.get(name).is_some() {
+                return Err(format!("--subgraph-schema given twice for `{name}`"));
+            }
+            let schema = fs::read_to_string(path)
+                .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+            self.subgraph_schemas
+                .insert(name, &schema)
+                .map_err(|err| format!("Invalid subgraph schema {}: {err}", path.display()))?;
+        }
+        self.native_planner_config()?;
+        self.legacy_planner_config()?;
+        self.legacy_b_planner_config()?;
+        Ok(())
+    }
+
+    fn native_planner_config(&self) -> Result<native_planner::QueryPlannerConfig, String> {
+        match &self.native_config {
+            Some(json) => merge_native_config(self.into(), json),
+            None => Ok(self.into()),
+        }
+    }
+
+    fn legacy_planner_config(&self) -> Result<legacy_planner::QueryPlannerConfig, String> {
+        match &self.legacy_config {
+            Some(json) => merge_legacy_config(self.into(), json),
+            None => Ok(self.into()),
+        }
+    }
+
+    fn legacy_b_planner_config(
+        &self,
+    ) -> Result<Option<legacy_planner::QueryPlannerConfig>, String> {
+        self.legacy_b_config
+            .as_ref()
+            .map(|json| merge_legacy_config(self.legacy_planner_config()?, json))
+            .transpose()
+    }
+
+    // Note: In legacy A/B mode, both runs use the legacy planner and the native config is unused.
+    //       In schema drift and schema variants modes, all runs use the native planner.
+    fn fragment_settings_warning(&self) -> Option<String> {
+        if self.legacy_b_config.is_some() || self.schema_b.is_some() || self.schema.is_dir() {
+            return None;
+        }
+        fragment_settings_warning(
+            &self.native_planner_config().ok()?,
+            &self.legacy_planner_config().ok()?,
+        )
+    }
+
+    fn generate_fragments(&self) -> bool {
+        self.generate_fragments.unwrap_or(true)
+    }
+
+    fn type_conditioned_fetching(&self) -> bool {
+        self.type_conditioned_fetching.unwrap_or(false)
+    }
+
+    fn enable_defer(&self) -> bool {
+        self.enable_defer.unwrap_or(false)
+    }
+
+    fn format(&self) -> OutputFormat {
+        self.format.unwrap_or_else(|| {
+            OutputFormat::default_for(std::env::var_os("GITHUB_ACTIONS").as_deref())
+        })
+    }
 }
 
 impl From<&PlanArgs> for native_planner::QueryPlannerConfig {
     fn from(args: &PlanArgs) -> Self {
         Self {
-            generate_query_fragments: args.generate_fragments,
-            type_conditioned_fetching: args.type_conditioned_fetching,
+            generate_query_fragments: args.generate_fragments(),
+            type_conditioned_fetching: args.type_conditioned_fetching(),
+            incremental_delivery: native_planner::QueryPlanIncrementalDeliveryConfig {
+                enable_defer: args.enable_defer(),
+            },
             ..Default::default()
         }
     }
@@ -49,13 +630,37 @@ impl From<&PlanArgs> for legacy_planner::QueryPlannerConfig {
     fn from(args: &PlanArgs) -> Self {
         Self {
             reuse_query_fragments: Some(false),
-            generate_query_fragments: Some(args.generate_fragments),
-            type_conditioned_fetching: args.type_conditioned_fetching,
+            generate_query_fragments: Some(args.generate_fragments()),
+            type_conditioned_fetching: args.type_conditioned_fetching(),
+            incremental_delivery: Some(legacy_planner::IncrementalDeliverySupport {
+                enable_defer: Some(args.enable_defer()),
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+// Note: `override_conditions` is the only legacy `PlanOptions` field, and maps to the native
+//       option of the same name.
+impl From<&PlanArgs> for native_planner::QueryPlanOptions {
+    fn from(args: &PlanArgs) -> Self {
+        Self {
+            override_conditions: args.override_conditions.clone(),
             ..Default::default()
         }
     }
 }
 
+impl From<&PlanArgs> for legacy_planner::PlanOptions {
+    fn from(args: &PlanArgs) -> Self {
+        Self {
+            override_conditions: args.override_conditions.clone(),
+        }
+    }
+}
+
+const LEGACY_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
 fn write_file(path: &str, content: &str) {
     let mut file = fs::OpenOptions::new()
         .create(true) // Create the file if it doesn't exist
@@ -67,50 +672,3048 @@ fn write_file(path: &str, content: &str) {
         .expect("Unable to write data");
 }
 
-pub fn run_both_planners(schema_str: &str, query_str: &str, args: &PlanArgs) -> Result<(), String> {
-    let rust_plan = run_native_planner(
+/// The plans of an operation (or the planner errors), with the time each planner took.
+pub struct PlannedOperation {
+    pub js_plan: Result<LegacyQueryPlanResult, String>,
+    pub rust_plan: Result<NativeQueryPlan, String>,
+    pub legacy_time: Duration,
+    pub native_time: Duration,
+    /// Peak memory growth while each planner ran, with `--report-memory` on supported platforms.
+    pub legacy_memory: Option<usize>,
+    pub native_memory: Option<usize>,
+    /// The document planned and the operation selected in it, to quote in mismatch reports.
+    pub query: String,
+    pub operation_name: Option<Name>,
+}
+
+// Selects the operation to plan, returning the document to plan and the operation name. The
+// schema is checked once, when loaded (see `check_schema_not_operation`).
+fn prepare_operation<'a>(
+    query_str: &'a str,
+    args: &PlanArgs,
+) -> Result<(Cow<'a, str>, Option<Name>), String> {
+    check_operation_not_schema(query_str)?;
+    let query_str = match &args.shared_fragments {
+        Some(fragments) => Cow::Owned(merge_fragments(query_str, fragments)?),
+        None => Cow::Borrowed(query_str),
+    };
+    let query_str = match args.operation_index {
+        Some(index) => Cow::Owned(extract_operation(&query_str, index)?),
+        None => query_str,
+    };
+    let query_str = match &args.variables {
+        Some(variables) if args.apply_conditions => {
+            Cow::Owned(apply_conditions(&query_str, variables)?)
+        }
+        _ => query_str,
+    };
+    let operation_name = select_operation(&query_str, args.operation_name.as_deref())?;
+    Ok((query_str, operation_name))
+}
+
+// An operation file, prepared once for naming, introspection filtering and planning.
+struct OperationFile {
+    // The display name of the operation, per the operation selection flags.
+    name: String,
+    // The document to plan and the operation selected in it, or why the file can't be planned.
+    prepared: Result<(String, Option<Name>), String>,
+}
+
+impl OperationFile {
+    fn read(path: &Path, args: &PlanArgs) -> Self {
+        match fs::read_to_string(path) {
+            Ok(query_str) => Self::prepare(path, &query_str, args),
+            Err(err) => Self {
+                name: operation_display_name(path, ""),
+                prepared: Err(err.to_string()),
+            },
+        }
+    }
+
+    fn prepare(path: &Path, query_str: &str, args: &PlanArgs) -> Self {
+        match prepare_operation(query_str, args) {
+            Ok((prepared, operation_name)) => Self {
+                name: match &operation_name {
+                    Some(name) => name.to_string(),
+                    None => operation_display_name(path, &prepared),
+                },
+                prepared: Ok((prepared.into_owned(), operation_name)),
+            },
+            Err(error) => Self {
+                name: operation_display_name(path, query_str),
+                prepared: Err(error),
+            },
+        }
+    }
+
+    // Whether the selected operation only queries introspection fields.
+    fn is_introspection(&self) -> bool {
+        self.prepared.as_ref().is_ok_and(|(query, operation_name)| {
+            is_introspection_only(query, operation_name.as_ref().map(Name::as_str))
+        })
+    }
+}
+
+// The operation files of a batch, with display names made unique per `--on-duplicate`.
+fn batch_operation_files(
+    operation_files: &[PathBuf],
+    args: &PlanArgs,
+) -> Result<Vec<OperationFile>, String> {
+    let mut operations: Vec<OperationFile> = operation_files
+        .iter()
+        .map(|path| OperationFile::read(path, args))
+        .collect();
+    let files: Vec<String> = operation_files
+        .iter()
+        .map(|path| {
+            let file = path.strip_prefix(args.operation()).unwrap_or(path);
+            file.display().to_string()
+        })
+        .collect();
+    resolve_duplicate_names(
+        files
+            .iter()
+            .map(String::as_str)
+            .zip(operations.iter_mut().map(|operation| &mut operation.name)),
+        args.on_duplicate,
+    )?;
+    Ok(operations)
+}
+
+// Renames operations sharing a display name (given with their files) after their file, or fails
+// listing the duplicates, per `--on-duplicate`.
+fn resolve_duplicate_names<'a>(
+    operations: impl IntoIterator<Item = (&'a str, &'a mut String)>,
+    on_duplicate: OnDuplicate,
+) -> Result<(), String> {
+    let mut by_name: BTreeMap<String, Vec<(&str, &mut String)>> = BTreeMap::new();
+    for (file, name) in operations {
+        by_name.entry(name.clone()).or_default().push((file, name));
+    }
+    let mut duplicates = Vec::new();
+    for (name, operations) in &mut by_name {
+        if operations.len() < 2 {
+            continue;
+        }
+        match on_duplicate {
+            OnDuplicate::Error => {
+                let files: Vec<&str> = operations.iter().map(|(file, _)| *file).collect();
+                duplicates.push(format!("`{name}` in {}", files.join(", ")));
+            }
+            OnDuplicate::Disambiguate => {
+                for (file, display_name) in operations {
+                    **display_name = format!("{name} ({file})");
+                }
+            }
+        }
+    }
+    if duplicates.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Several files have operations with the same name (see --on-duplicate):\n{}",
+            duplicates.join("\n")
+        ))
+    }
+}
+
+// Builds both planners for the supergraph once per run, to plan every operation with them (see
+// `run_both_planners`).
+fn build_comparator(schema_str: &str, args: &PlanArgs) -> Result<Comparator, String> {
+    Comparator::new(
         schema_str,
-        query_str,
-        None,
-        &args.operation,
-        args.into(),
-        Default::default(),
+        args.native_planner_config()?,
+        args.legacy_planner_config()?,
+        CompareOptions::from(args),
     )
-    .map_err(|err| err.to_string())?;
-    println!("{}", rust_plan);
-    let js_plan = run_legacy_planner(schema_str, query_str, None, args.into(), Default::default())
-        .map_err(|err| err.join("\n"))?;
-    if args.dump_plans {
+}
+
+// `comparator` is the `build_comparator` of the supergraph, and `query_str` the document prepared
+// by `prepare_operation`, selecting `operation_name`.
+pub fn run_both_planners(
+    comparator: &Comparator,
+    query_str: &str,
+    operation_name: Option<Name>,
+    query_path: &Path,
+    args: &PlanArgs,
+) -> Result<PlannedOperation, String> {
+    validate_against_api_schema(comparator.api_schema(), query_str)
+        .map_err(|errors| format!("Invalid operation:\n{errors}"))?;
+    set_in_flight(query_path, "native");
+    let native_start = Instant::now();
+    let (rust_plan, native_memory) = measure_memory(args.report_memory, || {
+        comparator.plan_native(query_str, operation_name.clone(), query_path, args.into())
+    });
+    let rust_plan = rust_plan.map_err(|err| err.to_string());
+    let native_time = native_start.elapsed();
+    tracing::debug!(
+        duration_ms = native_time.as_millis() as u64,
+        success = rust_plan.is_ok(),
+        "native planner finished"
+    );
+    set_in_flight(query_path, "legacy");
+    let legacy_start = Instant::now();
+    let (js_plan, legacy_memory) = measure_memory(args.report_memory, || {
+        run_legacy_planner_with_retries(
+            comparator,
+            query_str,
+            operation_name.as_ref().map(|name| name.to_string()),
+            args,
+        )
+    });
+    let js_plan = js_plan.map_err(|err| err.join("\n"));
+    let legacy_time = legacy_start.elapsed();
+    tracing::debug!(
+        duration_ms = legacy_time.as_millis() as u64,
+        success = js_plan.is_ok(),
+        "legacy planner finished"
+    );
+    Ok(PlannedOperation {
+        js_plan,
+        rust_plan,
+        legacy_time,
+        native_time,
+        legacy_memory,
+        native_memory,
+        query: query_str.to_string(),
+        operation_name,
+    })
+}
+
+fn measure_memory<T>(enabled: bool, f: impl FnOnce() -> T) -> (T, Option<usize>) {
+    if enabled {
+        peak_memory_growth(f)
+    } else {
+        (f(), None)
+    }
+}
+
+// The JS planner occasionally fails transiently (e.g. under heavy load).
+fn run_legacy_planner_with_retries(
+    comparator: &Comparator,
+    query_str: &str,
+    operation_name: Option<String>,
+    args: &PlanArgs,
+) -> Result<LegacyQueryPlanResult, Vec<String>> {
+    let mut previous_errors = None;
+    for attempt in 0..=args.legacy_retries {
+        if attempt > 0 {
+            std::thread::sleep(LEGACY_RETRY_BACKOFF * attempt as u32);
+        }
+        match comparator.plan_legacy(query_str, operation_name.clone(), args.into()) {
+            Ok(js_plan) => {
+                if attempt > 0 {
+                    tracing::warn!(attempt, "The legacy planner succeeded on retry");
+                }
+                return Ok(js_plan);
+            }
+            Err(errors) => {
+                if previous_errors.as_ref() == Some(&errors) {
+                    // Deterministic failure: retrying won't help.
+                    return Err(errors);
+                }
+                previous_errors = Some(errors);
+            }
+        }
+    }
+    Err(previous_errors.unwrap_or_default())
+}
+
+// `query_str` and `operation_name` are the operation planned, quoted near the divergence of
+// mismatching plans.
+pub fn compare_plans(
+    js_plan: &LegacyQueryPlanResult,
+    rust_plan: &NativeQueryPlan,
+    query_str: &str,
+    operation_name: Option<&Name>,
+    args: &PlanArgs,
+) -> Result<(), String> {
+    let comparison = plan_matches_with_fingerprint(js_plan, rust_plan, &CompareOptions::from(args));
+    let matched = comparison.is_ok();
+    if args.dump_plans && !(matched && args.only_failing_dumps) {
         write_file(
             "./plan_legacy.txt",
             js_plan.formatted_query_plan.as_ref().unwrap(),
         );
-        write_file("./plan_legacy.detail.txt", &render_legacy_plan(&js_plan));
+        write_file(
+            "./plan_legacy.detail.txt",
+            &plan_detail(js_plan.root_node(), args),
+        );
         write_file("./plan_native.txt", rust_plan.to_string().as_str());
-        write_file("./plan_native.detail.txt", &render_native_plan(&rust_plan));
+        write_file(
+            "./plan_native.detail.txt",
+            &plan_detail(convert_native_plan(rust_plan).as_ref(), args),
+        );
+    }
+    if args.validate_rewrites {
+        let rust_root_node = convert_native_plan(rust_plan);
+        let problems: Vec<String> = [
+            ("legacy", js_plan.root_node()),
+            ("native", rust_root_node.as_ref()),
+        ]
+        .into_iter()
+        .flat_map(|(planner, node)| {
+            invalid_rewrites(node)
+                .into_iter()
+                .map(move |problem| format!("{planner} plan: {problem}"))
+        })
+        .collect();
+        if !problems.is_empty() {
+            return Err(format!("Invalid rewrites:\n{}", problems.join("\n")));
+        }
+    }
+    if args.validate_structure {
+        let rust_root_node = convert_native_plan(rust_plan);
+        let problems: Vec<String> = [
+            ("legacy", js_plan.root_node()),
+            ("native", rust_root_node.as_ref()),
+        ]
+        .into_iter()
+        .flat_map(|(planner, node)| {
+            dangling_depends(node)
+                .into_iter()
+                .map(move |problem| format!("{planner} plan: {problem}"))
+        })
+        .collect();
+        if !problems.is_empty() {
+            return Err(format!("Invalid plan structure:\n{}", problems.join("\n")));
+        }
     }
-    match plan_matches(&js_plan, &rust_plan) {
-        Ok(_) => Ok(()),
-        Err(match_failure) => {
-            let diff = diff_plan(&js_plan, &rust_plan);
-            Err(format!(
-                "Query plan mismatch:\n{match_failure:#?}\n\nDiff:\n{diff}"
-            ))
+    if let Some(max_bytes) = args.max_operation_bytes {
+        let rust_root_node = convert_native_plan(rust_plan);
+        let oversized: Vec<String> = [
+            ("legacy", js_plan.root_node()),
+            ("native", rust_root_node.as_ref()),
+        ]
+        .into_iter()
+        .flat_map(|(planner, node)| {
+            oversized_operations(node, max_bytes)
+                .into_iter()
+                .map(move |operation| format!("{planner} plan: {operation}"))
+        })
+        .collect();
+        if !oversized.is_empty() {
+            if args.fail_on_large_operations {
+                return Err(format!(
+                    "Oversized subgraph operations:\n{}",
+                    oversized.join("\n")
+                ));
+            }
+            for operation in &oversized {
+                tracing::warn!("Oversized subgraph operation in the {operation}");
+            }
         }
     }
+    let Err(match_failure) = comparison else {
+        return Ok(());
+    };
+    let rust_root_node = convert_native_plan(rust_plan);
+    let diff = plan_diff(
+        js_plan.root_node(),
+        rust_root_node.as_ref(),
+        ["legacy", "native"],
+        args,
+    );
+    let fetch_diff = fetch_differences(js_plan, rust_plan);
+    let metrics = MetricsComparison::new(js_plan, rust_plan);
+    let divergence = divergence_line(&match_failure);
+    let snippet = divergence_snippet(&match_failure, query_str, operation_name);
+    Err(format!(
+        "Query plan mismatch:\n{divergence}{snippet}{match_failure:#?}\n\n{fetch_diff}\n\
+         {metrics}\nDiff:\n{diff}"
+    ))
 }
 
-fn main() -> ExitCode {
-    let args = PlanArgs::parse();
-    let schema = fs::read_to_string(&args.schema).unwrap();
-    let query = fs::read_to_string(&args.operation).unwrap();
-    let result = run_both_planners(&schema, &query, &args);
-    match result {
-        Err(error) => {
-            eprintln!("{error}");
-            ExitCode::FAILURE
+// The plan tree in full detail, as in `render_plan`, for dumps.
+fn plan_detail(node: Option<&PlanNode>, args: &PlanArgs) -> String {
+    match node {
+        Some(node) if args.canonical_operations => {
+            render_plan(Some(&canonicalize_operations(node)))
         }
+        node => render_plan(node),
+    }
+}
 
-        Ok(_) => ExitCode::SUCCESS,
+// Diffs two plans in the `--diff-style`, styled for stderr. `labels` name the plans.
+fn plan_diff(
+    this: Option<&PlanNode>,
+    other: Option<&PlanNode>,
+    labels: [&str; 2],
+    args: &PlanArgs,
+) -> String {
+    let canonical = |node: Option<&PlanNode>| {
+        node.filter(|_| args.canonical_operations)
+            .map(canonicalize_operations)
+    };
+    let (this_canonical, other_canonical) = (canonical(this), canonical(other));
+    let this = this_canonical.as_ref().or(this);
+    let other = other_canonical.as_ref().or(other);
+    let style = Style {
+        elide_matching: !args.full,
+        indent: args.indent,
+        explain: args.explain,
+        ..Style::for_stream(&std::io::stderr(), args.color, args.width)
+    };
+    diff_nodes_with_style(this, other, args.diff_style, labels, &style)
+}
+
+fn divergence_line(failure: &MatchFailure) -> String {
+    failure
+        .divergence_path()
+        .map(|path| format!("First divergence: {path}\n"))
+        .unwrap_or_default()
+}
+
+// The part of the operation that the first divergent fetch (or subscription) plans, if located.
+fn divergence_snippet(
+    failure: &MatchFailure,
+    query_str: &str,
+    operation_name: Option<&Name>,
+) -> String {
+    let subgraph_operation = match failure.divergent_node() {
+        Some(PlanNode::Fetch(fetch)) => &fetch.operation,
+        Some(PlanNode::Flatten(flatten)) => match &*flatten.node {
+            PlanNode::Fetch(fetch) => &fetch.operation,
+            _ => return String::new(),
+        },
+        Some(PlanNode::Subscription { primary, .. }) => &primary.operation,
+        _ => return String::new(),
+    };
+    source_snippet(
+        query_str,
+        operation_name.map(Name::as_str),
+        failure.divergence_response_path(),
+        subgraph_operation.as_serialized(),
+    )
+    .map(|snippet| format!("In the operation:\n{snippet}"))
+    .unwrap_or_default()
+}
+
+// Compares the plans, or reports the planners that failed.
+fn compare_planned(planned: &PlannedOperation, args: &PlanArgs) -> Result<(), String> {
+    match (&planned.js_plan, &planned.rust_plan) {
+        (Ok(js_plan), Ok(rust_plan)) => compare_plans(
+            js_plan,
+            rust_plan,
+            &planned.query,
+            planned.operation_name.as_ref(),
+            args,
+        ),
+        (Err(legacy_error), Ok(rust_plan)) => Err(format!(
+            "Only the native planner succeeded (a behavior change to review).\n\
+             Legacy planner error: {legacy_error}\nNative plan:\n{rust_plan}"
+        )),
+        (Ok(js_plan), Err(native_error)) => Err(format!(
+            "Only the legacy planner succeeded (a behavior change to review).\n\
+             Native planner error: {native_error}\nLegacy plan:\n{}",
+            match &js_plan.formatted_query_plan {
+                Some(formatted) => formatted.to_string(),
+                None => render_legacy_plan(js_plan),
+            }
+        )),
+        (Err(legacy_error), Err(native_error)) => Err(format!(
+            "Legacy planner error: {legacy_error}\nNative planner error: {native_error}"
+        )),
+    }
+}
+
+//=================================================================================================
+// Legacy A/B mode: compare the legacy planner against itself under two configs
+
+fn run_legacy_ab(schema_str: &str, query_str: &str, args: &PlanArgs) -> Result<(), String> {
+    let config_b = args
+        .legacy_b_planner_config()?
+        .expect("legacy A/B mode requires --legacy-b-config");
+    let (query_str, operation_name) = prepare_operation(query_str, args)?;
+    let operation_name = operation_name.map(|name| name.to_string());
+    let run = |config| {
+        run_legacy_planner_with_retries(
+            schema_str,
+            &query_str,
+            operation_name.clone(),
+            config,
+            args,
+        )
+        .map_err(|err| err.join("\n"))
+    };
+    let plan_a = run(args.legacy_planner_config()?)?;
+    let plan_b = run(config_b)?;
+    if let Some(formatted) = &plan_b.formatted_query_plan {
+        println!("{formatted}");
+    }
+    legacy_plans_match(&plan_a, &plan_b, &CompareOptions::from(args)).map_err(|failure| {
+        let diff = plan_diff(plan_a.root_node(), plan_b.root_node(), ["A", "B"], args);
+        let divergence = divergence_line(&failure);
+        format!(
+            "Legacy query plan mismatch (left: A, right: B):\n{divergence}{failure:#?}\n\n\
+             Diff:\n{diff}"
+        )
+    })
+}
+
+//=================================================================================================
+// Schema drift mode: plan an operation against two versions of the schema
+
+fn run_schema_drift(schema_str: &str, query_str: &str, args: &PlanArgs) -> Result<(), String> {
+    let schema_b_path = args
+        .schema_b
+        .as_ref()
+        .expect("schema drift mode requires --schema-b");
+    let schema_b = fs::read_to_string(schema_b_path)
+        .map_err(|err| format!("Failed to read {}: {err}", schema_b_path.display()))?;
+    let (query_str, operation_name) = prepare_operation(query_str, args)?;
+    let config = args.native_planner_config()?;
+    let run = |schema: &str| {
+        run_native_planner(
+            schema,
+            &query_str,
+            operation_name.clone(),
+            args.operation(),
+            config.clone(),
+            args.into(),
+        )
+        .map_err(|err| err.to_string())
+    };
+    let plan_a = run(schema_str)?;
+    let plan_b = run(&schema_b)?;
+    println!("{plan_b}");
+    native_plans_match(&plan_a, &plan_b, &CompareOptions::from(args)).map_err(|failure| {
+        let diff = plan_diff(
+            convert_native_plan(&plan_a).as_ref(),
+            convert_native_plan(&plan_b).as_ref(),
+            ["--schema", "--schema-b"],
+            args,
+        );
+        let divergence = divergence_line(&failure);
+        let changes = schema_changes_report(schema_str, &schema_b, &[&plan_a, &plan_b]);
+        format!(
+            "Query plan drift (left: --schema, right: --schema-b):\n{divergence}{failure:#?}\n\n\
+             {changes}\nDiff:\n{diff}"
+        )
+    })
+}
+
+// Lists the changed schema definitions, those the plans mention first: they are the likely causes.
+fn schema_changes_report(old: &str, new: &str, plans: &[&NativeQueryPlan]) -> String {
+    let changes = match schema_changes(old, new) {
+        Ok(changes) => changes,
+        Err(err) => return format!("Schema changes unavailable: {err}\n"),
+    };
+    if changes.is_empty() {
+        return "No schema definition changed.\n".to_string();
+    }
+    let rendered_plans: Vec<String> = plans.iter().map(|plan| plan.to_string()).collect();
+    let rendered_plans = rendered_plans.join("\n");
+    let (related, unrelated): (Vec<_>, Vec<_>) = changes
+        .iter()
+        .partition(|change| change.is_mentioned_in(&rendered_plans));
+    let mut report = String::new();
+    for (title, changes) in [
+        ("Schema changes mentioned by the plans", related),
+        ("Other schema changes", unrelated),
+    ] {
+        if !changes.is_empty() {
+            let changes: Vec<String> = changes.iter().map(ToString::to_string).collect();
+            report.push_str(&format!("{title}: {}\n", changes.join(", ")));
+        }
+    }
+    report
+}
+
+//=================================================================================================
+// Schema variants mode: plan an operation against every schema of a directory
+
+const SCHEMA_EXTENSIONS: [&str; 4] = ["graphql", "graphqls", "gql", "sdl"];
+
+// The schema files of a `--schema` directory, in name order.
+fn schema_variant_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut schema_files = Vec::new();
+    let extensions = SCHEMA_EXTENSIONS.map(String::from);
+    collect_operation_files(dir, &extensions, &mut schema_files)
+        .map_err(|err| format!("Failed to read {}: {err}", dir.display()))?;
+    schema_files.sort();
+    Ok(schema_files)
+}
+
+// Plans the operation against each schema of the `--schema` directory (in name order, the first
+// one being the baseline), prints which schemas produce which distinct plans, and reports how the
+// plans differ from the baseline one. Returns whether every schema planned the operation.
+fn run_schema_variants(args: &PlanArgs) -> Result<bool, String> {
+    let schemas = schema_variant_files(&args.schema)?
+        .iter()
+        .map(|path| {
+            let name = slash_path(path.strip_prefix(&args.schema).unwrap_or(path));
+            let schema = fs::read_to_string(path)
+                .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+            check_schema_not_operation(&schema)
+                .map_err(|err| format!("{}: {err}", path.display()))?;
+            Ok((name, schema))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let Some((baseline_name, baseline_schema)) = schemas.first() else {
+        return Err(format!("No schema files in {}", args.schema.display()));
+    };
+    let query_str = fs::read_to_string(args.operation())
+        .map_err(|err| format!("Failed to read {}: {err}", args.operation().display()))?;
+    let (query_str, operation_name) = prepare_operation(&query_str, args)?;
+    let config = args.native_planner_config()?;
+    let plans: Vec<Result<NativeQueryPlan, String>> = schemas
+        .iter()
+        .map(|(_, schema)| {
+            run_native_planner(
+                schema,
+                &query_str,
+                operation_name.clone(),
+                args.operation(),
+                config.clone(),
+                args.into(),
+            )
+            .map_err(|err| err.to_string())
+        })
+        .collect();
+    let options = CompareOptions::from(args);
+    let plan_numbers = distinct_plan_numbers(&plans, &options);
+    let distinct = plan_numbers.iter().flatten().max().copied().unwrap_or(0);
+    println!(
+        "{distinct} distinct plans against {} schemas (baseline: {baseline_name}):",
+        schemas.len()
+    );
+    let width = schemas
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for ((name, _), number) in schemas.iter().zip(&plan_numbers) {
+        match number {
+            Some(number) => println!("{name:<width$}  plan {number}"),
+            None => println!("{name:<width$}  ERROR"),
+        }
+    }
+    for ((name, _), plan) in schemas.iter().zip(&plans) {
+        if let Err(error) = plan {
+            eprintln!("{name}: {error}");
+        }
+    }
+    let Ok(baseline_plan) = &plans[0] else {
+        return Ok(false);
+    };
+    for (((name, schema), plan), number) in schemas.iter().zip(&plans).zip(&plan_numbers).skip(1) {
+        let Ok(plan) = plan else {
+            continue;
+        };
+        if let Err(failure) = native_plans_match(baseline_plan, plan, &options) {
+            let diff = plan_diff(
+                convert_native_plan(baseline_plan).as_ref(),
+                convert_native_plan(plan).as_ref(),
+                [baseline_name.as_str(), name.as_str()],
+                args,
+            );
+            let divergence = divergence_line(&failure);
+            let changes = schema_changes_report(baseline_schema, schema, &[baseline_plan, plan]);
+            eprintln!(
+                "\nPlan {} of {name} differs from the baseline (left: {baseline_name}, right: \
+                 {name}):\n{divergence}{changes}Diff:\n{diff}",
+                number.expect("planned schemas are numbered")
+            );
+        }
+    }
+    Ok(plans.iter().all(Result::is_ok))
+}
+
+// Numbers the distinct plans (from 1) in order of first appearance, plans with the same canonical
+// form per `options` sharing their number. Failed plans have none.
+fn distinct_plan_numbers(
+    plans: &[Result<NativeQueryPlan, String>],
+    options: &CompareOptions,
+) -> Vec<Option<usize>> {
+    let mut distinct: HashMap<Option<CanonicalPlan>, usize> = HashMap::new();
+    plans
+        .iter()
+        .map(|plan| {
+            let root_node = convert_native_plan(plan.as_ref().ok()?);
+            let canonical = root_node
+                .as_ref()
+                .map(|node| CanonicalPlan::with_options(node, options));
+            let next = distinct.len() + 1;
+            Some(*distinct.entry(canonical).or_insert(next))
+        })
+        .collect()
+}
+
+//=================================================================================================
+// Watchdog: abort batch runs that stop making progress
+
+struct Progress {
+    last_completed: Instant,
+    /// The operation file being planned, the planner running, and since when.
+    in_flight: Option<(PathBuf, &'static str, Instant)>,
+}
+
+static PROGRESS: Mutex<Option<Progress>> = Mutex::new(None);
+
+fn set_in_flight(operation: &Path, planner: &'static str) {
+    if let Some(progress) = PROGRESS.lock().unwrap().as_mut() {
+        progress.in_flight = Some((operation.to_owned(), planner, Instant::now()));
+    }
+}
+
+fn mark_completed() {
+    if let Some(progress) = PROGRESS.lock().unwrap().as_mut() {
+        progress.last_completed = Instant::now();
+        progress.in_flight = None;
+    }
+}
+
+// Note: Planner calls can't be interrupted, so the watchdog exits the whole process.
+fn spawn_watchdog(interval: Duration) {
+    *PROGRESS.lock().unwrap() = Some(Progress {
+        last_completed: Instant::now(),
+        in_flight: None,
+    });
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(interval.min(Duration::from_secs(1)));
+            let progress = PROGRESS.lock().unwrap();
+            let Some(progress) = progress.as_ref() else {
+                continue;
+            };
+            if progress.last_completed.elapsed() < interval {
+                continue;
+            }
+            match &progress.in_flight {
+                Some((operation, planner, since)) => tracing::error!(
+                    "Watchdog: no operation completed in {}s; {} has been running in the {planner} planner for {:.1}s. Aborting.",
+                    interval.as_secs(),
+                    operation.display(),
+                    since.elapsed().as_secs_f64()
+                ),
+                None => tracing::error!(
+                    "Watchdog: no operation completed in {}s. Aborting.",
+                    interval.as_secs()
+                ),
+            }
+            std::process::exit(2);
+        }
+    });
+}
+
+//=================================================================================================
+// Progress: throughput and ETA of batch runs
+
+/// The number of most recent operations the throughput is measured over. Planning times vary a
+/// lot between operations, mostly in the legacy planner, so a single one says little.
+const PROGRESS_WINDOW: usize = 50;
+
+struct BatchProgress {
+    total: usize,
+    completed: usize,
+    /// The durations of the most recent operations, oldest first.
+    recent: VecDeque<Duration>,
+    /// Whether to keep a progress line up to date at the bottom of the terminal.
+    live: bool,
+}
+
+impl BatchProgress {
+    fn new(total: usize, live: bool) -> Self {
+        Self {
+            total,
+            completed: 0,
+            recent: VecDeque::with_capacity(PROGRESS_WINDOW),
+            live,
+        }
+    }
+
+    fn record(&mut self, duration: Duration) {
+        self.completed += 1;
+        if self.recent.len() == PROGRESS_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(duration);
+    }
+
+    /// Counts an operation that wasn't planned (e.g. a skipped introspection query), leaving its
+    /// near-zero duration out of the throughput.
+    fn skip(&mut self) {
+        self.completed += 1;
+    }
+
+    /// Operations completed per second, over the most recent operations.
+    fn throughput(&self) -> Option<f64> {
+        let elapsed: Duration = self.recent.iter().sum();
+        (!elapsed.is_zero()).then(|| self.recent.len() as f64 / elapsed.as_secs_f64())
+    }
+
+    /// The time left to complete the remaining operations at the current throughput.
+    fn eta(&self) -> Option<Duration> {
+        let remaining = self.total.saturating_sub(self.completed);
+        self.throughput()
+            .map(|throughput| Duration::from_secs_f64(remaining as f64 / throughput))
+    }
+
+    /// The throughput and ETA, e.g. `3.2 ops/s, ETA 1m42s`, once an operation completed.
+    fn estimate(&self) -> Option<String> {
+        let (throughput, eta) = (self.throughput()?, self.eta()?);
+        Some(format!("{throughput:.1} ops/s, ETA {}", format_eta(eta)))
+    }
+
+    /// Redraws the progress line on stderr, when live.
+    fn draw(&self) {
+        if self.live {
+            let estimate = self.estimate().unwrap_or_default();
+            eprint!("\r\x1b[K[{}/{}] {estimate}", self.completed, self.total);
+        }
+    }
+
+    /// Erases the progress line, before anything else is printed.
+    fn clear(&self) {
+        if self.live {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+fn format_eta(eta: Duration) -> String {
+    let seconds = eta.as_secs_f64().ceil() as u64;
+    if seconds >= 3600 {
+        format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60)
+    } else if seconds >= 60 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+//=================================================================================================
+// Interruption: stop batch runs cleanly on Ctrl-C
+
+/// The exit code of interrupted batch runs, as shells report processes killed by SIGINT.
+const INTERRUPTED_EXIT_CODE: u8 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// On Ctrl-C, batch runs stop starting new operations and report the partial run. A second Ctrl-C
+// exits right away, e.g. when the operation in flight hangs.
+fn install_interrupt_handler() {
+    std::thread::spawn(|| {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(err) => {
+                tracing::warn!("Failed to install the Ctrl-C handler: {err}");
+                return;
+            }
+        };
+        runtime.block_on(async {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                    std::process::exit(INTERRUPTED_EXIT_CODE.into());
+                }
+                tracing::warn!(
+                    "Interrupted: finishing the operation in flight, then reporting the partial \
+                     run. Press Ctrl-C again to exit right away."
+                );
+            }
+        });
+    });
+}
+
+//=================================================================================================
+// Batch mode: compare every operation file in a directory
+
+#[derive(Debug, Default)]
+struct BatchSummary {
+    matched: usize,
+    /// Matched operations whose plans only differ in orders the comparison ignores.
+    trivially_divergent: usize,
+    mismatched: usize,
+    /// Mismatched operations listed in the allowlist, which don't fail the run.
+    ignored: usize,
+    /// Allowlisted operations that match, whose allowlist entries can be pruned.
+    stale_allowlist_entries: Vec<String>,
+    /// The operations fixed and regressed since the `--compare-baseline` run.
+    baseline: Option<BaselineComparison>,
+    /// Mismatched operations per root cause.
+    categories: BTreeMap<MismatchCategory, usize>,
+    /// Operations both planners planned whose native plan is smaller (or larger) than the legacy
+    /// one, per `MetricsComparison::native_size`.
+    native_smaller: usize,
+    native_larger: usize,
+    /// Operations only one of the planners planned.
+    only_native: usize,
+    only_legacy: usize,
+    /// Operations both planners failed to plan (or that couldn't be read).
+    errors: usize,
+    /// Introspection-only operations, skipped (and otherwise left out of the counts) unless
+    /// `--include-introspection` is given, and compared (and counted by outcome) if it is.
+    introspection_skipped: usize,
+    introspection_compared: usize,
+    /// Operations left out once the time budget was exceeded, or the run interrupted.
+    skipped: usize,
+    /// The files of the skipped operations, relative to the operations directory.
+    skipped_operations: Vec<String>,
+    /// Whether the run was cut short by Ctrl-C, rather than by the time budget.
+    interrupted: bool,
+    wall_time: Duration,
+    /// Planning times, summed over the operations both planners planned.
+    native_time: Duration,
+    legacy_time: Duration,
+    /// The largest peak memory growth of each planner, with `--report-memory`.
+    native_peak_memory: Option<usize>,
+    legacy_peak_memory: Option<usize>,
+}
+
+impl BatchSummary {
+    fn operations(&self) -> usize {
+        self.matched
+            + self.mismatched
+            + self.ignored
+            + self.only_native
+            + self.only_legacy
+            + self.errors
+    }
+
+    fn is_success(&self) -> bool {
+        self.operations() == self.matched + self.ignored
+    }
+
+    /// Operations both planners planned.
+    fn planned(&self) -> usize {
+        self.matched + self.mismatched + self.ignored
+    }
+
+    /// How many times faster the native planner was than the legacy one, in total.
+    fn speedup(&self) -> Option<f64> {
+        (!self.native_time.is_zero())
+            .then(|| self.legacy_time.as_secs_f64() / self.native_time.as_secs_f64())
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "operations": self.operations(),
+            "matched": self.matched,
+            "trivially_divergent": self.trivially_divergent,
+            "mismatched": self.mismatched,
+            "ignored": self.ignored,
+            "stale_allowlist_entries": self.stale_allowlist_entries,
+            "baseline": self.baseline.as_ref().map(BaselineComparison::to_json),
+            "mismatch_categories": self
+                .categories
+                .iter()
+                .map(|(category, count)| (category.name(), count))
+                .collect::<BTreeMap<_, _>>(),
+            "native_smaller": self.native_smaller,
+            "native_larger": self.native_larger,
+            "only_native": self.only_native,
+            "only_legacy": self.only_legacy,
+            "errors": self.errors,
+            "introspection_skipped": self.introspection_skipped,
+            "introspection_compared": self.introspection_compared,
+            "skipped": self.skipped,
+            "skipped_operations": self.skipped_operations,
+            "interrupted": self.interrupted,
+            "wall_clock_ms": self.wall_time.as_millis() as u64,
+            "native_ms": self.native_time.as_millis() as u64,
+            "legacy_ms": self.legacy_time.as_millis() as u64,
+            "speedup": self.speedup(),
+            "native_peak_memory_bytes": self.native_peak_memory,
+            "legacy_peak_memory_bytes": self.legacy_peak_memory,
+        })
+    }
+}
+
+impl std::fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} operations: {} matched, {} mismatched, {} planned by native only, {} planned by \
+             legacy only, {} failed to plan",
+            self.operations(),
+            self.matched,
+            self.mismatched,
+            self.only_native,
+            self.only_legacy,
+            self.errors
+        )?;
+        if self.introspection_skipped > 0 {
+            writeln!(
+                f,
+                "{} introspection-only operations skipped (compare them with \
+                 --include-introspection)",
+                self.introspection_skipped
+            )?;
+        }
+        if self.introspection_compared > 0 {
+            writeln!(
+                f,
+                "{} of the operations are introspection-only",
+                self.introspection_compared
+            )?;
+        }
+        if self.trivially_divergent > 0 {
+            writeln!(
+                f,
+                "{} matched operations are trivially divergent (only differing in the order of \
+                 parallel nodes, requires, variable usages or rewrites)",
+                self.trivially_divergent
+            )?;
+        }
+        // Only reported when sizes differ, which they rarely do in a fully matching corpus.
+        if self.native_smaller + self.native_larger > 0 {
+            let share = |count: usize| 100.0 * count as f64 / self.planned() as f64;
+            writeln!(
+                f,
+                "Plan size (fetches, then nodes): native smaller in {} ({:.1}%) and larger in {} \
+                 ({:.1}%) of {} operations planned by both",
+                self.native_smaller,
+                share(self.native_smaller),
+                self.native_larger,
+                share(self.native_larger),
+                self.planned()
+            )?;
+        }
+        if !self.categories.is_empty() {
+            // Most frequent first, then most severe first.
+            let mut categories: Vec<_> = self.categories.iter().collect();
+            categories.sort_by_key(|(category, count)| (std::cmp::Reverse(**count), **category));
+            let categories: Vec<String> = categories
+                .into_iter()
+                .map(|(category, count)| format!("{count} {category}"))
+                .collect();
+            writeln!(f, "Mismatches by cause: {}", categories.join(", "))?;
+        }
+        if self.ignored > 0 {
+            writeln!(
+                f,
+                "{} allowlisted mismatches ignored (not failing the run)",
+                self.ignored
+            )?;
+        }
+        if !self.stale_allowlist_entries.is_empty() {
+            writeln!(
+                f,
+                "{} allowlisted operations now match, prune them from the allowlist: {}",
+                self.stale_allowlist_entries.len(),
+                self.stale_allowlist_entries.join(", ")
+            )?;
+        }
+        if let Some(baseline) = &self.baseline {
+            writeln!(f, "{baseline}")?;
+        }
+        write!(
+            f,
+            "Time: {:.2}s wall-clock, {:.2}s native planning, {:.2}s legacy planning",
+            self.wall_time.as_secs_f64(),
+            self.native_time.as_secs_f64(),
+            self.legacy_time.as_secs_f64()
+        )?;
+        if let Some(speedup) = self.speedup() {
+            write!(f, " (native {speedup:.1}x faster)")?;
+        }
+        if let (Some(native), Some(legacy)) = (self.native_peak_memory, self.legacy_peak_memory) {
+            write!(
+                f,
+                "\nPeak memory growth: {} native planning, {} legacy planning",
+                format_mib(native),
+                format_mib(legacy)
+            )?;
+        }
+        if self.skipped > 0 || self.interrupted {
+            let reason = if self.interrupted {
+                "interrupted"
+            } else {
+                "time budget exceeded"
+            };
+            write!(
+                f,
+                "\nPartial run: {reason}, {} of {} operations skipped",
+                self.skipped,
+                self.operations() + self.skipped
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of one operation in a batch run.
+struct OperationRecord {
+    name: String,
+    /// The display name of the operation (see `operation_display_name`).
+    operation: String,
+    status: &'static str,
+    /// Only known for operations both planners planned.
+    planned: Option<PlannedRecord>,
+}
+
+struct PlannedRecord {
+    native_time: Duration,
+    legacy_time: Duration,
+    metrics: MetricsComparison,
+    mismatch_category: Option<MismatchCategory>,
+    /// The normalized plans, with `--emit-normalized`.
+    normalized: Option<NormalizedPlans>,
+    /// The fingerprint of the native plan, with `--baseline`.
+    native_fingerprint: Option<u64>,
+}
+
+struct NormalizedPlans {
+    native: Option<PlanNode>,
+    legacy: Option<PlanNode>,
+}
+
+impl NormalizedPlans {
+    fn new(js_plan: &LegacyQueryPlanResult, rust_plan: &NativeQueryPlan, args: &PlanArgs) -> Self {
+        let options = CompareOptions::from(args);
+        let normalize =
+            |node: Option<&PlanNode>| node.map(|node| normalize_with_options(node, &options));
+        Self {
+            native: normalize(convert_native_plan(rust_plan).as_ref()),
+            legacy: normalize(js_plan.root_node()),
+        }
+    }
+}
+
+const CSV_HEADER: &str = "name,status,native_ms,legacy_ms,native_nodes,legacy_nodes,\
+                          top_mismatch_kind,node_delta,fetch_delta,operation";
+
+impl OperationRecord {
+    fn csv_row(&self) -> String {
+        let mut fields = vec![csv_field(&self.name), self.status.to_string()];
+        match &self.planned {
+            Some(planned) => fields.extend([
+                planned.native_time.as_millis().to_string(),
+                planned.legacy_time.as_millis().to_string(),
+                planned.metrics.native.nodes.to_string(),
+                planned.metrics.legacy.nodes.to_string(),
+                planned
+                    .mismatch_category
+                    .map_or("", MismatchCategory::name)
+                    .to_string(),
+                planned.metrics.node_delta().to_string(),
+                planned.metrics.fetch_delta().to_string(),
+            ]),
+            None => fields.extend(std::iter::repeat_n(String::new(), 7)),
+        }
+        fields.push(csv_field(&self.operation));
+        fields.join(",")
+    }
+
+    fn baseline_entry(&self) -> BaselineEntry {
+        let outcome = match self.status {
+            "PASS" => Outcome::Matched,
+            "FAIL" | "IGNORED" => Outcome::Mismatch,
+            _ => Outcome::Error,
+        };
+        BaselineEntry {
+            outcome,
+            fingerprint: self
+                .planned
+                .as_ref()
+                .and_then(|planned| planned.native_fingerprint)
+                .map(|fingerprint| format!("{fingerprint:016x}")),
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut json = serde_json::json!({
+            "name": self.name,
+            "operation": self.operation,
+            "status": self.status,
+        });
+        if let Some(planned) = &self.planned {
+            json["native_ms"] = planned.native_time.as_millis().into();
+            json["legacy_ms"] = planned.legacy_time.as_millis().into();
+            json["native_nodes"] = planned.metrics.native.nodes.into();
+            json["legacy_nodes"] = planned.metrics.legacy.nodes.into();
+            json["native_fetches"] = planned.metrics.native.fetches.into();
+            json["legacy_fetches"] = planned.metrics.legacy.fetches.into();
+            json["node_delta"] = planned.metrics.node_delta().into();
+            json["fetch_delta"] = planned.metrics.fetch_delta().into();
+            json["native_fragment_definitions"] =
+                planned.metrics.native.fragment_definitions.into();
+            json["legacy_fragment_definitions"] =
+                planned.metrics.legacy.fragment_definitions.into();
+            json["native_fragment_spreads"] = planned.metrics.native.fragment_spreads.into();
+            json["legacy_fragment_spreads"] = planned.metrics.legacy.fragment_spreads.into();
+            json["top_mismatch_kind"] =
+                planned.mismatch_category.map(MismatchCategory::name).into();
+            if let Some(normalized) = &planned.normalized {
+                json["native_normalized_plan"] = serde_json::to_value(&normalized.native).unwrap();
+                json["legacy_normalized_plan"] = serde_json::to_value(&normalized.legacy).unwrap();
+            }
+        }
+        json
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+//=================================================================================================
+// `--format github`: GitHub Actions workflow annotations
+
+// A workflow command annotating `file`. The message is the summary of the report: its first line
+// and its first divergence, if any, since annotations are meant to be short.
+fn github_annotation(level: &str, file: &Path, title: &str, report: &str) -> String {
+    let mut lines = report.lines();
+    let mut message = lines
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(':')
+        .to_string();
+    if let Some(divergence) = lines.find(|line| line.starts_with("First divergence: ")) {
+        message.push_str(&format!(" ({divergence})"));
+    }
+    format!(
+        "::{level} file={},title={}::{}",
+        escape_github_property(&slash_path(file)),
+        escape_github_property(title),
+        escape_github_data(&message)
+    )
+}
+
+fn escape_github_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_github_property(value: &str) -> String {
+    escape_github_data(value)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+fn collect_operation_files(
+    dir: &Path,
+    extensions: &[String],
+    files: &mut Vec<PathBuf>,
+) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_operation_files(&path, extensions, files)?;
+        } else if has_extension(&path, extensions) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            extensions
+                .iter()
+                .any(|accepted| ext.eq_ignore_ascii_case(accepted))
+        })
+}
+
+fn read_shared_fragments(path: &Path, extensions: &[String]) -> Result<String, String> {
+    let read_error = |err: std::io::Error| format!("Failed to read {}: {err}", path.display());
+    if !path.is_dir() {
+        return fs::read_to_string(path).map_err(read_error);
+    }
+    let mut files = Vec::new();
+    collect_operation_files(path, extensions, &mut files).map_err(read_error)?;
+    files.sort();
+    let mut fragments = Vec::new();
+    for file in files {
+        fragments.push(fs::read_to_string(file).map_err(read_error)?);
+    }
+    Ok(fragments.join("\n"))
+}
+
+fn parse_extension(value: &str) -> Result<String, String> {
+    let extension = value.strip_prefix('.').unwrap_or(value);
+    if extension.is_empty() || extension.contains(['.', '/', '\\']) {
+        return Err("must be a file extension, e.g. `graphql`".to_string());
+    }
+    Ok(extension.to_string())
+}
+
+fn parse_subgraph_schema(value: &str) -> Result<(String, PathBuf), String> {
+    match value.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_string(), PathBuf::from(path)))
+        }
+        _ => Err("must be `NAME=FILE`, e.g. `products=products.graphql`".to_string()),
+    }
+}
+
+fn parse_variables(value: &str) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+    match serde_json::from_str(value).map_err(|err| err.to_string())? {
+        serde_json::Value::Object(variables) => Ok(variables),
+        _ => Err("must be a JSON object".to_string()),
+    }
+}
+
+fn parse_percentage(value: &str) -> Result<f64, String> {
+    let percentage: f64 = value
+        .parse()
+        .map_err(|err: std::num::ParseFloatError| err.to_string())?;
+    if percentage > 0.0 && percentage <= 100.0 {
+        Ok(percentage)
+    } else {
+        Err("must be greater than 0 and at most 100".to_string())
+    }
+}
+
+// Selects `percentage`% of the files (at least one), deterministically for a given seed.
+fn sample_operation_files(files: &[PathBuf], percentage: f64, seed: u64) -> Vec<PathBuf> {
+    let amount = ((files.len() as f64 * percentage / 100.0).ceil() as usize).min(files.len());
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut sample: Vec<PathBuf> = files.choose_multiple(&mut rng, amount).cloned().collect();
+    sample.sort();
+    sample
+}
+
+fn shuffle_operation_files(files: &mut [PathBuf], seed: u64) {
+    files.shuffle(&mut StdRng::seed_from_u64(seed));
+}
+
+// The directory that the entries of an `--operations-from` listfile are relative to.
+fn operation_list_dir(listfile: &Path) -> &Path {
+    match listfile.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+// The operation files of an `--operations-from` listfile, resolved relative to its directory.
+fn parse_operation_list(listfile: &str, dir: &Path) -> Vec<PathBuf> {
+    listfile
+        .lines()
+        .map(|line| line.split_once('#').map_or(line, |(entry, _)| entry).trim())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| dir.join(entry.trim_start_matches("./")))
+        .collect()
+}
+
+// Returns the operation files of directory mode, after `--sample` and `--shuffle`, and the lines
+// reporting that selection. A single operation file is returned as is.
+fn select_operation_files(args: &PlanArgs) -> Result<(Vec<PathBuf>, Vec<String>), String> {
+    let mut operation_files = if let Some(listed) = &args.listed_operations {
+        // Listed operations keep their order.
+        listed.clone()
+    } else if args.operation().is_dir() {
+        let mut operation_files = Vec::new();
+        collect_operation_files(
+            args.operation(),
+            &args.operation_extensions,
+            &mut operation_files,
+        )
+        .map_err(|err| format!("Failed to read {}: {err}", args.operation().display()))?;
+        if let Some(fragments) = &args.fragments {
+            // Shared fragment files may live next to the operations. Both paths are canonicalized,
+            // as they may be spelled differently (e.g. relative and absolute).
+            let fragments = fs::canonicalize(fragments)
+                .map_err(|err| format!("Failed to read {}: {err}", fragments.display()))?;
+            operation_files.retain(|path| {
+                !fs::canonicalize(path).is_ok_and(|path| path.starts_with(&fragments))
+            });
+        }
+        operation_files.sort();
+        operation_files
+    } else {
+        return Ok((vec![args.operation().to_path_buf()], Vec::new()));
+    };
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut footer = Vec::new();
+    if let Some(percentage) = args.sample {
+        let total = operation_files.len();
+        operation_files = sample_operation_files(&operation_files, percentage, seed);
+        footer.push(format!(
+            "Sampled {} of {total} operations (seed: {seed})",
+            operation_files.len()
+        ));
+    }
+    if args.shuffle {
+        shuffle_operation_files(&mut operation_files, seed);
+        // Also reported upfront, in case a planner takes the process down.
+        tracing::info!(seed, "Shuffling the operations");
+        footer.push(format!("Shuffled the operations (seed: {seed})"));
+    }
+    Ok((operation_files, footer))
+}
+
+// Prints the line (or row, or object) of an operation, with its mismatch report or error if any.
+fn print_record(record: &OperationRecord, report: Option<&str>, path: &Path, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => println!("{:<5} {}", record.status, record.name),
+        OutputFormat::Csv => println!("{}", record.csv_row()),
+        OutputFormat::Json => println!("{}", record.to_json()),
+        OutputFormat::Github => {
+            println!("{:<5} {}", record.status, record.name);
+            if let Some(report) = report {
+                let level = if record.status == "IGNORED" {
+                    "warning"
+                } else {
+                    "error"
+                };
+                let title = format!("{} {}", record.status, record.operation);
+                println!("{}", github_annotation(level, path, &title, report));
+            }
+        }
+    }
+}
+
+fn run_batch(schema_str: &str, args: &PlanArgs) -> ExitCode {
+    let (operation_files, mut footer) = match select_operation_files(args) {
+        Ok(selection) => selection,
+        Err(error) => {
+            tracing::error!("{error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let comparator = match build_comparator(schema_str, args) {
+        Ok(comparator) => comparator,
+        Err(error) => {
+            tracing::error!("{error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(seconds) = args.watchdog {
+        spawn_watchdog(Duration::from_secs(seconds));
+    }
+    install_interrupt_handler();
+    let start = Instant::now();
+    let mut summary = BatchSummary::default();
+    let format = args.format();
+    if format == OutputFormat::Csv {
+        println!("{CSV_HEADER}");
+    }
+    let operations = match batch_operation_files(&operation_files, args) {
+        Ok(operations) => operations,
+        Err(error) => {
+            tracing::error!("{error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut dump_dir = args.dump_dir.clone().map(DumpDir::new);
+    let mut baseline = Baseline::default();
+    let time_budget = args.time_budget.map(Duration::from_secs);
+    // Verbose runs report progress on each result line instead.
+    let mut progress = BatchProgress::new(
+        operation_files.len(),
+        !args.verbose && std::io::stderr().is_terminal(),
+    );
+    for (index, path) in operation_files.iter().enumerate() {
+        let interrupted = INTERRUPTED.load(Ordering::SeqCst);
+        if interrupted || time_budget.is_some_and(|budget| start.elapsed() >= budget) {
+            summary.interrupted = interrupted;
+            summary.skipped = operation_files.len() - index;
+            summary.skipped_operations = operation_files[index..]
+                .iter()
+                .map(|path| {
+                    let name = path.strip_prefix(args.operation()).unwrap_or(path);
+                    name.display().to_string()
+                })
+                .collect();
+            break;
+        }
+        let operation_start = Instant::now();
+        let name = path.strip_prefix(args.operation()).unwrap_or(path);
+        let operation_file = &operations[index];
+        let operation = operation_file.name.clone();
+        let _span = tracing::info_span!("operation", name = %name.display(), %operation).entered();
+        if operation_file.is_introspection() {
+            if !args.include_introspection {
+                summary.introspection_skipped += 1;
+                let record = OperationRecord {
+                    name: name.display().to_string(),
+                    operation,
+                    status: "SKIP",
+                    planned: None,
+                };
+                progress.clear();
+                print_record(&record, None, path, format);
+                progress.skip();
+                progress.draw();
+                mark_completed();
+                continue;
+            }
+            summary.introspection_compared += 1;
+        }
+        // Planning may log on stderr, so the progress line is cleared beforehand.
+        progress.clear();
+        let planned = operation_file
+            .prepared
+            .as_ref()
+            .map_err(Clone::clone)
+            .and_then(|(query, operation_name)| {
+                run_both_planners(&comparator, query, operation_name.clone(), path, args)
+            });
+        // The mismatch report or planning error, if any.
+        let mut report = None;
+        // Note: Plans are rendered for every operation, since whether it fails isn't known yet.
+        let mut artifacts = match &dump_dir {
+            Some(_) => plan_artifacts(&planned, args),
+            None => Vec::new(),
+        };
+        let record = match planned {
+            Err(error) => {
+                summary.errors += 1;
+                eprintln!("{error}");
+                report = Some(error.clone());
+                artifacts.push(("error.txt", error));
+                OperationRecord {
+                    name: name.display().to_string(),
+                    operation: operation.clone(),
+                    status: "ERROR",
+                    planned: None,
+                }
+            }
+            Ok(PlannedOperation {
+                js_plan: Ok(js_plan),
+                rust_plan: Ok(rust_plan),
+                native_time,
+                legacy_time,
+                native_memory,
+                legacy_memory,
+                query: planned_query,
+                operation_name,
+            }) => {
+                summary.native_time += native_time;
+                summary.legacy_time += legacy_time;
+                summary.native_peak_memory = summary.native_peak_memory.max(native_memory);
+                summary.legacy_peak_memory = summary.legacy_peak_memory.max(legacy_memory);
+                let metrics = MetricsComparison::new(&js_plan, &rust_plan);
+                match metrics.native_size() {
+                    std::cmp::Ordering::Less => summary.native_smaller += 1,
+                    std::cmp::Ordering::Greater => summary.native_larger += 1,
+                    std::cmp::Ordering::Equal => {}
+                }
+                let allowlisted = args.allowlisted.contains(name);
+                let (status, mismatch_category) = match compare_plans(
+                    &js_plan,
+                    &rust_plan,
+                    &planned_query,
+                    operation_name.as_ref(),
+                    args,
+                ) {
+                    Ok(()) => {
+                        summary.matched += 1;
+                        if trivially_divergent(&js_plan, &rust_plan, &CompareOptions::from(args)) {
+                            summary.trivially_divergent += 1;
+                        }
+                        if allowlisted {
+                            summary
+                                .stale_allowlist_entries
+                                .push(name.display().to_string());
+                        }
+                        ("PASS", None)
+                    }
+                    Err(mismatch) if allowlisted => {
+                        summary.ignored += 1;
+                        eprintln!("{mismatch}");
+                        report = Some(mismatch.clone());
+                        artifacts.push(("diff.txt", mismatch));
+                        let category =
+                            classify_mismatch(&js_plan, &rust_plan, &CompareOptions::from(args));
+                        ("IGNORED", Some(category))
+                    }
+                    Err(mismatch) => {
+                        summary.mismatched += 1;
+                        eprintln!("{mismatch}");
+                        report = Some(mismatch.clone());
+                        artifacts.push(("diff.txt", mismatch));
+                        let category =
+                            classify_mismatch(&js_plan, &rust_plan, &CompareOptions::from(args));
+                        *summary.categories.entry(category).or_default() += 1;
+                        ("FAIL", Some(category))
+                    }
+                };
+                OperationRecord {
+                    name: name.display().to_string(),
+                    operation: operation.clone(),
+                    status,
+                    planned: Some(PlannedRecord {
+                        native_time,
+                        legacy_time,
+                        metrics,
+                        mismatch_category,
+                        normalized: args
+                            .emit_normalized
+                            .then(|| NormalizedPlans::new(&js_plan, &rust_plan, args)),
+                        native_fingerprint: match args.baseline {
+                            Some(_) => convert_native_plan(&rust_plan)
+                                .as_ref()
+                                .map(plan_fingerprint),
+                            None => None,
+                        },
+                    }),
+                }
+            }
+            Ok(planned) => {
+                let status = match (&planned.js_plan, &planned.rust_plan) {
+                    (Err(_), Ok(_)) => {
+                        summary.only_native += 1;
+                        "NATIVE-ONLY"
+                    }
+                    (Ok(_), Err(_)) => {
+                        summary.only_legacy += 1;
+                        "LEGACY-ONLY"
+                    }
+                    _ => {
+                        summary.errors += 1;
+                        "ERROR"
+                    }
+                };
+                if let Err(error) = compare_planned(&planned, args) {
+                    eprintln!("{error}");
+                    report = Some(error.clone());
+                    artifacts.push(("error.txt", error));
+                }
+                OperationRecord {
+                    name: name.display().to_string(),
+                    operation: operation.clone(),
+                    status,
+                    planned: None,
+                }
+            }
+        };
+        print_record(&record, report.as_deref(), path, format);
+        progress.record(operation_start.elapsed());
+        if args.verbose {
+            let estimate = progress
+                .estimate()
+                .map_or_else(String::new, |estimate| format!(", {estimate}"));
+            eprintln!(
+                "[{}/{}] {:<5} {} ({} ms{estimate})",
+                index + 1,
+                operation_files.len(),
+                record.status,
+                record.name,
+                operation_start.elapsed().as_millis()
+            );
+        }
+        baseline.insert(record.operation.clone(), record.baseline_entry());
+        if let Some(dump_dir) = dump_dir
+            .as_mut()
+            .filter(|_| !(record.status == "PASS" && args.only_failing_dumps))
+        {
+            if let Err(error) = dump_dir.dump(name, &record.operation, record.status, &artifacts) {
+                tracing::error!("{error}");
+            }
+        }
+        progress.draw();
+        mark_completed();
+    }
+    progress.clear();
+    summary.wall_time = start.elapsed();
+    if let Some(dump_dir) = &dump_dir {
+        if let Err(error) = dump_dir.write_index() {
+            tracing::error!("{error}");
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Some(previous) = &args.previous_baseline {
+        summary.baseline = Some(previous.compare(&baseline));
+    }
+    if let Some(path) = &args.baseline {
+        if let Err(err) = fs::write(path, baseline.to_json_string()) {
+            tracing::error!("Failed to write {}: {err}", path.display());
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Some(path) = &args.summary_json {
+        let json = serde_json::to_string_pretty(&summary.to_json()).unwrap();
+        if let Err(err) = fs::write(path, json) {
+            tracing::error!("Failed to write {}: {err}", path.display());
+            return ExitCode::FAILURE;
+        }
+    }
+    footer.push(summary.to_string());
+    for line in footer {
+        match format {
+            OutputFormat::Text | OutputFormat::Github => println!("{line}"),
+            OutputFormat::Csv | OutputFormat::Json => eprintln!("{line}"),
+        }
+    }
+
+    if summary.interrupted {
+        ExitCode::from(INTERRUPTED_EXIT_CODE)
+    } else if summary.is_success() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+//=================================================================================================
+// `--planner-only`: plan with a single planner, without comparing
+
+fn run_planner_only(schema_str: &str, planner: SinglePlanner, args: &PlanArgs) -> ExitCode {
+    let (operation_files, footer) = match select_operation_files(args) {
+        Ok(selection) => selection,
+        Err(error) => {
+            tracing::error!("{error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let (native_config, legacy_config) =
+        match (args.native_planner_config(), args.legacy_planner_config()) {
+            (Ok(native_config), Ok(legacy_config)) => (native_config, legacy_config),
+            (Err(error), _) | (_, Err(error)) => {
+                tracing::error!("{error}");
+                return ExitCode::FAILURE;
+            }
+        };
+    install_interrupt_handler();
+    let format = args.format();
+    if format == OutputFormat::Csv {
+        println!("name,status,planner_ms");
+    }
+    // The native planner is built once and reused for every operation.
+    let pool = NativePlannerPool::new(NonZeroUsize::MIN);
+    let start = Instant::now();
+    let (mut planned, mut failed) = (0, 0);
+    for path in &operation_files {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            break;
+        }
+        // Single operation files are named in full.
+        let name = match path.strip_prefix(args.operation()) {
+            Ok(name) if !name.as_os_str().is_empty() => name,
+            _ => path.as_path(),
+        };
+        let operation_start = Instant::now();
+        let result = fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|query| {
+                let (query_str, operation_name) = prepare_operation(&query, args)?;
+                match planner {
+                    SinglePlanner::Native => run_pooled_native_planner(
+                        &pool,
+                        schema_str,
+                        &query_str,
+                        operation_name,
+                        path,
+                        &native_config,
+                        args.into(),
+                    )
+                    .map(drop)
+                    .map_err(|err| err.to_string()),
+                    SinglePlanner::Legacy => run_legacy_planner_with_retries(
+                        schema_str,
+                        &query_str,
+                        operation_name.map(|name| name.to_string()),
+                        legacy_config.clone(),
+                        args,
+                    )
+                    .map(drop)
+                    .map_err(|errors| errors.join("\n")),
+                }
+            });
+        let planner_ms = operation_start.elapsed().as_millis();
+        let status = match &result {
+            Ok(()) => {
+                planned += 1;
+                "OK"
+            }
+            Err(error) => {
+                failed += 1;
+                eprintln!("{}: {error}", name.display());
+                "ERROR"
+            }
+        };
+        match format {
+            OutputFormat::Text | OutputFormat::Github => println!("{status:<5} {}", name.display()),
+            OutputFormat::Csv => println!(
+                "{},{status},{planner_ms}",
+                csv_field(&name.display().to_string())
+            ),
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::json!({
+                    "name": name.display().to_string(),
+                    "status": status,
+                    "planner_ms": planner_ms,
+                    "error": result.err(),
+                })
+            ),
+        }
+    }
+    let planner_name = match planner {
+        SinglePlanner::Native => "native",
+        SinglePlanner::Legacy => "legacy",
+    };
+    let mut summary = vec![format!(
+        "Planned {} of {} operations with the {planner_name} planner: {planned} succeeded, \
+         {failed} failed (in {:.1}s)",
+        planned + failed,
+        operation_files.len(),
+        start.elapsed().as_secs_f64()
+    )];
+    summary.extend(footer);
+    for line in summary {
+        match format {
+            OutputFormat::Text | OutputFormat::Github => println!("{line}"),
+            OutputFormat::Csv | OutputFormat::Json => eprintln!("{line}"),
+        }
+    }
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        ExitCode::from(INTERRUPTED_EXIT_CODE)
+    } else if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+//=================================================================================================
+// `--dump-dir`: per-operation artifacts, in a tree mirroring the operations directory
+
+// The plans of an operation, by artifact file name.
+fn plan_artifacts(
+    planned: &Result<PlannedOperation, String>,
+    args: &PlanArgs,
+) -> Vec<(&'static str, String)> {
+    let mut artifacts = Vec::new();
+    let Ok(planned) = planned else {
+        return artifacts;
+    };
+    if let Ok(js_plan) = &planned.js_plan {
+        if let Some(formatted) = &js_plan.formatted_query_plan {
+            artifacts.push(("legacy.txt", formatted.to_string()));
+        }
+        artifacts.push(("legacy.detail.txt", plan_detail(js_plan.root_node(), args)));
+    }
+    if let Ok(rust_plan) = &planned.rust_plan {
+        artifacts.push(("native.txt", rust_plan.to_string()));
+        let rust_root_node = convert_native_plan(rust_plan);
+        artifacts.push((
+            "native.detail.txt",
+            plan_detail(rust_root_node.as_ref(), args),
+        ));
+    }
+    artifacts
+}
+
+/// The `--dump-dir` output, with the index of the operations dumped so far.
+struct DumpDir {
+    root: PathBuf,
+    /// Artifact paths and status by operation, all relative to their root directory.
+    index: BTreeMap<String, serde_json::Value>,
+}
+
+impl DumpDir {
+    fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            index: BTreeMap::new(),
+        }
+    }
+
+    /// Writes the artifacts of an operation (relative to the operations directory) under a
+    /// subdirectory named after it.
+    fn dump(
+        &mut self,
+        operation: &Path,
+        display_name: &str,
+        status: &str,
+        artifacts: &[(&str, String)],
+    ) -> Result<(), String> {
+        let write_error =
+            |path: &Path, err: std::io::Error| format!("Failed to write {}: {err}", path.display());
+        let dir = self.root.join(operation);
+        fs::create_dir_all(&dir).map_err(|err| write_error(&dir, err))?;
+        let mut paths = Vec::new();
+        for (file_name, content) in artifacts {
+            let path = dir.join(file_name);
+            fs::write(&path, content).map_err(|err| write_error(&path, err))?;
+            paths.push(slash_path(&operation.join(file_name)));
+        }
+        self.index.insert(
+            slash_path(operation),
+            serde_json::json!({
+                "operation": display_name,
+                "status": status,
+                "artifacts": paths,
+            }),
+        );
+        Ok(())
+    }
+
+    fn write_index(&self) -> Result<(), String> {
+        let path = self.root.join("index.json");
+        fs::create_dir_all(&self.root)
+            .and_then(|()| fs::write(&path, serde_json::to_string_pretty(&self.index).unwrap()))
+            .map_err(|err| format!("Failed to write {}: {err}", path.display()))
+    }
+}
+
+// Joins the path components with `/`, so that indexes don't depend on the platform.
+fn slash_path(path: &Path) -> String {
+    let components: Vec<_> = path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+//=================================================================================================
+// `--list`: the operations a run would compare
+
+/// An operation selected for comparison, listed by `--list`.
+struct ListedOperation {
+    /// Relative to the operation directory in directory mode.
+    file: String,
+    /// The operation's display name, or the reason it can't be planned.
+    operation: Result<String, String>,
+}
+
+impl ListedOperation {
+    fn csv_row(&self) -> String {
+        let (name, error) = match &self.operation {
+            Ok(name) => (name.as_str(), ""),
+            Err(error) => ("", error.as_str()),
+        };
+        [self.file.as_str(), name, error].map(csv_field).join(",")
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match &self.operation {
+            Ok(name) => serde_json::json!({ "file": self.file, "operation": name }),
+            Err(error) => serde_json::json!({ "file": self.file, "error": error }),
+        }
+    }
+}
+
+impl std::fmt::Display for ListedOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.operation {
+            Ok(name) => write!(f, "{name} ({})", self.file),
+            Err(error) => write!(f, "ERROR ({}): {error}", self.file),
+        }
+    }
+}
+
+// Resolves the operation of each file the way planning does, so that invalid selections are
+// reported without running the planners.
+fn list_operation(path: &Path, args: &PlanArgs) -> ListedOperation {
+    let file = if args.operation().is_dir() {
+        path.strip_prefix(args.operation()).unwrap_or(path)
+    } else {
+        path
+    };
+    let operation = fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|query| {
+            let (prepared, name) = prepare_operation(&query, args)?;
+            Ok(match name {
+                Some(name) => name.to_string(),
+                None => operation_display_name(path, &prepared),
+            })
+        });
+    ListedOperation {
+        file: file.display().to_string(),
+        operation,
+    }
+}
+
+fn run_list(args: &PlanArgs) -> ExitCode {
+    let (operation_files, footer) = match select_operation_files(args) {
+        Ok(selection) => selection,
+        Err(error) => {
+            tracing::error!("{error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut listed: Vec<ListedOperation> = operation_files
+        .iter()
+        .map(|path| list_operation(path, args))
+        .collect();
+    let named = listed
+        .iter_mut()
+        .filter_map(|ListedOperation { file, operation }| {
+            Some((&**file, operation.as_mut().ok()?))
+        });
+    if let Err(error) = resolve_duplicate_names(named, args.on_duplicate) {
+        tracing::error!("{error}");
+        return ExitCode::FAILURE;
+    }
+    match args.format() {
+        OutputFormat::Json => {
+            let json: Vec<serde_json::Value> =
+                listed.iter().map(ListedOperation::to_json).collect();
+            println!("{}", serde_json::to_string_pretty(&json).unwrap());
+        }
+        OutputFormat::Csv => {
+            println!("file,operation,error");
+            for operation in &listed {
+                println!("{}", operation.csv_row());
+            }
+        }
+        OutputFormat::Text => {
+            for operation in &listed {
+                println!("{operation}");
+            }
+        }
+        OutputFormat::Github => {
+            for (path, operation) in operation_files.iter().zip(&listed) {
+                println!("{operation}");
+                if let Err(error) = &operation.operation {
+                    println!(
+                        "{}",
+                        github_annotation("error", path, "Invalid operation", error)
+                    );
+                }
+            }
+        }
+    }
+    for line in footer {
+        eprintln!("{line}");
+    }
+    if listed.iter().all(|operation| operation.operation.is_ok()) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+//=================================================================================================
+// `normalize` subcommand: print the canonical form of a plan
+
+/// Print the canonical form of a legacy plan, i.e. what the comparator compares. This helps
+/// producing stable golden files, and debugging the normalization itself.
+#[derive(Debug, clap::Parser)]
+pub struct NormalizeArgs {
+    /// A plan in JSON: captured from the router (with a `queryPlan` field), or a bare plan node.
+    pub plan: PathBuf,
+
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = PlanFormat::Json)]
+    pub format: PlanFormat,
+
+    /// With `--format graphql`, annotate each fetch with its operation kind, the entities it
+    /// resolves (with their keys) and the variables it uses.
+    #[arg(long)]
+    pub explain: bool,
+}
+
+/// Output format of the `normalize` subcommand.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PlanFormat {
+    /// The plan's JSON form, with every detail.
+    #[default]
+    Json,
+    /// A pseudo-GraphQL form, like the planners' formatted query plans.
+    Graphql,
+}
+
+fn read_plan(path: &Path) -> Result<Option<PlanNode>, String> {
+    let json = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+    if let Ok(result) = LegacyQueryPlanResult::from_json(&json) {
+        return Ok(result.root_node().cloned());
+    }
+    serde_json::from_str(&json)
+        .map(Some)
+        .map_err(|err| format!("Invalid plan {}: {err}", path.display()))
+}
+
+fn print_normalized_plan(args: &NormalizeArgs) -> Result<(), String> {
+    if args.explain && args.format != PlanFormat::Graphql {
+        return Err("--explain requires --format graphql".to_string());
+    }
+    let node = read_plan(&args.plan)?.map(|node| normalize(&node));
+    match args.format {
+        PlanFormat::Json => println!("{}", serde_json::to_string_pretty(&node).unwrap()),
+        PlanFormat::Graphql if args.explain => print!("{}", format_plan_explained(node.as_ref())),
+        PlanFormat::Graphql => print!("{}", format_plan(node.as_ref())),
+    }
+    Ok(())
+}
+
+//=================================================================================================
+// `stats` subcommand: characterize the plans of a corpus of operations
+
+/// Plan every operation of a directory with one planner and print aggregate statistics of the
+/// plans: single-fetch plans, defer and subscription usage, node and fetch count histograms and
+/// calls per service. Nothing is compared.
+#[derive(Debug, clap::Parser)]
+pub struct StatsArgs {
+    /// Specify path to schema file(s) to plan operations against
+    #[arg(short, long)]
+    pub schema: PathBuf,
+
+    /// A directory of operations, or an operation file.
+    #[arg(short, long)]
+    pub operation: PathBuf,
+
+    /// File extensions of the operation files read from directories, matched case-insensitively.
+    #[arg(
+        long = "operation-ext",
+        value_name = "EXT",
+        value_delimiter = ',',
+        value_parser = parse_extension,
+        default_value = "graphql,graphqls,gql,sdl"
+    )]
+    pub operation_extensions: Vec<String>,
+
+    /// The planner to plan with, with its default config.
+    #[arg(long, value_enum, default_value_t = SinglePlanner::Native)]
+    pub planner: SinglePlanner,
+
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = StatsFormat::Text)]
+    pub format: StatsFormat,
+}
+
+/// A single planner to plan with: for the `stats` subcommand, and `--planner-only`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SinglePlanner {
+    #[default]
+    Native,
+    Legacy,
+}
+
+/// Output format of the `stats` subcommand.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatsFormat {
+    /// A human-readable report.
+    #[default]
+    Text,
+    /// A JSON object, with histograms as objects keyed by count.
+    Json,
+}
+
+fn print_stats(args: &StatsArgs) -> Result<(), String> {
+    let schema = fs::read_to_string(&args.schema)
+        .map_err(|err| format!("Failed to read {}: {err}", args.schema.display()))?;
+    let mut operation_files = Vec::new();
+    if args.operation.is_dir() {
+        collect_operation_files(
+            &args.operation,
+            &args.operation_extensions,
+            &mut operation_files,
+        )
+        .map_err(|err| format!("Failed to read {}: {err}", args.operation.display()))?;
+        operation_files.sort();
+    } else {
+        operation_files.push(args.operation.clone());
+    }
+    // The native planner is built once and reused for every operation.
+    let pool = NativePlannerPool::new(NonZeroUsize::MIN);
+    let mut stats = CorpusStats::default();
+    for path in &operation_files {
+        let plan = fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|query| plan_for_stats(&schema, &query, path, args.planner, &pool));
+        match plan {
+            Ok(node) => stats.add_plan(node.as_ref()),
+            Err(error) => {
+                tracing::warn!(operation = %path.display(), "Failed to plan: {error}");
+                stats.add_failure();
+            }
+        }
+    }
+    match args.format {
+        StatsFormat::Text => print!("{stats}"),
+        StatsFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&stats.to_json()).unwrap()
+        ),
+    }
+    Ok(())
+}
+
+fn plan_for_stats(
+    schema_str: &str,
+    query_str: &str,
+    path: &Path,
+    planner: SinglePlanner,
+    pool: &NativePlannerPool,
+) -> Result<Option<PlanNode>, String> {
+    let operation_name = select_operation(query_str, None)?;
+    match planner {
+        SinglePlanner::Native => run_pooled_native_planner(
+            pool,
+            schema_str,
+            query_str,
+            operation_name,
+            path,
+            &Default::default(),
+            Default::default(),
+        )
+        .map(|plan| convert_native_plan(&plan))
+        .map_err(|err| err.to_string()),
+        SinglePlanner::Legacy => run_legacy_planner(
+            schema_str,
+            query_str,
+            operation_name.map(|name| name.to_string()),
+            Default::default(),
+            Default::default(),
+        )
+        .map(|plan| plan.root_node().cloned())
+        .map_err(|errors| errors.join("\n")),
+    }
+}
+
+//=================================================================================================
+// `compose-check` subcommand: check that subgraphs compose
+
+/// Compose subgraph schemas into a supergraph without planning anything, and report the
+/// composition errors and hints. Exits with a failure status if composition fails.
+#[derive(Debug, clap::Parser)]
+pub struct ComposeCheckArgs {
+    /// The schema of a subgraph, by name (repeatable).
+    #[arg(
+        required = true,
+        value_name = "NAME=FILE",
+        value_parser = parse_subgraph_schema
+    )]
+    pub subgraphs: Vec<(String, PathBuf)>,
+
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = ComposeCheckFormat::Text)]
+    pub format: ComposeCheckFormat,
+}
+
+/// Output format of the `compose-check` subcommand.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ComposeCheckFormat {
+    /// A line per error and hint, followed by a summary.
+    #[default]
+    Text,
+    /// A JSON object with `success`, `errors` and `hints`.
+    Json,
+}
+
+// Returns whether the subgraphs compose.
+fn run_compose_check(args: &ComposeCheckArgs) -> Result<bool, String> {
+    let subgraphs = args
+        .subgraphs
+        .iter()
+        .map(|(name, path)| {
+            fs::read_to_string(path)
+                .map(|schema| (name.clone(), schema))
+                .map_err(|err| format!("Failed to read {}: {err}", path.display()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let report = check_composition(&subgraphs);
+    match args.format {
+        ComposeCheckFormat::Text => {
+            for error in &report.errors {
+                println!("error: {error}");
+            }
+            for hint in &report.hints {
+                println!("hint: {hint}");
+            }
+            if report.is_success() {
+                println!(
+                    "Composed {} subgraphs ({} hints)",
+                    subgraphs.len(),
+                    report.hints.len()
+                );
+            } else {
+                println!("Composition failed ({} errors)", report.errors.len());
+            }
+        }
+        ComposeCheckFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&report.to_json()).unwrap()
+        ),
+    }
+    Ok(report.is_success())
+}
+
+// Diagnostics are logged to stderr, filtered by `RUST_LOG` (informational events by default).
+// Reports and results are printed directly.
+fn init_logging() {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("qp_compare=info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+fn run_command(command: &Command) -> ExitCode {
+    let result = match command {
+        Command::Normalize(args) => print_normalized_plan(args).map(|()| true),
+        Command::Stats(args) => print_stats(args).map(|()| true),
+        Command::ComposeCheck(args) => run_compose_check(args),
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    init_logging();
+    let mut args = match Cli::parse() {
+        Cli {
+            command: Some(command),
+            ..
+        } => return run_command(&command),
+        Cli {
+            plan: Some(args), ..
+        } => args,
+        Cli {
+            command: None,
+            plan: None,
+        } => {
+            // Clap requires `--schema` without a subcommand, so this is only a safeguard.
+            Cli::command().print_help().ok();
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(error) = args.resolve_options() {
+        eprintln!("{error}");
+        return ExitCode::FAILURE;
+    }
+    if let Some(warning) = args.fragment_settings_warning() {
+        tracing::warn!("{warning}");
+    }
+    if args.report_memory && !qp_compare::memory::is_supported() {
+        tracing::warn!("Memory usage can't be read on this platform, ignoring --report-memory");
+    }
+    if args.schema.is_dir() {
+        if args.operation().is_dir()
+            || args.legacy_b_config.is_some()
+            || args.schema_b.is_some()
+            || args.planner_only.is_some()
+            || args.list
+            || args.dump_dir.is_some()
+        {
+            tracing::error!(
+                "A schema directory only supports a single operation file, without \
+                 --legacy-b-config, --schema-b, --planner-only, --list or --dump-dir"
+            );
+            return ExitCode::FAILURE;
+        }
+        return match run_schema_variants(&args) {
+            Ok(true) => ExitCode::SUCCESS,
+            Ok(false) => ExitCode::FAILURE,
+            Err(error) => {
+                eprintln!("{error}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+    let schema = fs::read_to_string(&args.schema).unwrap();
+    if let Err(error) = check_schema_not_operation(&schema) {
+        tracing::error!("{error}");
+        return ExitCode::FAILURE;
+    }
+    if args.list {
+        return run_list(&args);
+    }
+    if let Some(planner) = args.planner_only {
+        return run_planner_only(&schema, planner, &args);
+    }
+    if args.operation().is_dir() {
+        if args.legacy_b_config.is_some() {
+            tracing::error!("--legacy-b-config is not supported in directory mode");
+            return ExitCode::FAILURE;
+        }
+        if args.schema_b.is_some() {
+            tracing::error!("--schema-b is not supported in directory mode");
+            return ExitCode::FAILURE;
+        }
+        return run_batch(&schema, &args);
+    }
+    if args.dump_dir.is_some() {
+        tracing::error!("--dump-dir is only supported in directory mode");
+        return ExitCode::FAILURE;
+    }
+    let query = fs::read_to_string(args.operation()).unwrap();
+    let operation_file = OperationFile::prepare(args.operation(), &query, &args);
+    let operation = &operation_file.name;
+    let _span =
+        tracing::info_span!("operation", name = %args.operation().display(), %operation).entered();
+    if args.legacy_b_config.is_some() {
+        return match run_legacy_ab(&schema, &query, &args) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("{error}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+    if args.schema_b.is_some() {
+        return match run_schema_drift(&schema, &query, &args) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("{error}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+    let result = operation_file
+        .prepared
+        .and_then(|(query, operation_name)| {
+            let comparator = build_comparator(&schema, &args)?;
+            run_both_planners(&comparator, &query, operation_name, args.operation(), &args)
+        })
+        .and_then(|planned| {
+            if let Ok(rust_plan) = &planned.rust_plan {
+                println!("{rust_plan}");
+            }
+            if let (Some(native), Some(legacy)) = (planned.native_memory, planned.legacy_memory) {
+                eprintln!(
+                    "Peak memory growth: {} native planning, {} legacy planning",
+                    format_mib(native),
+                    format_mib(legacy)
+                );
+            }
+            compare_planned(&planned, &args)
+        });
+    match result {
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+
+        Ok(_) => ExitCode::SUCCESS,
+    }
+}
+
+#[cfg(test)]
+mod operation_files_tests {
+    use super::*;
+
+    #[test]
+    fn test_extensions_match_case_insensitively() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/extensions");
+        let extensions = ["graphql", "graphqls", "gql", "sdl"].map(String::from);
+        let mut files = Vec::new();
+        collect_operation_files(&dir, &extensions, &mut files).unwrap();
+        files.sort();
+        let names: Vec<_> = files
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "Inventory.Sdl",
+                "Products.GQL",
+                "Reviews.GraphQL",
+                "prices.graphqls"
+            ]
+        );
+
+        let mut files = Vec::new();
+        collect_operation_files(&dir, &["GQL".to_string()], &mut files).unwrap();
+        assert_eq!(files, [dir.join("Products.GQL")]);
+    }
+
+    #[test]
+    fn test_parse_extension() {
+        assert_eq!(parse_extension(".gql").unwrap(), "gql");
+        assert_eq!(parse_extension("graphql").unwrap(), "graphql");
+        assert!(parse_extension(".").is_err());
+        assert!(parse_extension("tar.gz").is_err());
+    }
+
+    #[test]
+    fn test_parse_subgraph_schema() {
+        assert_eq!(
+            parse_subgraph_schema("products=schemas/products.graphql").unwrap(),
+            (
+                "products".to_string(),
+                PathBuf::from("schemas/products.graphql")
+            )
+        );
+        assert!(parse_subgraph_schema("products.graphql").is_err());
+        assert!(parse_subgraph_schema("=products.graphql").is_err());
+    }
+}
+
+#[cfg(test)]
+mod dump_dir_tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_dir_mirrors_operations() {
+        let root = std::env::temp_dir().join(format!("qp-compare-dump-{}", std::process::id()));
+        let mut dump_dir = DumpDir::new(root.clone());
+        let artifacts = [
+            ("native.txt", "QueryPlan {\n}\n".to_string()),
+            ("diff.txt", "-a\n+b\n".to_string()),
+        ];
+        dump_dir
+            .dump(
+                Path::new("products/top.graphql"),
+                "TopProducts",
+                "FAIL",
+                &artifacts,
+            )
+            .unwrap();
+        dump_dir.write_index().unwrap();
+        let diff = fs::read_to_string(root.join("products/top.graphql/diff.txt")).unwrap();
+        assert_eq!(diff, "-a\n+b\n");
+        let index: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(root.join("index.json")).unwrap()).unwrap();
+        assert_eq!(
+            index,
+            serde_json::json!({
+                "products/top.graphql": {
+                    "operation": "TopProducts",
+                    "status": "FAIL",
+                    "artifacts": [
+                        "products/top.graphql/native.txt",
+                        "products/top.graphql/diff.txt",
+                    ],
+                },
+            })
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_only_failing_dumps_requires_dumps() {
+        let args = ["qp-compare", "--schema", "s.graphql", "--operation", "ops"];
+        let parse = |extra: &[&str]| PlanArgs::try_parse_from(args.iter().chain(extra));
+        assert!(parse(&["--only-failing-dumps"]).is_err());
+        assert!(parse(&["--only-failing-dumps", "--dump-dir", "dumps"]).is_ok());
+        assert!(parse(&["--only-failing-dumps", "--dump-plans"]).is_ok());
+    }
+
+    #[test]
+    fn test_planner_only_excludes_comparison_modes() {
+        let args = ["qp-compare", "--schema", "s.graphql", "--operation", "ops"];
+        let parse = |extra: &[&str]| PlanArgs::try_parse_from(args.iter().chain(extra));
+        let args = parse(&["--planner-only", "legacy"]).unwrap();
+        assert_eq!(args.planner_only, Some(SinglePlanner::Legacy));
+        assert!(parse(&["--planner-only", "native", "--dump-dir", "dumps"]).is_err());
+        assert!(parse(&["--planner-only", "native", "--list"]).is_err());
+    }
+}
+
+#[cfg(test)]
+mod list_tests {
+    use super::*;
+
+    #[test]
+    fn test_list_operations() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let args = PlanArgs::parse_from([
+            "qp-compare",
+            "--schema",
+            fixtures.join("supergraph.graphql").to_str().unwrap(),
+            "--operation",
+            fixtures.join("extensions").to_str().unwrap(),
+            "--list",
+        ]);
+        let (files, footer) = select_operation_files(&args).unwrap();
+        assert!(footer.is_empty());
+        let listed: Vec<String> = files
+            .iter()
+            .map(|path| list_operation(path, &args).to_string())
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                "Inventory (Inventory.Sdl)",
+                "Products (Products.GQL)",
+                "Reviews (Reviews.GraphQL)",
+                "Prices (prices.graphqls)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_reports_unknown_operation_names() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let operation = fixtures.join("extensions/Products.GQL");
+        let args = PlanArgs::parse_from([
+            "qp-compare",
+            "--schema",
+            fixtures.join("supergraph.graphql").to_str().unwrap(),
+            "--operation",
+            operation.to_str().unwrap(),
+            "--operation-name",
+            "Reviews",
+            "--list",
+        ]);
+        let listed = list_operation(&operation, &args);
+        let json = listed.to_json();
+        assert!(
+            json["error"].as_str().unwrap().contains("`Reviews`"),
+            "{json}"
+        );
+    }
+}
+
+#[cfg(test)]
+mod duplicate_names_tests {
+    use super::*;
+
+    fn display_names(on_duplicate: &str) -> Result<Vec<String>, String> {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let operations = fixtures.join("duplicates");
+        let args = PlanArgs::parse_from([
+            "qp-compare",
+            "--schema",
+            fixtures.join("supergraph.graphql").to_str().unwrap(),
+            "--operation",
+            operations.to_str().unwrap(),
+            "--on-duplicate",
+            on_duplicate,
+        ]);
+        let files = [
+            "anonymous.graphql",
+            "nested/top_names.graphql",
+            "top.graphql",
+        ]
+        .map(|file| operations.join(file));
+        let operations = batch_operation_files(&files, &args)?;
+        Ok(operations
+            .into_iter()
+            .map(|operation| operation.name)
+            .collect())
+    }
+
+    #[test]
+    fn test_duplicate_names_are_disambiguated() {
+        assert_eq!(
+            display_names("disambiguate").unwrap(),
+            [
+                "anonymous",
+                "TopProducts (nested/top_names.graphql)",
+                "TopProducts (top.graphql)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_names_are_reported() {
+        assert_eq!(
+            display_names("error").unwrap_err(),
+            "Several files have operations with the same name (see --on-duplicate):\n\
+             `TopProducts` in nested/top_names.graphql, top.graphql"
+        );
+    }
+}
+
+#[cfg(test)]
+mod normalize_tests {
+    use super::*;
+
+    #[test]
+    fn test_read_plan_shapes() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/plans");
+        let captured = read_plan(&fixtures.join("legacy_result.json")).unwrap();
+        assert!(captured.is_some());
+        let bare = read_plan(&fixtures.join("parallel.json")).unwrap().unwrap();
+        assert!(matches!(bare, PlanNode::Sequence { .. }));
+        assert!(read_plan(&fixtures.join("missing.json")).is_err());
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::*;
+
+    #[test]
+    fn test_subcommands() {
+        let cli = Cli::try_parse_from(["qp-compare", "normalize", "plan.json"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Normalize(_))));
+        assert!(cli.plan.is_none());
+        let cli =
+            Cli::try_parse_from(["qp-compare", "-s", "supergraph.graphql", "-o", "ops"]).unwrap();
+        assert!(cli.command.is_none());
+        assert!(cli.plan.is_some());
+        assert!(Cli::try_parse_from(["qp-compare", "-o", "ops"]).is_err());
+        assert!(Cli::try_parse_from(["qp-compare", "-s", "supergraph.graphql", "stats"]).is_err());
+    }
+
+    #[test]
+    fn test_emit_normalized_requires_json() {
+        let resolve = |format: &str| {
+            let mut args = PlanArgs::parse_from([
+                "qp-compare",
+                "-s",
+                "supergraph.graphql",
+                "-o",
+                "operations",
+                "--emit-normalized",
+                "--format",
+                format,
+            ]);
+            args.resolve_options()
+        };
+        assert!(resolve("json").is_ok());
+        assert_eq!(
+            resolve("csv").unwrap_err(),
+            "--emit-normalized requires --format json"
+        );
+    }
+}
+
+#[cfg(test)]
+mod operation_list_tests {
+    use super::*;
+
+    #[test]
+    fn test_fragment_files_are_not_operations() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let operations = fixtures.join("shared_fragments");
+        // The same file, spelled differently.
+        let fragments = fixtures.join("operations/../shared_fragments/./fragments.graphql");
+        let args = PlanArgs::parse_from([
+            "qp-compare",
+            "--schema",
+            fixtures.join("supergraph.graphql").to_str().unwrap(),
+            "--operation",
+            operations.to_str().unwrap(),
+            "--fragments",
+            fragments.to_str().unwrap(),
+        ]);
+        let (files, _) = select_operation_files(&args).unwrap();
+        assert_eq!(files, [operations.join("top_products.graphql")]);
+    }
+
+    #[test]
+    fn test_operations_from_listfile() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let operations = fixtures.join("operations");
+        let mut args = PlanArgs::parse_from([
+            "qp-compare",
+            "--schema",
+            fixtures.join("supergraph.graphql").to_str().unwrap(),
+            "--operations-from",
+            operations.join("changed.txt").to_str().unwrap(),
+        ]);
+        args.resolve_options().unwrap();
+        assert_eq!(args.operation(), operations.as_path());
+        let (files, footer) = select_operation_files(&args).unwrap();
+        assert_eq!(
+            files,
+            [
+                operations.join("condition.graphql"),
+                operations.join("anonymous.graphql")
+            ]
+        );
+        assert!(footer.is_empty());
+    }
+
+    #[test]
+    fn test_operations_from_conflicts_with_operation() {
+        let parse = |extra: &[&str]| {
+            PlanArgs::try_parse_from(
+                ["qp-compare", "--schema", "supergraph.graphql"]
+                    .iter()
+                    .chain(extra),
+            )
+        };
+        assert!(parse(&["--operations-from", "changed.txt"]).is_ok());
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["--operations-from", "changed.txt", "-o", "operations"]).is_err());
+    }
+}
+
+#[cfg(test)]
+mod shuffle_tests {
+    use super::*;
+
+    #[test]
+    fn test_shuffle_is_reproducible() {
+        let files: Vec<PathBuf> = (0..20)
+            .map(|i| PathBuf::from(format!("{i}.graphql")))
+            .collect();
+        let mut first = files.clone();
+        let mut second = files.clone();
+        shuffle_operation_files(&mut first, 42);
+        shuffle_operation_files(&mut second, 42);
+        assert_eq!(first, second);
+        assert_ne!(first, files);
+        first.sort();
+        let mut sorted = files.clone();
+        sorted.sort();
+        assert_eq!(first, sorted);
+    }
+}
+
+#[cfg(test)]
+mod summary_tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_timing() {
+        let summary = BatchSummary {
+            matched: 2,
+            trivially_divergent: 0,
+            mismatched: 1,
+            ignored: 0,
+            stale_allowlist_entries: Vec::new(),
+            baseline: None,
+            categories: BTreeMap::from([(MismatchCategory::OrderingOnly, 1)]),
+            native_smaller: 0,
+            native_larger: 0,
+            introspection_skipped: 0,
+            introspection_compared: 0,
+            only_native: 0,
+            only_legacy: 0,
+            errors: 0,
+            skipped: 0,
+            skipped_operations: Vec::new(),
+            interrupted: false,
+            wall_time: Duration::from_millis(5000),
+            native_time: Duration::from_millis(500),
+            legacy_time: Duration::from_millis(4000),
+            native_peak_memory: None,
+            legacy_peak_memory: None,
+        };
+        assert_eq!(
+            summary.to_string(),
+            "3 operations: 2 matched, 1 mismatched, 0 planned by native only, 0 planned by \
+             legacy only, 0 failed to plan\n\
+             Mismatches by cause: 1 ordering only\n\
+             Time: 5.00s wall-clock, 0.50s native planning, 4.00s legacy planning \
+             (native 8.0x faster)"
+        );
+        assert_eq!(summary.to_json()["speedup"], 8.0);
+        assert_eq!(summary.to_json()["legacy_ms"], 4000);
+        assert_eq!(summary.to_json()["mismatch_categories"]["ordering_only"], 1);
+    }
+
+    #[test]
+    fn test_trivially_divergent_operations() {
+        let summary = BatchSummary {
+            matched: 3,
+            trivially_divergent: 2,
+            ..Default::default()
+        };
+        let text = summary.to_string();
+        assert!(
+            text.contains("2 matched operations are trivially divergent"),
+            "{text}"
+        );
+        assert_eq!(summary.to_json()["trivially_divergent"], 2);
+    }
+
+    #[test]
+    fn test_allowlisted_mismatches_dont_fail() {
+        let summary = BatchSummary {
+            matched: 2,
+            ignored: 1,
+            stale_allowlist_entries: vec!["me.graphql".to_string()],
+            ..Default::default()
+        };
+        assert!(summary.is_success());
+        assert_eq!(summary.operations(), 3);
+        let text = summary.to_string();
+        assert!(text.contains("1 allowlisted mismatches ignored"), "{text}");
+        assert!(
+            text.contains(
+                "1 allowlisted operations now match, prune them from the allowlist: me.graphql"
+            ),
+            "{text}"
+        );
+        assert_eq!(
+            summary.to_json()["stale_allowlist_entries"][0],
+            "me.graphql"
+        );
+        let failing = BatchSummary {
+            mismatched: 1,
+            ..summary
+        };
+        assert!(!failing.is_success());
+    }
+
+    #[test]
+    fn test_summary_without_planned_operations() {
+        let summary = BatchSummary {
+            errors: 1,
+            ..Default::default()
+        };
+        assert_eq!(summary.speedup(), None);
+        assert!(summary.to_json()["speedup"].is_null());
+    }
+
+    #[test]
+    fn test_summary_of_partial_run() {
+        let summary = BatchSummary {
+            matched: 3,
+            skipped: 7,
+            ..Default::default()
+        };
+        assert!(
+            summary
+                .to_string()
+                .ends_with("Partial run: time budget exceeded, 7 of 10 operations skipped"),
+            "{summary}"
+        );
+        assert_eq!(summary.to_json()["skipped"], 7);
+    }
+
+    #[test]
+    fn test_summary_of_interrupted_run() {
+        let summary = BatchSummary {
+            matched: 1,
+            skipped: 2,
+            skipped_operations: vec!["me.graphql".into(), "top.graphql".into()],
+            interrupted: true,
+            ..Default::default()
+        };
+        assert!(
+            summary
+                .to_string()
+                .ends_with("Partial run: interrupted, 2 of 3 operations skipped"),
+            "{summary}"
+        );
+        assert_eq!(summary.to_json()["interrupted"], true);
+        assert_eq!(
+            summary.to_json()["skipped_operations"],
+            serde_json::json!(["me.graphql", "top.graphql"])
+        );
+    }
+}
+
+#[cfg(test)]
+mod csv_tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_field_quoting() {
+        assert_eq!(csv_field("plain.graphql"), "plain.graphql");
+        assert_eq!(csv_field("a,b.graphql"), "\"a,b.graphql\"");
+        assert_eq!(
+            csv_field("say \"hi\".graphql"),
+            "\"say \"\"hi\"\".graphql\""
+        );
+    }
+
+    #[test]
+    fn test_csv_row_of_error() {
+        let record = OperationRecord {
+            name: "broken.graphql".to_string(),
+            operation: "broken".to_string(),
+            status: "ERROR",
+            planned: None,
+        };
+        assert_eq!(record.csv_row(), "broken.graphql,ERROR,,,,,,,,broken");
+        assert_eq!(CSV_HEADER.split(',').count(), 10);
+    }
+}
+
+#[cfg(test)]
+mod github_tests {
+    use super::*;
+
+    #[test]
+    fn test_github_annotation() {
+        let report = "Query plan mismatch:\nFirst divergence: /Sequence/1/Fetch\nMatchFailure {}";
+        assert_eq!(
+            github_annotation(
+                "error",
+                Path::new("ops/top.graphql"),
+                "FAIL TopProducts",
+                report
+            ),
+            "::error file=ops/top.graphql,title=FAIL TopProducts::Query plan mismatch \
+             (First divergence: /Sequence/1/Fetch)"
+        );
+        assert_eq!(
+            github_annotation("warning", Path::new("a,b.graphql"), "x: y", "50% off\n"),
+            "::warning file=a%2Cb.graphql,title=x%3A y::50%25 off"
+        );
+    }
+
+    #[test]
+    fn test_default_format() {
+        assert_eq!(OutputFormat::default_for(None), OutputFormat::Text);
+        assert_eq!(
+            OutputFormat::default_for(Some("true".as_ref())),
+            OutputFormat::Github
+        );
+    }
+}
+
+#[cfg(test)]
+mod schema_variants_tests {
+    use super::*;
+
+    fn plan(query: &str) -> Result<NativeQueryPlan, String> {
+        run_native_planner(
+            include_str!("../tests/fixtures/supergraph.graphql"),
+            query,
+            None,
+            "operation.graphql",
+            Default::default(),
+            Default::default(),
+        )
+        .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_distinct_plan_numbers() {
+        let simple = include_str!("../tests/fixtures/operations/anonymous.graphql");
+        let entity_join = include_str!("../tests/fixtures/operations/entity_join.graphql");
+        let plans = [
+            plan(simple),
+            Err("schema failed to compose".to_string()),
+            plan(entity_join),
+            plan(simple),
+        ];
+        assert_eq!(
+            distinct_plan_numbers(&plans, &CompareOptions::default()),
+            [Some(1), None, Some(2), Some(1)]
+        );
+    }
+
+    #[test]
+    fn test_schema_variant_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/schema_variants");
+        assert_eq!(
+            schema_variant_files(&dir).unwrap(),
+            [dir.join("baseline.graphql"), dir.join("variant.SDL")]
+        );
+    }
+}
+
+#[cfg(test)]
+mod progress_tests {
+    use super::*;
+
+    #[test]
+    fn test_eta_from_recent_operations() {
+        let mut progress = BatchProgress::new(100, false);
+        assert_eq!(progress.estimate(), None);
+        // A slow start doesn't weigh on the estimate once out of the window.
+        progress.record(Duration::from_secs(60));
+        for _ in 0..PROGRESS_WINDOW {
+            progress.record(Duration::from_millis(500));
+        }
+        assert_eq!(progress.throughput(), Some(2.0));
+        assert_eq!(progress.eta(), Some(Duration::from_millis(24_500)));
+        assert_eq!(progress.estimate().unwrap(), "2.0 ops/s, ETA 25s");
+    }
+
+    #[test]
+    fn test_skipped_operations_leave_throughput_alone() {
+        let mut progress = BatchProgress::new(10, false);
+        progress.record(Duration::from_secs(1));
+        progress.skip();
+        assert_eq!(progress.completed, 2);
+        assert_eq!(progress.throughput(), Some(1.0));
+        assert_eq!(progress.eta(), Some(Duration::from_secs(8)));
+    }
+
+    #[test]
+    fn test_format_eta() {
+        assert_eq!(format_eta(Duration::ZERO), "0s");
+        assert_eq!(format_eta(Duration::from_millis(59_500)), "1m00s");
+        assert_eq!(format_eta(Duration::from_secs(102)), "1m42s");
+        assert_eq!(format_eta(Duration::from_secs(3720)), "1h02m");
     }
 }
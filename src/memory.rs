@@ -0,0 +1,74 @@
+//! Opt-in sampling of the process memory usage while a planner runs.
+//!
+//! Both planners run in this process (the legacy one in an embedded JS runtime), so the resident
+//! set size is sampled from a background thread while a planner call is in progress. Sampling
+//! adds overhead and misses allocations shorter than the sampling interval, so the figures are
+//! estimates, meant to compare the planners on the same operations.
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+const SAMPLING_INTERVAL: Duration = Duration::from_millis(1);
+
+fn resident_set_size() -> Option<usize> {
+    memory_stats::memory_stats().map(|stats| stats.physical_mem)
+}
+
+/// Returns true if memory usage can be sampled on this platform.
+pub fn is_supported() -> bool {
+    resident_set_size().is_some()
+}
+
+/// Runs `f`, returning its result and how far the resident set size rose above its initial value
+/// while `f` ran, in bytes.
+///
+/// Returns `None` for the memory on platforms where the resident set size can't be read.
+pub fn peak_memory_growth<T>(f: impl FnOnce() -> T) -> (T, Option<usize>) {
+    let Some(baseline) = resident_set_size() else {
+        return (f(), None);
+    };
+    let done = Arc::new(AtomicBool::new(false));
+    let sampler = std::thread::spawn({
+        let done = done.clone();
+        move || {
+            let mut peak = baseline;
+            while !done.load(Ordering::Relaxed) {
+                peak = peak.max(resident_set_size().unwrap_or(peak));
+                std::thread::sleep(SAMPLING_INTERVAL);
+            }
+            peak
+        }
+    });
+    let result = f();
+    let after = resident_set_size().unwrap_or(baseline);
+    done.store(true, Ordering::Relaxed);
+    let peak = sampler.join().unwrap_or(baseline).max(after);
+    (result, Some(peak - baseline))
+}
+
+/// Formats a byte count in MiB, for reports.
+pub fn format_mib(bytes: usize) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peak_memory_growth() {
+        let (length, growth) = peak_memory_growth(|| {
+            let buffer = vec![1u8; 64 * 1024 * 1024];
+            std::hint::black_box(&buffer).len()
+        });
+        assert_eq!(length, 64 * 1024 * 1024);
+        assert_eq!(growth.is_some(), is_supported());
+    }
+
+    #[test]
+    fn test_format_mib() {
+        assert_eq!(format_mib(3 * 1024 * 1024 / 2), "1.5 MiB");
+    }
+}
@@ -0,0 +1,431 @@
+//! Size and shape metrics of plan trees, for a quick quantitative comparison of two plans, and
+//! aggregate statistics of the plans of a corpus of operations.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use apollo_compiler::ast;
+
+use crate::LegacyQueryPlanResult;
+use crate::NativeQueryPlan;
+use crate::plan::PlanNode;
+use crate::router::convert_native_plan;
+
+/// Node counts and depth of a plan tree. An empty plan has all metrics at zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlanMetrics {
+    /// Total number of nodes, of any kind.
+    pub nodes: usize,
+    /// Number of nodes on the longest root-to-leaf path.
+    pub depth: usize,
+    pub sequences: usize,
+    pub parallels: usize,
+    pub fetches: usize,
+    pub flattens: usize,
+    /// Fragment definitions in the subgraph operations (of fetches and subscriptions).
+    pub fragment_definitions: usize,
+    /// Fragment spreads in the subgraph operations, which only use fragments they define.
+    pub fragment_spreads: usize,
+}
+
+impl PlanMetrics {
+    pub fn of_node(node: Option<&PlanNode>) -> Self {
+        let mut metrics = Self::default();
+        if let Some(node) = node {
+            metrics.visit(node, 1);
+        }
+        metrics
+    }
+
+    pub fn of_legacy_plan(js_plan: &LegacyQueryPlanResult) -> Self {
+        Self::of_node(js_plan.root_node())
+    }
+
+    pub fn of_native_plan(rust_plan: &NativeQueryPlan) -> Self {
+        Self::of_node(convert_native_plan(rust_plan).as_ref())
+    }
+
+    fn visit(&mut self, node: &PlanNode, depth: usize) {
+        self.nodes += 1;
+        self.depth = self.depth.max(depth);
+        match node {
+            PlanNode::Sequence { .. } => self.sequences += 1,
+            PlanNode::Parallel { .. } => self.parallels += 1,
+            PlanNode::Fetch(fetch) => {
+                self.fetches += 1;
+                self.count_fragments(fetch.operation.as_serialized());
+            }
+            PlanNode::Flatten(_) => self.flattens += 1,
+            PlanNode::Subscription { primary, rest: _ } => {
+                self.count_fragments(primary.operation.as_serialized());
+            }
+            PlanNode::Defer { .. } | PlanNode::Condition { .. } => {}
+        }
+        for child in node.children() {
+            self.visit(child, depth + 1);
+        }
+    }
+
+    // Counts the fragments of a subgraph operation. Unparsable operations are left out.
+    fn count_fragments(&mut self, operation: &str) {
+        let Ok(document) = ast::Document::parse(operation, "operation.graphql") else {
+            return;
+        };
+        for definition in &document.definitions {
+            let selection_set = match definition {
+                ast::Definition::OperationDefinition(operation) => &operation.selection_set,
+                ast::Definition::FragmentDefinition(fragment) => {
+                    self.fragment_definitions += 1;
+                    &fragment.selection_set
+                }
+                _ => continue,
+            };
+            self.fragment_spreads += fragment_spreads(selection_set);
+        }
+    }
+
+    fn rows(&self) -> [(&'static str, usize); 8] {
+        [
+            ("nodes", self.nodes),
+            ("depth", self.depth),
+            ("sequences", self.sequences),
+            ("parallels", self.parallels),
+            ("fetches", self.fetches),
+            ("flattens", self.flattens),
+            ("fragment definitions", self.fragment_definitions),
+            ("fragment spreads", self.fragment_spreads),
+        ]
+    }
+}
+
+fn fragment_spreads(selection_set: &[ast::Selection]) -> usize {
+    selection_set
+        .iter()
+        .map(|selection| match selection {
+            ast::Selection::Field(field) => fragment_spreads(&field.selection_set),
+            ast::Selection::FragmentSpread(_) => 1,
+            ast::Selection::InlineFragment(inline) => fragment_spreads(&inline.selection_set),
+        })
+        .sum()
+}
+
+/// Lists the subgraph operations (of fetches and subscriptions) longer than `max_bytes`, naming the
+/// service and the size of each, in plan order.
+pub fn oversized_operations(node: Option<&PlanNode>, max_bytes: usize) -> Vec<String> {
+    let mut oversized = Vec::new();
+    let Some(node) = node else {
+        return oversized;
+    };
+    for_each_node(node, &mut |node| {
+        let (service_name, operation) = match node {
+            PlanNode::Fetch(fetch) => (&fetch.service_name, &fetch.operation),
+            PlanNode::Subscription { primary, .. } => (&primary.service_name, &primary.operation),
+            _ => return,
+        };
+        let bytes = operation.as_serialized().len();
+        if bytes > max_bytes {
+            oversized.push(format!(
+                "service `{service_name}`: {bytes} bytes (over {max_bytes})"
+            ));
+        }
+    });
+    oversized
+}
+
+/// Side-by-side metrics of the legacy and native plans, highlighting the differing ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsComparison {
+    pub legacy: PlanMetrics,
+    pub native: PlanMetrics,
+}
+
+impl MetricsComparison {
+    pub fn new(js_plan: &LegacyQueryPlanResult, rust_plan: &NativeQueryPlan) -> Self {
+        Self {
+            legacy: PlanMetrics::of_legacy_plan(js_plan),
+            native: PlanMetrics::of_native_plan(rust_plan),
+        }
+    }
+
+    /// How many more nodes the native plan has than the legacy one (negative if fewer).
+    pub fn node_delta(&self) -> i64 {
+        self.native.nodes as i64 - self.legacy.nodes as i64
+    }
+
+    /// How many more fetches the native plan has than the legacy one (negative if fewer).
+    pub fn fetch_delta(&self) -> i64 {
+        self.native.fetches as i64 - self.legacy.fetches as i64
+    }
+
+    /// The size of the native plan relative to the legacy one: by fetch count (subgraph calls),
+    /// then by node count.
+    pub fn native_size(&self) -> Ordering {
+        (self.native.fetches, self.native.nodes).cmp(&(self.legacy.fetches, self.legacy.nodes))
+    }
+}
+
+impl fmt::Display for MetricsComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Plan metrics (legacy / native):")?;
+        for ((name, legacy), (_, native)) in self.legacy.rows().into_iter().zip(self.native.rows())
+        {
+            write!(f, "  {name}: {legacy} / {native}")?;
+            if native < legacy {
+                write!(f, "  <- native has {} fewer", legacy - native)?;
+            } else if native > legacy {
+                write!(f, "  <- native has {} more", native - legacy)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Aggregate statistics of the plans of a corpus of operations, planned by one planner.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorpusStats {
+    pub planned: usize,
+    /// Operations the planner failed to plan.
+    pub failed: usize,
+    /// Plans made of a single fetch.
+    pub single_fetch: usize,
+    /// Plans with a `Defer` node.
+    pub deferred: usize,
+    pub subscriptions: usize,
+    /// Number of plans by node count.
+    pub nodes: BTreeMap<usize, usize>,
+    /// Number of plans by fetch count.
+    pub fetches: BTreeMap<usize, usize>,
+    /// Number of fetches (and subscription primaries) by service, over all plans.
+    pub service_calls: BTreeMap<String, usize>,
+}
+
+impl CorpusStats {
+    pub fn add_plan(&mut self, node: Option<&PlanNode>) {
+        let metrics = PlanMetrics::of_node(node);
+        self.planned += 1;
+        if metrics.nodes == 1 && metrics.fetches == 1 {
+            self.single_fetch += 1;
+        }
+        *self.nodes.entry(metrics.nodes).or_default() += 1;
+        *self.fetches.entry(metrics.fetches).or_default() += 1;
+        let (mut deferred, mut subscription) = (false, false);
+        if let Some(node) = node {
+            for_each_node(node, &mut |node| match node {
+                PlanNode::Fetch(fetch) => {
+                    *self
+                        .service_calls
+                        .entry(fetch.service_name.to_string())
+                        .or_default() += 1;
+                }
+                PlanNode::Subscription { primary, rest: _ } => {
+                    subscription = true;
+                    *self
+                        .service_calls
+                        .entry(primary.service_name.to_string())
+                        .or_default() += 1;
+                }
+                PlanNode::Defer { .. } => deferred = true,
+                PlanNode::Sequence { .. }
+                | PlanNode::Parallel { .. }
+                | PlanNode::Flatten(_)
+                | PlanNode::Condition { .. } => {}
+            });
+        }
+        self.deferred += usize::from(deferred);
+        self.subscriptions += usize::from(subscription);
+    }
+
+    pub fn add_failure(&mut self) {
+        self.failed += 1;
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "planned": self.planned,
+            "failed": self.failed,
+            "single_fetch": self.single_fetch,
+            "deferred": self.deferred,
+            "subscriptions": self.subscriptions,
+            "nodes": self.nodes,
+            "fetches": self.fetches,
+            "service_calls": self.service_calls,
+        })
+    }
+}
+
+// Visits the nodes of a plan tree in pre-order. Iterative, so that deep plans don't overflow the
+// stack.
+pub(crate) fn for_each_node(node: &PlanNode, f: &mut impl FnMut(&PlanNode)) {
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        f(node);
+        stack.extend(node.children().into_iter().rev());
+    }
+}
+
+impl fmt::Display for CorpusStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} operations planned, {} failed to plan",
+            self.planned, self.failed
+        )?;
+        let share = |count: usize| 100.0 * count as f64 / self.planned.max(1) as f64;
+        for (label, count) in [
+            ("Single-fetch plans", self.single_fetch),
+            ("Deferred plans", self.deferred),
+            ("Subscriptions", self.subscriptions),
+        ] {
+            writeln!(f, "{label}: {count} ({:.1}%)", share(count))?;
+        }
+        for (title, histogram) in [
+            ("Plans by node count", &self.nodes),
+            ("Plans by fetch count", &self.fetches),
+        ] {
+            writeln!(f, "{title}:")?;
+            for (value, count) in histogram {
+                writeln!(f, "  {value:>4}: {count} ({:.1}%)", share(*count))?;
+            }
+        }
+        // Most called first.
+        let mut service_calls: Vec<_> = self.service_calls.iter().collect();
+        service_calls.sort_by_key(|(_, calls)| std::cmp::Reverse(**calls));
+        writeln!(f, "Calls by service:")?;
+        for (service, calls) in service_calls {
+            writeln!(f, "  {service}: {calls}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::testing::parallel_plan;
+
+    #[test]
+    fn test_fixture_metrics() {
+        assert_eq!(
+            PlanMetrics::of_node(Some(&parallel_plan())),
+            PlanMetrics {
+                nodes: 8,
+                depth: 5,
+                sequences: 1,
+                parallels: 1,
+                fetches: 3,
+                flattens: 2,
+                fragment_definitions: 0,
+                fragment_spreads: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_fragment_metrics() {
+        let fetch: PlanNode = serde_json::from_value(serde_json::json!({
+            "kind": "Fetch",
+            "serviceName": "products",
+            "variableUsages": [],
+            "operation": "query TopProducts__products__0 { topProducts { ...Product \
+                          reviews { ... on Review { ...Review } } } } \
+                          fragment Product on Product { upc } \
+                          fragment Review on Review { body author { ...User } } \
+                          fragment User on User { id }",
+            "operationName": "TopProducts__products__0",
+            "operationKind": "query"
+        }))
+        .unwrap();
+        let metrics = PlanMetrics::of_node(Some(&fetch));
+        assert_eq!(metrics.fragment_definitions, 3);
+        assert_eq!(metrics.fragment_spreads, 3);
+    }
+
+    #[test]
+    fn test_oversized_operations() {
+        let plan = parallel_plan();
+        let mut sizes = Vec::new();
+        for_each_node(&plan, &mut |node| {
+            if let PlanNode::Fetch(fetch) = node {
+                sizes.push(fetch.operation.as_serialized().len());
+            }
+        });
+        let largest = sizes.iter().copied().max().unwrap();
+        assert!(oversized_operations(Some(&plan), largest).is_empty());
+        assert_eq!(
+            oversized_operations(Some(&plan), largest - 1).len(),
+            sizes.iter().filter(|&&size| size == largest).count()
+        );
+        assert_eq!(oversized_operations(Some(&plan), 0).len(), 3);
+        assert!(oversized_operations(None, 0).is_empty());
+    }
+
+    #[test]
+    fn test_empty_plan_metrics() {
+        assert_eq!(PlanMetrics::of_node(None), PlanMetrics::default());
+    }
+
+    #[test]
+    fn test_comparison_highlights_differences() {
+        let legacy = PlanMetrics::of_node(Some(&parallel_plan()));
+        let native = PlanMetrics { depth: 3, ..legacy };
+        let rendered = MetricsComparison { legacy, native }.to_string();
+        assert!(rendered.contains("  depth: 5 / 3  <- native has 2 fewer\n"));
+        assert!(rendered.contains("  fetches: 3 / 3\n"));
+    }
+
+    #[test]
+    fn test_size_deltas() {
+        let legacy = PlanMetrics::of_node(Some(&parallel_plan()));
+        let comparison = MetricsComparison {
+            legacy,
+            native: PlanMetrics {
+                nodes: 10,
+                ..legacy
+            },
+        };
+        assert_eq!(comparison.node_delta(), 2);
+        assert_eq!(comparison.fetch_delta(), 0);
+        assert_eq!(comparison.native_size(), Ordering::Greater);
+        // Fetches weigh more than nodes.
+        let comparison = MetricsComparison {
+            legacy,
+            native: PlanMetrics {
+                nodes: 10,
+                fetches: 2,
+                ..legacy
+            },
+        };
+        assert_eq!(comparison.fetch_delta(), -1);
+        assert_eq!(comparison.native_size(), Ordering::Less);
+    }
+
+    #[test]
+    fn test_corpus_stats() {
+        let single_fetch: PlanNode = serde_json::from_value(serde_json::json!({
+            "kind": "Fetch",
+            "serviceName": "products",
+            "variableUsages": [],
+            "operation": "{ topProducts { upc } }",
+            "operationKind": "query"
+        }))
+        .unwrap();
+        let mut stats = CorpusStats::default();
+        stats.add_plan(Some(&parallel_plan()));
+        stats.add_plan(Some(&single_fetch));
+        stats.add_failure();
+        assert_eq!(stats.planned, 2);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.single_fetch, 1);
+        assert_eq!(stats.deferred, 0);
+        assert_eq!(stats.fetches, BTreeMap::from([(1, 1), (3, 1)]));
+        assert_eq!(stats.service_calls["products"], 2);
+        assert_eq!(stats.service_calls.len(), 3);
+        let rendered = stats.to_string();
+        assert!(rendered.starts_with("2 operations planned, 1 failed to plan\n"));
+        assert!(rendered.contains("Single-fetch plans: 1 (50.0%)\n"));
+        assert!(rendered.contains("Calls by service:\n  products: 2\n"));
+        assert_eq!(stats.to_json()["fetches"]["3"], 1);
+    }
+}
@@ -0,0 +1,953 @@
+//! Inspecting operation documents before handing them to the planners.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+
+use apollo_compiler::Name;
+use apollo_compiler::Node;
+use apollo_compiler::ast;
+use serde_json::Map;
+use serde_json::Value;
+
+fn parse_document(query_str: &str) -> ast::Document {
+    // Syntax errors are left for the planners to report.
+    ast::Document::parse(query_str, "operation.graphql")
+        .unwrap_or_else(|with_errors| with_errors.partial)
+}
+
+fn parse_operations(query_str: &str) -> Vec<Node<ast::OperationDefinition>> {
+    parse_document(query_str)
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => Some(operation.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Resolves which operation of a document to plan, symmetrically for both planners.
+///
+/// Returns the operation name to pass to the planners. With a single operation in the document
+/// (named or anonymous), no name is needed and `None` is returned.
+pub fn select_operation(
+    query_str: &str,
+    operation_name: Option<&str>,
+) -> Result<Option<Name>, String> {
+    let operations = parse_operations(query_str);
+    if let Some(operation_name) = operation_name {
+        return operations
+            .iter()
+            .find_map(|operation| {
+                operation
+                    .name
+                    .as_ref()
+                    .filter(|name| name.as_str() == operation_name)
+            })
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| {
+                format!(
+                    "The document has no operation named `{operation_name}`. Its operations \
+                     are:\n{}",
+                    list_operations(&operations)
+                )
+            });
+    }
+    match operations.len() {
+        0 => Err("The document has no operations".to_string()),
+        1 => Ok(None),
+        count => Err(format!(
+            "The document has {count} operations: use --operation-name (or --operation-index, \
+             for anonymous operations) to select one of:\n{}",
+            list_operations(&operations)
+        )),
+    }
+}
+
+/// Returns the names of the document's operations, in declaration order (`None` for anonymous
+/// operations).
+pub fn operation_names(query_str: &str) -> Vec<Option<Name>> {
+    parse_operations(query_str)
+        .into_iter()
+        .map(|operation| operation.name.clone())
+        .collect()
+}
+
+/// The name reports use for the operation of a file: the name of its operation, or the file stem
+/// if the operation is anonymous, or if the document has several operations (or none).
+///
+/// Pass the document after selecting an operation (e.g. with `extract_operation`) to name the
+/// selected operation.
+pub fn operation_display_name(file: &Path, query_str: &str) -> String {
+    match operation_names(query_str).as_slice() {
+        [Some(name)] => name.to_string(),
+        _ => file.file_stem().map_or_else(
+            || file.display().to_string(),
+            |stem| stem.to_string_lossy().into_owned(),
+        ),
+    }
+}
+
+/// Whether the selected operation of a document (the named one, or its only one) is an
+/// introspection query: a query selecting `__schema` or `__type` at its root, and otherwise only
+/// `__typename`. Federation answers these without fetching anything, so they plan to empty plans.
+pub fn is_introspection_only(query_str: &str, operation_name: Option<&str>) -> bool {
+    let document = parse_document(query_str);
+    let fragments = document_fragments(&document);
+    let mut operations = document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => Some(operation),
+            _ => None,
+        });
+    let operation = match operation_name {
+        Some(operation_name) => operations.find(|operation| {
+            operation
+                .name
+                .as_ref()
+                .is_some_and(|name| name.as_str() == operation_name)
+        }),
+        None => match (operations.next(), operations.next()) {
+            (Some(operation), None) => Some(operation),
+            _ => None,
+        },
+    };
+    let Some(operation) =
+        operation.filter(|operation| operation.operation_type == ast::OperationType::Query)
+    else {
+        return false;
+    };
+    let fields = selected_fields(&operation.selection_set, &fragments);
+    let is_introspection =
+        |field: &&Node<ast::Field>| matches!(field.name.as_str(), "__schema" | "__type");
+    fields.iter().any(is_introspection)
+        && fields
+            .iter()
+            .all(|field| is_introspection(field) || field.name.as_str() == "__typename")
+}
+
+// One line per operation, with the index `--operation-index` expects.
+fn list_operations(operations: &[Node<ast::OperationDefinition>]) -> String {
+    operations
+        .iter()
+        .enumerate()
+        .map(|(index, operation)| {
+            let name = operation
+                .name
+                .as_ref()
+                .map_or("<anonymous>", |name| name.as_str());
+            format!("  {index}: {name}")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Extracts the operation at `index` (in declaration order) into a standalone document, together
+/// with the fragment definitions it uses.
+///
+/// The extracted document has a single operation, so it can be planned without a name. This makes
+/// anonymous and duplicate-named operations of multi-operation documents plannable.
+pub fn extract_operation(query_str: &str, index: usize) -> Result<String, String> {
+    let document = parse_document(query_str);
+    let mut operations = Vec::new();
+    let mut fragments = HashMap::new();
+    for definition in &document.definitions {
+        match definition {
+            ast::Definition::OperationDefinition(operation) => operations.push(operation),
+            ast::Definition::FragmentDefinition(fragment) => {
+                fragments.insert(&fragment.name, fragment);
+            }
+            _ => {}
+        }
+    }
+    let Some(operation) = operations.get(index) else {
+        return Err(format!(
+            "Operation index {index} is out of range: the document has {} operations",
+            operations.len()
+        ));
+    };
+
+    let mut pending = Vec::new();
+    collect_fragment_spreads(&operation.selection_set, &mut pending);
+    let used_fragments = used_fragments(pending, &fragments);
+
+    let mut extracted = ast::Document::new();
+    extracted
+        .definitions
+        .push(ast::Definition::OperationDefinition((*operation).clone()));
+    for definition in &document.definitions {
+        if let ast::Definition::FragmentDefinition(fragment) = definition {
+            if used_fragments.contains(&fragment.name) {
+                extracted.definitions.push(definition.clone());
+            }
+        }
+    }
+    Ok(extracted.to_string())
+}
+
+/// Appends the fragment definitions of `fragments_str` used by `query_str` (directly or
+/// transitively) to it, the way clients assemble documents from shared fragment files.
+///
+/// Fragments defined in `query_str` take precedence over shared ones of the same name. Unused
+/// shared fragments are left out, since planners reject documents with unused fragments.
+pub fn merge_fragments(query_str: &str, fragments_str: &str) -> Result<String, String> {
+    let shared = ast::Document::parse(fragments_str, "fragments.graphql")
+        .map_err(|err| format!("Invalid shared fragments: {}", err.errors))?;
+    let mut shared_fragments = HashMap::new();
+    for definition in &shared.definitions {
+        match definition {
+            ast::Definition::FragmentDefinition(fragment) => {
+                shared_fragments.insert(&fragment.name, fragment);
+            }
+            _ => return Err("Shared fragment files may only define fragments".to_string()),
+        }
+    }
+
+    let document = parse_document(query_str);
+    let mut pending = Vec::new();
+    for definition in &document.definitions {
+        match definition {
+            ast::Definition::OperationDefinition(operation) => {
+                collect_fragment_spreads(&operation.selection_set, &mut pending);
+            }
+            ast::Definition::FragmentDefinition(fragment) => {
+                shared_fragments.remove(&fragment.name);
+                collect_fragment_spreads(&fragment.selection_set, &mut pending);
+            }
+            _ => {}
+        }
+    }
+    let used_fragments = used_fragments(pending, &shared_fragments);
+
+    let mut merged = query_str.to_string();
+    for definition in &shared.definitions {
+        if let ast::Definition::FragmentDefinition(fragment) = definition {
+            if used_fragments.contains(&fragment.name)
+                && shared_fragments.contains_key(&fragment.name)
+            {
+                merged.push_str(&format!("\n\n{definition}"));
+            }
+        }
+    }
+    Ok(merged)
+}
+
+// Resolves the fragments used by the `spreads`, following spreads within `fragments`.
+fn used_fragments<'a>(
+    mut spreads: Vec<&'a Name>,
+    fragments: &HashMap<&Name, &'a Node<ast::FragmentDefinition>>,
+) -> HashSet<&'a Name> {
+    let mut used_fragments = HashSet::new();
+    while let Some(name) = spreads.pop() {
+        if used_fragments.insert(name) {
+            if let Some(&fragment) = fragments.get(name) {
+                collect_fragment_spreads(&fragment.selection_set, &mut spreads);
+            }
+        }
+    }
+    used_fragments
+}
+
+fn collect_fragment_spreads<'a>(selections: &'a [ast::Selection], spreads: &mut Vec<&'a Name>) {
+    for selection in selections {
+        match selection {
+            ast::Selection::Field(field) => {
+                collect_fragment_spreads(&field.selection_set, spreads);
+            }
+            ast::Selection::FragmentSpread(spread) => spreads.push(&spread.fragment_name),
+            ast::Selection::InlineFragment(inline) => {
+                collect_fragment_spreads(&inline.selection_set, spreads);
+            }
+        }
+    }
+}
+
+//==================================================================================================
+// Applying `@skip`/`@include` conditions
+
+/// Evaluates the `@skip`/`@include` directives whose condition is a literal or a variable pinned
+/// by `variables` (to a boolean), removing the skipped selections and the evaluated directives.
+///
+/// Directives depending on other variables are left intact. Fragments and variable definitions
+/// left unused by the removal are removed as well, since planners reject unused ones. Returns an
+/// error if a selection set would be left empty.
+pub fn apply_conditions(query_str: &str, variables: &Map<String, Value>) -> Result<String, String> {
+    let Ok(mut document) = ast::Document::parse(query_str, "operation.graphql") else {
+        // Syntax errors are left for the planners to report.
+        return Ok(query_str.to_string());
+    };
+    let mut changed = false;
+    for definition in &mut document.definitions {
+        let (selection_set, description) = match definition {
+            ast::Definition::OperationDefinition(operation) => {
+                let operation = operation.make_mut();
+                let description = operation.name.as_ref().map_or_else(
+                    || "the anonymous operation".to_string(),
+                    |name| format!("`{name}`"),
+                );
+                (&mut operation.selection_set, description)
+            }
+            ast::Definition::FragmentDefinition(fragment) => {
+                let fragment = fragment.make_mut();
+                let description = format!("fragment `{}`", fragment.name);
+                (&mut fragment.selection_set, description)
+            }
+            _ => continue,
+        };
+        apply_selection_set_conditions(selection_set, variables, &mut changed)?;
+        if selection_set.is_empty() {
+            return Err(format!(
+                "Applying the conditions leaves {description} empty"
+            ));
+        }
+    }
+    if !changed {
+        return Ok(query_str.to_string());
+    }
+    remove_unused_definitions(&mut document, variables);
+    Ok(document.to_string())
+}
+
+fn apply_selection_set_conditions(
+    selections: &mut Vec<ast::Selection>,
+    variables: &Map<String, Value>,
+    changed: &mut bool,
+) -> Result<(), String> {
+    let mut kept = Vec::with_capacity(selections.len());
+    for mut selection in std::mem::take(selections) {
+        let included = match &mut selection {
+            ast::Selection::Field(field) => {
+                let field = field.make_mut();
+                let included =
+                    apply_directive_conditions(&mut field.directives, variables, changed);
+                if included && !field.selection_set.is_empty() {
+                    apply_selection_set_conditions(&mut field.selection_set, variables, changed)?;
+                    if field.selection_set.is_empty() {
+                        return Err(format!(
+                            "Applying the conditions leaves the selections of `{}` empty",
+                            field.name
+                        ));
+                    }
+                }
+                included
+            }
+            ast::Selection::FragmentSpread(spread) => {
+                apply_directive_conditions(&mut spread.make_mut().directives, variables, changed)
+            }
+            ast::Selection::InlineFragment(inline) => {
+                let inline = inline.make_mut();
+                let included =
+                    apply_directive_conditions(&mut inline.directives, variables, changed);
+                if included {
+                    apply_selection_set_conditions(&mut inline.selection_set, variables, changed)?;
+                    if inline.selection_set.is_empty() {
+                        return Err(
+                            "Applying the conditions leaves an inline fragment empty".to_string()
+                        );
+                    }
+                }
+                included
+            }
+        };
+        if included {
+            kept.push(selection);
+        }
+    }
+    *selections = kept;
+    Ok(())
+}
+
+// Removes the directives with a pinned condition, returning false if they exclude the selection.
+fn apply_directive_conditions(
+    directives: &mut ast::DirectiveList,
+    variables: &Map<String, Value>,
+    changed: &mut bool,
+) -> bool {
+    let mut included = true;
+    directives.retain(|directive| {
+        let Some(condition) = pinned_condition(directive, variables) else {
+            return true;
+        };
+        included &= condition;
+        *changed = true;
+        false
+    });
+    included
+}
+
+// Returns whether a pinned `@skip`/`@include` directive includes its selection.
+fn pinned_condition(directive: &ast::Directive, variables: &Map<String, Value>) -> Option<bool> {
+    let condition = match &**directive.specified_argument_by_name("if")? {
+        ast::Value::Boolean(value) => *value,
+        ast::Value::Variable(name) => variables.get(name.as_str())?.as_bool()?,
+        _ => return None,
+    };
+    match directive.name.as_str() {
+        "include" => Some(condition),
+        "skip" => Some(!condition),
+        _ => None,
+    }
+}
+
+// Removes the fragments no operation uses anymore, and the definitions of pinned variables an
+// operation no longer uses.
+fn remove_unused_definitions(document: &mut ast::Document, variables: &Map<String, Value>) {
+    let mut fragments = HashMap::new();
+    let mut spreads = Vec::new();
+    for definition in &document.definitions {
+        match definition {
+            ast::Definition::OperationDefinition(operation) => {
+                collect_fragment_spreads(&operation.selection_set, &mut spreads);
+            }
+            ast::Definition::FragmentDefinition(fragment) => {
+                fragments.insert(&fragment.name, fragment);
+            }
+            _ => {}
+        }
+    }
+    let used_fragments: HashSet<Name> = used_fragments(spreads, &fragments)
+        .into_iter()
+        .cloned()
+        .collect();
+    let mut unused_variables = Vec::new();
+    for definition in &document.definitions {
+        let ast::Definition::OperationDefinition(operation) = definition else {
+            continue;
+        };
+        let mut usages = HashSet::new();
+        collect_variable_usages(
+            &operation.selection_set,
+            &fragments,
+            &mut HashSet::new(),
+            &mut usages,
+        );
+        unused_variables.push(
+            operation
+                .variables
+                .iter()
+                .map(|variable| variable.name.clone())
+                .filter(|name| variables.contains_key(name.as_str()) && !usages.contains(name))
+                .collect::<HashSet<_>>(),
+        );
+    }
+
+    document.definitions.retain(|definition| match definition {
+        ast::Definition::FragmentDefinition(fragment) => used_fragments.contains(&fragment.name),
+        _ => true,
+    });
+    let operations = document
+        .definitions
+        .iter_mut()
+        .filter_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => Some(operation),
+            _ => None,
+        });
+    for (operation, unused_variables) in operations.zip(unused_variables) {
+        if !unused_variables.is_empty() {
+            operation
+                .make_mut()
+                .variables
+                .retain(|variable| !unused_variables.contains(&variable.name));
+        }
+    }
+}
+
+fn collect_variable_usages<'a>(
+    selections: &'a [ast::Selection],
+    fragments: &HashMap<&Name, &'a Node<ast::FragmentDefinition>>,
+    visited_fragments: &mut HashSet<&'a Name>,
+    usages: &mut HashSet<&'a Name>,
+) {
+    for selection in selections {
+        match selection {
+            ast::Selection::Field(field) => {
+                collect_directive_variables(&field.directives, usages);
+                for argument in &field.arguments {
+                    collect_value_variables(&argument.value, usages);
+                }
+                collect_variable_usages(&field.selection_set, fragments, visited_fragments, usages);
+            }
+            ast::Selection::FragmentSpread(spread) => {
+                collect_directive_variables(&spread.directives, usages);
+                if visited_fragments.insert(&spread.fragment_name) {
+                    if let Some(&fragment) = fragments.get(&spread.fragment_name) {
+                        collect_directive_variables(&fragment.directives, usages);
+                        collect_variable_usages(
+                            &fragment.selection_set,
+                            fragments,
+                            visited_fragments,
+                            usages,
+                        );
+                    }
+                }
+            }
+            ast::Selection::InlineFragment(inline) => {
+                collect_directive_variables(&inline.directives, usages);
+                collect_variable_usages(
+                    &inline.selection_set,
+                    fragments,
+                    visited_fragments,
+                    usages,
+                );
+            }
+        }
+    }
+}
+
+fn collect_directive_variables<'a>(
+    directives: &'a ast::DirectiveList,
+    usages: &mut HashSet<&'a Name>,
+) {
+    for directive in directives.iter() {
+        for argument in &directive.arguments {
+            collect_value_variables(&argument.value, usages);
+        }
+    }
+}
+
+fn collect_value_variables<'a>(value: &'a ast::Value, usages: &mut HashSet<&'a Name>) {
+    match value {
+        ast::Value::Variable(name) => {
+            usages.insert(name);
+        }
+        ast::Value::List(items) => {
+            for item in items {
+                collect_value_variables(item, usages);
+            }
+        }
+        ast::Value::Object(fields) => {
+            for (_, value) in fields {
+                collect_value_variables(value, usages);
+            }
+        }
+        _ => {}
+    }
+}
+
+//==================================================================================================
+// Source snippets
+
+/// Returns the numbered lines of `query_str` selecting the data of a subgraph operation, or `None`
+/// if they can't be located.
+///
+/// `operation_name` selects the operation of documents with several, and `response_path` is where
+/// the subgraph operation's data is merged in the response (the keys of the enclosing `Flatten`
+/// nodes). The snippet spans the fields at that path that the subgraph operation selects (at its
+/// root, or under `_entities`), or the field at the path itself if none of them is selected there.
+/// This is best-effort: fields are matched by response name only.
+pub fn source_snippet(
+    query_str: &str,
+    operation_name: Option<&str>,
+    response_path: &[String],
+    subgraph_operation: &str,
+) -> Option<String> {
+    let document = ast::Document::parse(query_str, "operation.graphql").ok()?;
+    let fragments = document_fragments(&document);
+    let operation = document
+        .definitions
+        .iter()
+        .find_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation)
+                if operation_name.is_none_or(|operation_name| {
+                    operation
+                        .name
+                        .as_ref()
+                        .is_some_and(|name| name.as_str() == operation_name)
+                }) =>
+            {
+                Some(operation)
+            }
+            _ => None,
+        })?;
+    let mut selections = &operation.selection_set;
+    let mut enclosing_field = None;
+    for key in response_path {
+        let field = selected_fields(selections, &fragments)
+            .into_iter()
+            .find(|field| field.response_name().as_str() == key)?;
+        selections = &field.selection_set;
+        enclosing_field = Some(field);
+    }
+    let fetched = fetched_response_names(subgraph_operation)?;
+    let mut spans: Vec<_> = selected_fields(selections, &fragments)
+        .into_iter()
+        .filter(|field| fetched.contains(field.response_name()))
+        .filter_map(|field| field.location())
+        .collect();
+    if spans.is_empty() {
+        spans.extend(enclosing_field?.location());
+    }
+    let start = spans.iter().map(|span| span.offset()).min()?;
+    let end = spans.iter().map(|span| span.end_offset()).max()?;
+    Some(numbered_lines(query_str, start, end))
+}
+
+fn document_fragments(document: &ast::Document) -> HashMap<&Name, &Node<ast::FragmentDefinition>> {
+    document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            ast::Definition::FragmentDefinition(fragment) => Some((&fragment.name, fragment)),
+            _ => None,
+        })
+        .collect()
+}
+
+// The fields of a selection set, including those of its inline fragments and fragment spreads.
+fn selected_fields<'a>(
+    selections: &'a [ast::Selection],
+    fragments: &HashMap<&Name, &'a Node<ast::FragmentDefinition>>,
+) -> Vec<&'a Node<ast::Field>> {
+    let mut fields = Vec::new();
+    collect_selected_fields(selections, fragments, &mut Vec::new(), &mut fields);
+    fields
+}
+
+fn collect_selected_fields<'a>(
+    selections: &'a [ast::Selection],
+    fragments: &HashMap<&Name, &'a Node<ast::FragmentDefinition>>,
+    spreads: &mut Vec<&'a Name>, // fragments already expanded, to break cycles
+    fields: &mut Vec<&'a Node<ast::Field>>,
+) {
+    for selection in selections {
+        match selection {
+            ast::Selection::Field(field) => fields.push(field),
+            ast::Selection::InlineFragment(inline) => {
+                collect_selected_fields(&inline.selection_set, fragments, spreads, fields);
+            }
+            ast::Selection::FragmentSpread(spread) => {
+                if spreads.contains(&&spread.fragment_name) {
+                    continue;
+                }
+                spreads.push(&spread.fragment_name);
+                if let Some(fragment) = fragments.get(&spread.fragment_name) {
+                    collect_selected_fields(&fragment.selection_set, fragments, spreads, fields);
+                }
+            }
+        }
+    }
+}
+
+// The response names of the fields a subgraph operation selects: at its root, or for entity
+// fetches, on the entities.
+fn fetched_response_names(subgraph_operation: &str) -> Option<HashSet<Name>> {
+    let document = ast::Document::parse(subgraph_operation, "operation.graphql").ok()?;
+    let fragments = document_fragments(&document);
+    let operation = document
+        .definitions
+        .iter()
+        .find_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => Some(operation),
+            _ => None,
+        })?;
+    let root_fields = selected_fields(&operation.selection_set, &fragments);
+    let fields = match root_fields.as_slice() {
+        [field] if field.name.as_str() == "_entities" => {
+            selected_fields(&field.selection_set, &fragments)
+        }
+        _ => root_fields,
+    };
+    Some(
+        fields
+            .into_iter()
+            .map(|field| field.response_name().clone())
+            .collect(),
+    )
+}
+
+// The lines of `source` overlapping the byte range, prefixed with their line numbers.
+fn numbered_lines(source: &str, start: usize, end: usize) -> String {
+    let first_line = source[..start].matches('\n').count();
+    let last_line = first_line + source[start..end].matches('\n').count();
+    let width = (last_line + 1).to_string().len();
+    source
+        .lines()
+        .enumerate()
+        .skip(first_line)
+        .take(last_line - first_line + 1)
+        .map(|(index, line)| format!("{:>width$} | {line}\n", index + 1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_anonymous_operation_needs_no_name() {
+        let query = include_str!("../tests/fixtures/operations/anonymous.graphql");
+        assert_eq!(select_operation(query, None), Ok(None));
+    }
+
+    #[test]
+    fn test_single_named_operation_needs_no_name() {
+        assert_eq!(select_operation("query A { a }", None), Ok(None));
+    }
+
+    #[test]
+    fn test_multiple_operations_require_a_name() {
+        let query = "query A { a } query B { b }";
+        let error = select_operation(query, None).unwrap_err();
+        assert!(error.contains("--operation-name"), "{error}");
+        assert_eq!(
+            select_operation(query, Some("B")),
+            Ok(Some(Name::new("B").unwrap()))
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_document_lists_operations() {
+        let query = "query A { a } { b } mutation C { c }";
+        let error = select_operation(query, None).unwrap_err();
+        assert!(
+            error.ends_with("  0: A\n  1: <anonymous>\n  2: C"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_unknown_operation_name() {
+        let error = select_operation("query A { a }", Some("B")).unwrap_err();
+        assert!(error.ends_with("  0: A"), "{error}");
+    }
+
+    #[test]
+    fn test_operation_names() {
+        assert_eq!(
+            operation_names("query A { a } { b } fragment F on T { x }"),
+            vec![Some(Name::new("A").unwrap()), None]
+        );
+    }
+
+    #[test]
+    fn test_operation_display_name() {
+        let file = Path::new("products/top.graphql");
+        assert_eq!(
+            operation_display_name(file, "query TopProducts { a }"),
+            "TopProducts"
+        );
+        assert_eq!(operation_display_name(file, "{ a }"), "top");
+        assert_eq!(
+            operation_display_name(file, "query A { a } query B { b }"),
+            "top"
+        );
+        let extracted = extract_operation("query A { a } query B { b }", 1).unwrap();
+        assert_eq!(operation_display_name(file, &extracted), "B");
+        assert_eq!(operation_display_name(file, "{ a"), "top");
+    }
+
+    #[test]
+    fn test_extract_operation_by_index() {
+        let query = r#"
+            query A { a }
+            { b ...F }
+            fragment G on T { y }
+            fragment F on T { x ...H }
+            fragment H on T { z }
+        "#;
+        let extracted = extract_operation(query, 1).unwrap();
+        let document = ast::Document::parse(&extracted, "extracted.graphql").unwrap();
+        let names: Vec<String> = document
+            .definitions
+            .iter()
+            .map(|definition| match definition {
+                ast::Definition::OperationDefinition(operation) => {
+                    option_name(operation.name.as_ref())
+                }
+                ast::Definition::FragmentDefinition(fragment) => fragment.name.to_string(),
+                _ => panic!("unexpected definition"),
+            })
+            .collect();
+        assert_eq!(names, vec!["<anonymous>", "F", "H"]);
+        assert_eq!(select_operation(&extracted, None), Ok(None));
+    }
+
+    #[test]
+    fn test_extract_operation_index_out_of_range() {
+        assert!(extract_operation("query A { a } query B { b }", 2).is_err());
+    }
+
+    #[test]
+    fn test_merge_fragments() {
+        let query = "{ a ...F ...L }  fragment L on T { l }";
+        let fragments = r#"
+            fragment F on T { x ...H }
+            fragment G on T { y }
+            fragment H on T { z }
+            fragment L on T { shadowed }
+        "#;
+        let merged = merge_fragments(query, fragments).unwrap();
+        assert!(merged.starts_with(query));
+        let document = ast::Document::parse(&merged, "merged.graphql").unwrap();
+        let names: Vec<String> = document
+            .definitions
+            .iter()
+            .filter_map(|definition| match definition {
+                ast::Definition::FragmentDefinition(fragment) => Some(fragment.name.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, vec!["L", "F", "H"]);
+    }
+
+    #[test]
+    fn test_merge_fragments_rejects_operations() {
+        assert!(merge_fragments("{ a }", "query Q { b }").is_err());
+    }
+
+    fn variables(json: serde_json::Value) -> Map<String, Value> {
+        json.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_introspection_only_operations() {
+        let introspection = include_str!("../tests/fixtures/operations/introspection.graphql");
+        assert!(is_introspection_only(introspection, None));
+        assert!(is_introspection_only(
+            introspection,
+            Some("IntrospectionQuery")
+        ));
+        assert!(!is_introspection_only(introspection, Some("Missing")));
+        let anonymous = include_str!("../tests/fixtures/operations/anonymous.graphql");
+        assert!(!is_introspection_only(anonymous, None));
+        // Mixed with regular fields, the operation still fetches from subgraphs.
+        assert!(!is_introspection_only(
+            "{ __schema { queryType { name } } topProducts { upc } }",
+            None
+        ));
+        assert!(!is_introspection_only("{ __typename }", None));
+    }
+
+    #[test]
+    fn test_apply_pinned_conditions() {
+        let query = include_str!("../tests/fixtures/operations/include.graphql");
+        let excluded = apply_conditions(
+            query,
+            &variables(serde_json::json!({ "withReviews": false })),
+        )
+        .unwrap();
+        assert!(!excluded.contains("reviews"), "{excluded}");
+        assert!(!excluded.contains("$withReviews"), "{excluded}");
+        let included = apply_conditions(
+            query,
+            &variables(serde_json::json!({ "withReviews": true })),
+        )
+        .unwrap();
+        assert!(included.contains("reviews"), "{included}");
+        assert!(!included.contains("@include"), "{included}");
+    }
+
+    #[test]
+    fn test_unpinned_conditions_are_left_intact() {
+        let query = include_str!("../tests/fixtures/operations/include.graphql");
+        assert_eq!(
+            apply_conditions(query, &variables(serde_json::json!({ "first": 5 }))),
+            Ok(query.to_string())
+        );
+    }
+
+    #[test]
+    fn test_apply_conditions_removes_unused_fragments() {
+        let query = r#"
+            query Q($a: Boolean!, $b: Boolean!) {
+              t { x ...F @skip(if: $a) ... @include(if: $b) { y } }
+            }
+            fragment F on T { z }
+        "#;
+        let applied =
+            apply_conditions(query, &variables(serde_json::json!({ "a": true }))).unwrap();
+        let document = ast::Document::parse(&applied, "applied.graphql").unwrap();
+        assert_eq!(document.definitions.len(), 1, "{applied}");
+        assert!(applied.contains("$b: Boolean!"), "{applied}");
+        assert!(!applied.contains("$a"), "{applied}");
+    }
+
+    #[test]
+    fn test_apply_conditions_rejects_empty_selection_sets() {
+        let query = "query Q { t @include(if: true) { x @skip(if: true) } }";
+        assert!(apply_conditions(query, &Map::new()).is_err());
+    }
+
+    const TOP_PRODUCTS: &str = "query TopProducts {
+  me { id }
+  topProducts {
+    upc
+    ...ProductName
+    reviews {
+      body
+    }
+  }
+}
+
+fragment ProductName on Product { name }
+";
+
+    #[test]
+    fn test_source_snippet_of_entity_fetch() {
+        let subgraph_operation = "query TopProducts__reviews__1($representations: [_Any!]!) { \
+                                  _entities(representations: $representations) { \
+                                  ... on Product { reviews { body } } } }";
+        assert_eq!(
+            source_snippet(
+                TOP_PRODUCTS,
+                None,
+                &["topProducts".to_string()],
+                subgraph_operation
+            )
+            .as_deref(),
+            Some("6 |     reviews {\n7 |       body\n8 |     }\n")
+        );
+    }
+
+    #[test]
+    fn test_source_snippet_of_root_fetch() {
+        assert_eq!(
+            source_snippet(TOP_PRODUCTS, Some("TopProducts"), &[], "{ me { id } }").as_deref(),
+            Some("2 |   me { id }\n")
+        );
+        // Fields selected through fragments are located in their definition.
+        assert_eq!(
+            source_snippet(
+                TOP_PRODUCTS,
+                None,
+                &["topProducts".to_string()],
+                "{ _entities(representations: []) { ... on Product { name } } }"
+            )
+            .as_deref(),
+            Some("12 | fragment ProductName on Product { name }\n")
+        );
+    }
+
+    #[test]
+    fn test_source_snippet_falls_back_to_the_merge_path() {
+        let snippet = source_snippet(
+            TOP_PRODUCTS,
+            None,
+            &["topProducts".to_string()],
+            "{ _entities(representations: []) { ... on Product { price } } }",
+        )
+        .unwrap();
+        assert!(snippet.starts_with("3 |   topProducts {\n"), "{snippet}");
+        assert!(snippet.ends_with("9 |   }\n"), "{snippet}");
+        assert_eq!(
+            source_snippet(
+                TOP_PRODUCTS,
+                None,
+                &["bestSellers".to_string()],
+                "{ me { id } }"
+            ),
+            None
+        );
+        assert_eq!(
+            source_snippet(TOP_PRODUCTS, Some("Other"), &[], "{ me { id } }"),
+            None
+        );
+    }
+
+    fn option_name(name: Option<&Name>) -> String {
+        name.map_or_else(|| "<anonymous>".to_string(), |name| name.to_string())
+    }
+}
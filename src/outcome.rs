@@ -0,0 +1,159 @@
+//! The outcome of planning an operation with both planners and comparing the plans.
+
+use std::time::Duration;
+
+use crate::CompareOptions;
+use crate::FederationError;
+use crate::LegacyQueryPlanResult;
+use crate::MatchFailure;
+use crate::NativeQueryPlan;
+use crate::comparator::Comparator;
+use crate::legacy_planner;
+use crate::native_planner;
+use crate::plan::PlanNode;
+use crate::render_plan;
+use crate::router::plan_compare::plan_matches_with_fingerprint;
+
+#[derive(Debug)]
+pub enum ComparisonOutcome {
+    /// Both planners produced matching plans.
+    Match,
+    /// Both planners produced plans, but they don't match.
+    Mismatch(MatchFailure),
+    /// The legacy planner rejected the operation, but the native planner planned it. Like
+    /// `OnlyLegacySucceeded`, this is a behavior change to review.
+    OnlyNativeSucceeded {
+        rust_plan: Box<NativeQueryPlan>,
+        legacy_error: String,
+    },
+    /// The native planner rejected the operation, but the legacy planner planned it.
+    OnlyLegacySucceeded {
+        js_plan: LegacyQueryPlanResult,
+        native_error: String,
+    },
+    /// Both planners failed to plan the operation.
+    PlanningFailed(String),
+    /// The operation is invalid against the API schema, so it wasn't planned. Holds the
+    /// validation errors, with their locations.
+    InvalidOperation(String),
+}
+
+impl ComparisonOutcome {
+    /// Compares the plans produced by both planners.
+    pub fn from_plans(
+        js_plan: &LegacyQueryPlanResult,
+        rust_plan: &NativeQueryPlan,
+        options: &CompareOptions,
+    ) -> Self {
+        match plan_matches_with_fingerprint(js_plan, rust_plan, options) {
+            Ok(()) => Self::Match,
+            Err(match_failure) => Self::Mismatch(match_failure),
+        }
+    }
+
+    /// Compares the plans, or reports the planners that failed.
+    pub(crate) fn from_results(
+        js_plan: Result<LegacyQueryPlanResult, Vec<String>>,
+        rust_plan: Result<NativeQueryPlan, FederationError>,
+        options: &CompareOptions,
+    ) -> Self {
+        match (js_plan, rust_plan) {
+            (Ok(js_plan), Ok(rust_plan)) => Self::from_plans(&js_plan, &rust_plan, options),
+            (Err(js_errors), Ok(rust_plan)) => Self::OnlyNativeSucceeded {
+                rust_plan: Box::new(rust_plan),
+                legacy_error: js_errors.join("\n"),
+            },
+            (Ok(js_plan), Err(rust_error)) => Self::OnlyLegacySucceeded {
+                js_plan,
+                native_error: rust_error.to_string(),
+            },
+            (Err(js_errors), Err(rust_error)) => Self::PlanningFailed(format!(
+                "Legacy planner error: {}\nNative planner error: {rust_error}",
+                js_errors.join("\n")
+            )),
+        }
+    }
+
+    pub fn is_match(&self) -> bool {
+        matches!(self, Self::Match)
+    }
+}
+
+/// The outcome of a comparison, along with the plans compared and the time each planner took, for
+/// consumers presenting comparisons themselves (e.g. a service or a notebook).
+#[derive(Debug)]
+pub struct DetailedComparison {
+    pub outcome: ComparisonOutcome,
+    /// The plan tree of each planner, as compared (before normalization). `None` for an empty
+    /// plan, or if the planner failed or didn't run, as told by the outcome.
+    pub native_plan: Option<PlanNode>,
+    pub legacy_plan: Option<PlanNode>,
+    /// Zero if the planner didn't run.
+    pub native_time: Duration,
+    pub legacy_time: Duration,
+}
+
+impl DetailedComparison {
+    /// An outcome reached without planning, e.g. for an invalid operation.
+    pub(crate) fn unplanned(outcome: ComparisonOutcome) -> Self {
+        Self {
+            outcome,
+            native_plan: None,
+            legacy_plan: None,
+            native_time: Duration::ZERO,
+            legacy_time: Duration::ZERO,
+        }
+    }
+
+    /// The native plan, rendered as in mismatch reports.
+    pub fn render_native_plan(&self) -> String {
+        render_plan(self.native_plan.as_ref())
+    }
+
+    /// The legacy plan, rendered as in mismatch reports.
+    pub fn render_legacy_plan(&self) -> String {
+        render_plan(self.legacy_plan.as_ref())
+    }
+
+    /// Both plans as JSON, in the router's plan format, with the planning times.
+    pub fn plans_to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "native_plan": self.native_plan,
+            "legacy_plan": self.legacy_plan,
+            "native_ms": self.native_time.as_millis() as u64,
+            "legacy_ms": self.legacy_time.as_millis() as u64,
+        })
+    }
+}
+
+/// Plans an operation with both planners and compares the plans, once validated against the API
+/// schema. To compare several operations, a `Comparator` reuses the planners.
+pub fn compare_operation(
+    schema_str: &str,
+    query_str: &str,
+    operation_name: Option<apollo_compiler::Name>,
+    native_config: native_planner::QueryPlannerConfig,
+    legacy_config: legacy_planner::QueryPlannerConfig,
+    options: &CompareOptions,
+) -> ComparisonOutcome {
+    match Comparator::new(schema_str, native_config, legacy_config, options.clone()) {
+        Ok(comparator) => comparator.compare(query_str, operation_name),
+        Err(error) => ComparisonOutcome::PlanningFailed(error),
+    }
+}
+
+/// Like `compare_operation`, but also returns both plans and the planning times, to embed the
+/// comparison in a UI without planning the operation again.
+pub fn compare_detailed(
+    schema_str: &str,
+    query_str: &str,
+    operation_name: Option<apollo_compiler::Name>,
+    native_config: native_planner::QueryPlannerConfig,
+    legacy_config: legacy_planner::QueryPlannerConfig,
+    options: &CompareOptions,
+) -> DetailedComparison {
+    match Comparator::new(schema_str, native_config, legacy_config, options.clone()) {
+        Ok(comparator) => comparator.compare_detailed(query_str, operation_name),
+        Err(error) => DetailedComparison::unplanned(ComparisonOutcome::PlanningFailed(error)),
+    }
+}
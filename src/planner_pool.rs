@@ -0,0 +1,154 @@
+//! A bounded pool of native query planners, keyed by schema, for tools planning operations
+//! against several supergraphs in one process.
+//!
+//! Building a native planner (parsing and extracting the subgraphs of a supergraph) dominates the
+//! cost of planning small operations, so planners are reused for operations targeting the same
+//! schema. The least recently used planner is evicted when the pool is full.
+//!
+//! Thread safety: the native `QueryPlanner` is `Send` and `Sync`, and plans through `&self` (the
+//! router shares one across its planning threads), so pooled planners are handed out as `Arc`s and
+//! the pool itself can be shared between threads. The pool is locked while looking up or
+//! inserting a planner, but not while building one: threads missing the same schema concurrently
+//! may each build a planner, and the last one built is kept.
+
+use std::hash::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::FederationError;
+use crate::native_planner::QueryPlanner;
+use crate::native_planner::QueryPlannerConfig;
+
+pub struct NativePlannerPool {
+    capacity: NonZeroUsize,
+    /// Planners by key, the most recently used last.
+    planners: Mutex<Vec<(u64, Arc<QueryPlanner>)>>,
+}
+
+// Pools are meant to be shared between threads.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<NativePlannerPool>();
+};
+
+impl NativePlannerPool {
+    /// Creates an empty pool holding up to `capacity` planners.
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            capacity,
+            planners: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the pooled planner for this schema and config, building it if needed.
+    pub fn get_or_build(
+        &self,
+        schema_str: &str,
+        config: &QueryPlannerConfig,
+    ) -> Result<Arc<QueryPlanner>, FederationError> {
+        let key = pool_key(schema_str, config);
+        if let Some(planner) = self.get(key) {
+            return Ok(planner);
+        }
+        let supergraph = apollo_federation::Supergraph::new_with_router_specs(schema_str)?;
+        let planner = Arc::new(QueryPlanner::new(&supergraph, config.clone())?);
+        let mut planners = self.planners.lock().unwrap();
+        planners.retain(|(existing, _)| *existing != key);
+        if planners.len() == self.capacity.get() {
+            planners.remove(0);
+        }
+        planners.push((key, planner.clone()));
+        Ok(planner)
+    }
+
+    /// The number of pooled planners.
+    pub fn len(&self) -> usize {
+        self.planners.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Marks the planner as the most recently used.
+    fn get(&self, key: u64) -> Option<Arc<QueryPlanner>> {
+        let mut planners = self.planners.lock().unwrap();
+        let index = planners.iter().position(|(existing, _)| *existing == key)?;
+        let entry = planners.remove(index);
+        let planner = entry.1.clone();
+        planners.push(entry);
+        Some(planner)
+    }
+}
+
+// Note: `QueryPlannerConfig` doesn't implement `Hash`, so its `Debug` form is hashed instead.
+fn pool_key(schema_str: &str, config: &QueryPlannerConfig) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    schema_str.hash(&mut hasher);
+    format!("{config:?}").hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUPERGRAPH: &str = include_str!("../tests/fixtures/supergraph.graphql");
+
+    fn capacity(capacity: usize) -> NonZeroUsize {
+        NonZeroUsize::new(capacity).unwrap()
+    }
+
+    #[test]
+    fn test_planners_are_reused_per_schema() {
+        let pool = NativePlannerPool::new(capacity(2));
+        let config = QueryPlannerConfig::default();
+        let planner = pool.get_or_build(SUPERGRAPH, &config).unwrap();
+        let reused = pool.get_or_build(SUPERGRAPH, &config).unwrap();
+        assert!(Arc::ptr_eq(&planner, &reused));
+
+        let other_schema = format!("{SUPERGRAPH}\n# v2\n");
+        let other = pool.get_or_build(&other_schema, &config).unwrap();
+        assert!(!Arc::ptr_eq(&planner, &other));
+        let other_config = QueryPlannerConfig {
+            generate_query_fragments: !config.generate_query_fragments,
+            ..Default::default()
+        };
+        let other = pool.get_or_build(SUPERGRAPH, &other_config).unwrap();
+        assert!(!Arc::ptr_eq(&planner, &other));
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn test_least_recently_used_planner_is_evicted() {
+        let pool = NativePlannerPool::new(capacity(2));
+        let config = QueryPlannerConfig::default();
+        let schemas = ["# a", "# b", "# c"].map(|suffix| format!("{SUPERGRAPH}\n{suffix}\n"));
+        let a = pool.get_or_build(&schemas[0], &config).unwrap();
+        let b = pool.get_or_build(&schemas[1], &config).unwrap();
+        pool.get_or_build(&schemas[0], &config).unwrap();
+        pool.get_or_build(&schemas[2], &config).unwrap();
+        assert_eq!(pool.len(), 2);
+        assert!(Arc::ptr_eq(
+            &a,
+            &pool.get_or_build(&schemas[0], &config).unwrap()
+        ));
+        assert!(!Arc::ptr_eq(
+            &b,
+            &pool.get_or_build(&schemas[1], &config).unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_invalid_schemas_are_not_pooled() {
+        let pool = NativePlannerPool::new(capacity(1));
+        assert!(
+            pool.get_or_build("type Query { a: Int }", &QueryPlannerConfig::default())
+                .is_err()
+        );
+        assert!(pool.is_empty());
+    }
+}
@@ -0,0 +1,170 @@
+//! Sanity checks on the input files, run before the planners to turn common mistakes into clear
+//! errors.
+
+use apollo_compiler::ExecutableDocument;
+use apollo_compiler::Schema;
+use apollo_compiler::ast;
+use apollo_compiler::validation::Valid;
+
+/// Detects a schema and an operation passed in each other's place.
+///
+/// This is a heuristic: the schema must contain type system definitions and no executable ones,
+/// and conversely for the operation. Syntax errors are left for the planners to report.
+pub fn check_inputs_not_swapped(schema_str: &str, query_str: &str) -> Result<(), String> {
+    check_schema_not_operation(schema_str)?;
+    check_operation_not_schema(query_str)
+}
+
+/// The schema half of `check_inputs_not_swapped`, to run once when the schema is loaded.
+pub fn check_schema_not_operation(schema_str: &str) -> Result<(), String> {
+    if has_only_executable_definitions(schema_str) {
+        return Err(swapped_error(
+            "The schema file contains operations rather than type definitions",
+        ));
+    }
+    Ok(())
+}
+
+/// The operation half of `check_inputs_not_swapped`, to run on each operation.
+pub fn check_operation_not_schema(query_str: &str) -> Result<(), String> {
+    if has_only_type_system_definitions(query_str) {
+        return Err(swapped_error(
+            "The operation file contains type definitions rather than operations",
+        ));
+    }
+    Ok(())
+}
+
+fn swapped_error(problem: &str) -> String {
+    format!("{problem}: did you swap --schema and --operation?")
+}
+
+/// Validates an operation against the API schema of the supergraph (with `@defer` if
+/// `include_defer`), so that invalid operations are reported once, with the locations of their
+/// errors, rather than as whatever each planner makes of them.
+///
+/// Supergraph errors are left for the planners to report. To validate several operations, build
+/// the API schema once with `validation_api_schema`.
+pub fn validate_operation(
+    schema_str: &str,
+    query_str: &str,
+    include_defer: bool,
+) -> Result<(), String> {
+    match validation_api_schema(schema_str, include_defer) {
+        Some(api_schema) => validate_against_api_schema(&api_schema, query_str),
+        None => Ok(()),
+    }
+}
+
+/// Builds the API schema of the supergraph (with `@defer` if `include_defer`) that operations are
+/// validated against, or `None` if the supergraph is invalid.
+pub fn validation_api_schema(schema_str: &str, include_defer: bool) -> Option<Valid<Schema>> {
+    let supergraph = apollo_federation::Supergraph::new_with_router_specs(schema_str).ok()?;
+    let options = apollo_federation::ApiSchemaOptions {
+        include_defer,
+        ..Default::default()
+    };
+    let api_schema = supergraph.to_api_schema(options).ok()?;
+    Some(api_schema.schema().clone())
+}
+
+/// Like `validate_operation`, against an API schema already built, e.g. by
+/// `validation_api_schema` or the native planner.
+pub fn validate_against_api_schema(
+    api_schema: &Valid<Schema>,
+    query_str: &str,
+) -> Result<(), String> {
+    ExecutableDocument::parse_and_validate(api_schema, query_str, "operation.graphql")
+        .map(drop)
+        .map_err(|with_errors| with_errors.errors.to_string())
+}
+
+// Returns `(executable, type_system)` definition counts.
+fn count_definitions(source: &str) -> (usize, usize) {
+    let document = ast::Document::parse(source, "input.graphql")
+        .unwrap_or_else(|with_errors| with_errors.partial);
+    document
+        .definitions
+        .iter()
+        .fold(
+            (0, 0),
+            |(executable, type_system), definition| match definition {
+                ast::Definition::OperationDefinition(_)
+                | ast::Definition::FragmentDefinition(_) => (executable + 1, type_system),
+                _ => (executable, type_system + 1),
+            },
+        )
+}
+
+fn has_only_executable_definitions(source: &str) -> bool {
+    let (executable, type_system) = count_definitions(source);
+    executable > 0 && type_system == 0
+}
+
+fn has_only_type_system_definitions(source: &str) -> bool {
+    let (executable, type_system) = count_definitions(source);
+    executable == 0 && type_system > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"
+        schema { query: Query }
+        type Query { topProducts: [Product] }
+        type Product { upc: String! name: String }
+    "#;
+    const OPERATION: &str = include_str!("../tests/fixtures/operations/anonymous.graphql");
+
+    #[test]
+    fn test_inputs_in_order() {
+        assert_eq!(check_inputs_not_swapped(SCHEMA, OPERATION), Ok(()));
+    }
+
+    #[test]
+    fn test_operation_passed_as_schema() {
+        let error = check_inputs_not_swapped(OPERATION, OPERATION).unwrap_err();
+        assert!(error.contains("did you swap --schema and --operation?"));
+        assert!(error.starts_with("The schema file"));
+    }
+
+    #[test]
+    fn test_schema_passed_as_operation() {
+        let error = check_inputs_not_swapped(SCHEMA, SCHEMA).unwrap_err();
+        assert!(error.contains("did you swap --schema and --operation?"));
+        assert!(error.starts_with("The operation file"));
+    }
+
+    #[test]
+    fn test_both_swapped() {
+        assert!(check_inputs_not_swapped(OPERATION, SCHEMA).is_err());
+    }
+
+    #[test]
+    fn test_checks_of_each_input() {
+        assert_eq!(check_schema_not_operation(SCHEMA), Ok(()));
+        assert!(check_schema_not_operation(OPERATION).is_err());
+        assert_eq!(check_operation_not_schema(OPERATION), Ok(()));
+        assert!(check_operation_not_schema(SCHEMA).is_err());
+    }
+
+    #[test]
+    fn test_validate_operation() {
+        let supergraph = include_str!("../tests/fixtures/supergraph.graphql");
+        assert_eq!(validate_operation(supergraph, OPERATION, false), Ok(()));
+        let error =
+            validate_operation(supergraph, "{ topProducts { upc price } }", false).unwrap_err();
+        assert!(error.contains("`price`"), "{error}");
+        assert!(error.contains("operation.graphql:1:"), "{error}");
+    }
+
+    #[test]
+    fn test_validate_against_api_schema() {
+        let supergraph = include_str!("../tests/fixtures/supergraph.graphql");
+        let api_schema = validation_api_schema(supergraph, false).unwrap();
+        assert_eq!(validate_against_api_schema(&api_schema, OPERATION), Ok(()));
+        assert!(validate_against_api_schema(&api_schema, "{ topProducts { price } }").is_err());
+        assert!(validation_api_schema(SCHEMA, false).is_none());
+    }
+}
@@ -0,0 +1,189 @@
+//! Output styling shared by all renderers.
+//!
+//! Colors honor the `NO_COLOR` convention (<https://no-color.org>) and are only enabled on a
+//! terminal by default. Long lines are wrapped at the terminal width when writing to a terminal,
+//! and left alone otherwise (e.g. when piped to a file).
+
+use std::borrow::Cow;
+use std::io::IsTerminal;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// When to emit ANSI colors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Colorize when writing to a terminal and `NO_COLOR` is not set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// How plan differences are reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DiffStyle {
+    /// A line diff of the plan trees, showing every field of every node.
+    #[default]
+    Structural,
+    /// A unified diff (as `diff -u`) of the plans, rendered in a pseudo-GraphQL form like the
+    /// planners' formatted query plans. Easier to read, but some node fields are left out.
+    Unified,
+}
+
+/// Whether to colorize output, where to wrap long lines, how to indent and elide matching subtrees
+/// of structural diffs, and whether to explain the fetches of unified diffs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub color: bool,
+    /// Maximum line width. `None` disables wrapping.
+    pub width: Option<usize>,
+    /// Collapse plan node subtrees matching on both sides to a one-line summary, leaving the paths
+    /// to divergences expanded.
+    pub elide_matching: bool,
+    /// Spaces per nesting level of structural diffs. `None` keeps the 4 spaces of Rust's pretty
+    /// `Debug` format they are rendered from.
+    pub indent: Option<usize>,
+    /// Annotate the fetches of unified diffs with a summary (see `format_plan_explained`).
+    pub explain: bool,
+}
+
+impl Style {
+    /// Uncolored, unwrapped output.
+    pub fn plain() -> Self {
+        Self::default()
+    }
+
+    /// Detects the style for the given output stream, applying the `--color`/`--width` overrides.
+    pub fn for_stream(stream: &impl IsTerminal, color: ColorChoice, width: Option<usize>) -> Self {
+        let is_terminal = stream.is_terminal();
+        let color = match color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => is_terminal && !no_color(),
+        };
+        let width = width.or_else(|| if is_terminal { terminal_width() } else { None });
+        Self {
+            color,
+            width,
+            elide_matching: false,
+            indent: None,
+            explain: false,
+        }
+    }
+
+    /// Styles a line removed from the left-hand side of a diff.
+    pub fn removed(&self, line: &str) -> String {
+        self.paint(RED, line)
+    }
+
+    /// Styles a line added on the right-hand side of a diff.
+    pub fn added(&self, line: &str) -> String {
+        self.paint(GREEN, line)
+    }
+
+    /// Styles the `@@ ... @@` header of a unified diff hunk.
+    pub fn hunk_header(&self, line: &str) -> String {
+        self.paint(CYAN, line)
+    }
+
+    fn paint(&self, color: &str, line: &str) -> String {
+        if self.color {
+            format!("{color}{line}{RESET}")
+        } else {
+            line.to_string()
+        }
+    }
+
+    /// Rescales the indentation of a line of pretty `Debug` output (4 spaces per level) to the
+    /// `indent` width.
+    pub fn reindent<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        const DEBUG_INDENT: usize = 4;
+        let Some(indent) = self.indent.filter(|indent| *indent != DEBUG_INDENT) else {
+            return Cow::Borrowed(line);
+        };
+        let content = line.trim_start_matches(' ');
+        let spaces = line.len() - content.len();
+        let spaces = spaces / DEBUG_INDENT * indent + spaces % DEBUG_INDENT;
+        Cow::Owned(format!("{}{content}", " ".repeat(spaces)))
+    }
+
+    /// Splits `line` into chunks fitting the width, leaving room for a `prefix_width`-wide
+    /// prefix (such as a diff marker) on each chunk.
+    pub fn wrap<'a>(&self, line: &'a str, prefix_width: usize) -> Vec<&'a str> {
+        let Some(width) = self
+            .width
+            .map(|width| width.saturating_sub(prefix_width))
+            .filter(|width| *width > 0)
+        else {
+            return vec![line];
+        };
+        let mut chunks = Vec::new();
+        let mut rest = line;
+        while let Some((split, _)) = rest.char_indices().nth(width) {
+            chunks.push(&rest[..split]);
+            rest = &rest[split..];
+        }
+        chunks.push(rest);
+        chunks
+    }
+}
+
+fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+fn terminal_width() -> Option<usize> {
+    terminal_size::terminal_size().map(|(terminal_size::Width(width), _)| width as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_style_does_not_wrap_or_color() {
+        let style = Style::plain();
+        assert_eq!(style.wrap("abcdef", 1), vec!["abcdef"]);
+        assert_eq!(style.removed("-x"), "-x");
+    }
+
+    #[test]
+    fn test_wrap_leaves_room_for_prefix() {
+        let style = Style {
+            color: false,
+            width: Some(4),
+            elide_matching: false,
+            indent: None,
+            explain: false,
+        };
+        assert_eq!(style.wrap("abcdefg", 1), vec!["abc", "def", "g"]);
+        assert_eq!(style.wrap("abc", 1), vec!["abc"]);
+        assert_eq!(style.wrap("", 1), vec![""]);
+    }
+
+    #[test]
+    fn test_color_wraps_line_in_escape_codes() {
+        let style = Style {
+            color: true,
+            width: None,
+            elide_matching: false,
+            indent: None,
+            explain: false,
+        };
+        assert_eq!(style.added("+x"), "\x1b[32m+x\x1b[0m");
+    }
+
+    #[test]
+    fn test_reindent_rescales_debug_levels() {
+        let style = Style {
+            indent: Some(2),
+            ..Style::plain()
+        };
+        assert_eq!(style.reindent("        nodes: ["), "    nodes: [");
+        assert_eq!(style.reindent("root"), "root");
+        assert_eq!(Style::plain().reindent("    a"), "    a");
+    }
+}
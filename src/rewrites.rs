@@ -0,0 +1,149 @@
+//! Well-formedness checks on the data rewrites of plans, passed with `--validate-rewrites`.
+//!
+//! Rewrites with the same malformed name or path in both plans still compare equal, so a planner
+//! emitting them would pass the comparison. These checks catch them: renamed keys must be GraphQL
+//! names, and rewrite paths must be made of GraphQL names (or `..`, referring to the parent in
+//! context rewrites) and read back the same from their display form.
+
+use apollo_compiler::Name;
+
+use crate::plan::DataRewrite;
+use crate::plan::Path;
+use crate::plan::PathElement;
+use crate::plan::PlanNode;
+use crate::router::plan_compare::collect_fetches;
+use crate::router::plan_compare::describe_rewrite;
+
+/// Lists the malformed rewrites of a plan, naming the service and the rewrite of each.
+pub fn invalid_rewrites(node: Option<&PlanNode>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let Some(node) = node else {
+        return problems;
+    };
+    let mut check = |service_name: &str, kind: &str, rewrites: &Option<Vec<DataRewrite>>| {
+        for (index, rewrite) in rewrites.iter().flatten().enumerate() {
+            if let Some(problem) = rewrite_problem(rewrite) {
+                problems.push(format!(
+                    "service `{service_name}`, {kind} {index} ({}): {problem}",
+                    describe_rewrite(rewrite)
+                ));
+            }
+        }
+    };
+    if let PlanNode::Subscription { primary, .. } = node {
+        check(
+            &primary.service_name,
+            "input rewrite",
+            &primary.input_rewrites,
+        );
+        check(
+            &primary.service_name,
+            "output rewrite",
+            &primary.output_rewrites,
+        );
+    }
+    let mut fetches = Vec::new();
+    collect_fetches(node, &mut fetches);
+    for fetch in fetches {
+        check(&fetch.service_name, "input rewrite", &fetch.input_rewrites);
+        check(
+            &fetch.service_name,
+            "output rewrite",
+            &fetch.output_rewrites,
+        );
+        check(
+            &fetch.service_name,
+            "context rewrite",
+            &fetch.context_rewrites,
+        );
+    }
+    problems
+}
+
+fn rewrite_problem(rewrite: &DataRewrite) -> Option<String> {
+    match rewrite {
+        DataRewrite::ValueSetter(setter) => path_problem(&setter.path),
+        DataRewrite::KeyRenamer(renamer) => {
+            if !Name::is_valid_syntax(renamer.rename_key_to.as_str()) {
+                return Some(invalid_name(renamer.rename_key_to.as_str()));
+            }
+            path_problem(&renamer.path)
+        }
+    }
+}
+
+fn path_problem(path: &Path) -> Option<String> {
+    for element in path.iter() {
+        let names: Vec<&str> = match element {
+            PathElement::Key(key, type_conditions) => (key != "..")
+                .then_some(key.as_str())
+                .into_iter()
+                .chain(type_conditions.iter().flatten().map(String::as_str))
+                .collect(),
+            PathElement::Flatten(type_conditions) => type_conditions
+                .iter()
+                .flatten()
+                .map(String::as_str)
+                .collect(),
+            PathElement::Fragment(type_condition) => vec![type_condition.as_str()],
+            PathElement::Index(_) => Vec::new(),
+        };
+        if let Some(name) = names.into_iter().find(|name| !Name::is_valid_syntax(name)) {
+            return Some(invalid_name(name));
+        }
+    }
+    if path.to_string().parse::<Path>().as_ref() != Ok(path) {
+        return Some(format!("the path `{path}` reads back differently"));
+    }
+    None
+}
+
+fn invalid_name(name: &str) -> String {
+    format!("`{name}` is not a valid GraphQL name")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn fetch_with_rewrites(input_rewrites: serde_json::Value) -> PlanNode {
+        serde_json::from_value(json!({
+            "kind": "Fetch",
+            "serviceName": "reviews",
+            "variableUsages": [],
+            "operation": "{ topProducts { upc } }",
+            "operationKind": "query",
+            "inputRewrites": input_rewrites,
+            "contextRewrites": [
+                { "kind": "KeyRenamer", "path": ["..", "@|[Product]", "upc"], "renameKeyTo": "contextualArgument_1_0" },
+            ],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_well_formed_rewrites() {
+        let node = fetch_with_rewrites(json!([
+            { "kind": "ValueSetter", "path": ["... on Book", "__typename"], "setValueTo": "Product" },
+        ]));
+        assert!(invalid_rewrites(Some(&node)).is_empty());
+        assert!(invalid_rewrites(None).is_empty());
+    }
+
+    #[test]
+    fn test_malformed_rewrite_paths() {
+        let node = fetch_with_rewrites(json!([
+            { "kind": "ValueSetter", "path": ["... on Book", "__typename"], "setValueTo": "Product" },
+            { "kind": "ValueSetter", "path": ["top-products", "__typename"], "setValueTo": "Product" },
+        ]));
+        assert_eq!(
+            invalid_rewrites(Some(&node)),
+            [
+                "service `reviews`, input rewrite 1 (set the value at `/top-products/__typename` \
+                 to \"Product\"): `top-products` is not a valid GraphQL name"
+            ]
+        );
+    }
+}
@@ -0,0 +1,380 @@
+//! `proptest` strategies generating random plan trees, and the matching `Arbitrary` impls (so that
+//! `any::<PlanNode>()` works). Available with the `arbitrary` feature.
+//!
+//! Leaf values (service names, subgraph operations, paths, ...) are drawn from small fixed pools so
+//! that independently generated nodes have a realistic chance of matching each other. Generated
+//! trees are at most 5 levels deep.
+
+use std::sync::Arc;
+
+use apollo_compiler::Name;
+use apollo_federation::query_plan::requires_selection::Selection;
+use apollo_federation::query_plan::serializable_document::SerializableDocument;
+use proptest::prelude::*;
+use serde_json::json;
+use serde_json_bytes::Value;
+
+use crate::router::path::Path;
+use crate::router::path::PathElement;
+use crate::router::plan::DataKeyRenamer;
+use crate::router::plan::DataRewrite;
+use crate::router::plan::DataValueSetter;
+use crate::router::plan::DeferredNode;
+use crate::router::plan::Depends;
+use crate::router::plan::FetchNode;
+use crate::router::plan::FlattenNode;
+use crate::router::plan::OperationKind;
+use crate::router::plan::PlanNode;
+use crate::router::plan::Primary;
+use crate::router::plan::SubscriptionNode;
+
+const SERVICE_NAMES: &[&str] = &["accounts", "products", "reviews"];
+const VARIABLE_NAMES: &[&str] = &["first", "id", "representations"];
+const KEYS: &[&str] = &["topProducts", "reviews", "author", "me"];
+const TYPE_NAMES: &[&str] = &["Product", "Book", "User"];
+const OPERATIONS: &[&str] = &[
+    "{ topProducts { upc name } }",
+    "query($first: Int) { topProducts(first: $first) { upc } }",
+    "query($representations: [_Any!]!) { _entities(representations: $representations) { ... on Product { reviews { body } } } }",
+    "{ me { ...UserFields } } fragment UserFields on User { id username }",
+];
+const SUBSCRIPTIONS: &[&str] = &[
+    "subscription { reviewAdded { id } }",
+    "subscription($id: ID!) { productUpdated(id: $id) { upc price } }",
+];
+const SUBSELECTIONS: &[&str] = &[
+    "{ name }",
+    "{ reviews { body } }",
+    "{ ... on Product { price } }",
+];
+
+fn document(source: &str) -> SerializableDocument {
+    serde_json::from_value(json!(source)).expect("serialized documents deserialize from strings")
+}
+
+fn name(value: &str) -> Name {
+    Name::new(value).expect("pool values are valid GraphQL names")
+}
+
+fn type_conditions() -> impl Strategy<Value = Option<Vec<String>>> {
+    prop::option::of(prop::collection::vec(
+        prop::sample::select(TYPE_NAMES).prop_map(String::from),
+        0..3,
+    ))
+}
+
+pub fn path_element() -> impl Strategy<Value = PathElement> {
+    prop_oneof![
+        type_conditions().prop_map(PathElement::Flatten),
+        (0usize..4).prop_map(PathElement::Index),
+        prop::sample::select(TYPE_NAMES).prop_map(|t| PathElement::Fragment(t.to_string())),
+        (prop::sample::select(KEYS), type_conditions())
+            .prop_map(|(key, conditions)| PathElement::Key(key.to_string(), conditions)),
+    ]
+}
+
+pub fn path() -> impl Strategy<Value = Path> {
+    prop::collection::vec(path_element(), 0..4).prop_map(Path)
+}
+
+/// Generates `requires` selections: entity representations of the pooled types.
+pub fn requires() -> impl Strategy<Value = Vec<Selection>> {
+    prop::collection::vec((prop::sample::select(TYPE_NAMES), any::<bool>()), 0..3).prop_map(
+        |fragments| {
+            let fragments: Vec<_> = fragments
+                .into_iter()
+                .map(|(type_condition, with_upc)| {
+                    let mut selections = vec![
+                        json!({ "kind": "Field", "name": "__typename" }),
+                        json!({ "kind": "Field", "name": "id" }),
+                    ];
+                    if with_upc {
+                        selections.push(json!({ "kind": "Field", "name": "upc" }));
+                    }
+                    json!({
+                        "kind": "InlineFragment",
+                        "typeCondition": type_condition,
+                        "selections": selections,
+                    })
+                })
+                .collect();
+            serde_json::from_value(json!(fragments)).expect("requires selections are well-formed")
+        },
+    )
+}
+
+pub fn data_rewrite() -> impl Strategy<Value = DataRewrite> {
+    prop_oneof![
+        (path(), prop::sample::select(TYPE_NAMES)).prop_map(|(path, value)| {
+            DataRewrite::ValueSetter(DataValueSetter {
+                path,
+                set_value_to: Value::from(value),
+            })
+        }),
+        (path(), prop::sample::select(KEYS)).prop_map(|(path, key)| {
+            DataRewrite::KeyRenamer(DataKeyRenamer {
+                path,
+                rename_key_to: name(key),
+            })
+        }),
+    ]
+}
+
+fn rewrites() -> impl Strategy<Value = Option<Vec<DataRewrite>>> {
+    prop::option::of(prop::collection::vec(data_rewrite(), 1..3))
+}
+
+fn variable_usages() -> impl Strategy<Value = Vec<Arc<str>>> {
+    prop::sample::subsequence(VARIABLE_NAMES, 0..=VARIABLE_NAMES.len())
+        .prop_map(|names| names.into_iter().map(Arc::from).collect())
+}
+
+fn operation_name() -> impl Strategy<Value = Option<Arc<str>>> {
+    prop::option::of((0u32..4).prop_map(|index| Arc::from(format!("Query__op__{index}"))))
+}
+
+pub fn fetch_node() -> impl Strategy<Value = FetchNode> {
+    (
+        prop::sample::select(SERVICE_NAMES),
+        requires(),
+        variable_usages(),
+        prop::sample::select(OPERATIONS),
+        operation_name(),
+        prop::option::of((0u32..8).prop_map(|id| id.to_string())),
+        rewrites(),
+        rewrites(),
+        rewrites(),
+    )
+        .prop_map(
+            |(
+                service_name,
+                requires,
+                variable_usages,
+                operation,
+                operation_name,
+                id,
+                input_rewrites,
+                output_rewrites,
+                context_rewrites,
+            )| FetchNode {
+                service_name: Arc::from(service_name),
+                requires,
+                variable_usages,
+                operation: document(operation),
+                operation_name,
+                operation_kind: OperationKind::Query,
+                id,
+                input_rewrites,
+                output_rewrites,
+                context_rewrites,
+            },
+        )
+}
+
+pub fn subscription_node() -> impl Strategy<Value = SubscriptionNode> {
+    (
+        prop::sample::select(SERVICE_NAMES),
+        variable_usages(),
+        prop::sample::select(SUBSCRIPTIONS),
+        operation_name(),
+        rewrites(),
+        rewrites(),
+    )
+        .prop_map(
+            |(
+                service_name,
+                variable_usages,
+                operation,
+                operation_name,
+                input_rewrites,
+                output_rewrites,
+            )| SubscriptionNode {
+                service_name: Arc::from(service_name),
+                variable_usages,
+                operation: document(operation),
+                operation_name,
+                operation_kind: OperationKind::Subscription,
+                input_rewrites,
+                output_rewrites,
+            },
+        )
+}
+
+fn subselection() -> impl Strategy<Value = Option<String>> {
+    prop::option::of(prop::sample::select(SUBSELECTIONS).prop_map(String::from))
+}
+
+pub fn primary(node: BoxedStrategy<PlanNode>) -> impl Strategy<Value = Primary> {
+    (subselection(), prop::option::of(node)).prop_map(|(subselection, node)| Primary {
+        subselection,
+        node: node.map(Box::new),
+    })
+}
+
+pub fn deferred_node(node: BoxedStrategy<PlanNode>) -> impl Strategy<Value = DeferredNode> {
+    (
+        prop::collection::vec(
+            (0u32..8).prop_map(|id| Depends { id: id.to_string() }),
+            0..3,
+        ),
+        prop::option::of(prop::sample::select(KEYS).prop_map(String::from)),
+        path(),
+        subselection(),
+        prop::option::of(node),
+    )
+        .prop_map(
+            |(depends, label, query_path, subselection, node)| DeferredNode {
+                depends,
+                label,
+                query_path,
+                subselection,
+                node: node.map(Arc::new),
+            },
+        )
+}
+
+/// Generates plan trees covering every `PlanNode` variant, with `Fetch` nodes as leaves.
+pub fn plan_node() -> impl Strategy<Value = PlanNode> {
+    let leaf = fetch_node().prop_map(PlanNode::Fetch);
+    leaf.prop_recursive(4, 64, 4, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..4)
+                .prop_map(|nodes| PlanNode::Sequence { nodes }),
+            prop::collection::vec(inner.clone(), 0..4)
+                .prop_map(|nodes| PlanNode::Parallel { nodes }),
+            (path(), inner.clone()).prop_map(|(path, node)| {
+                PlanNode::Flatten(FlattenNode {
+                    path,
+                    node: Box::new(node),
+                })
+            }),
+            (
+                primary(inner.clone()),
+                prop::collection::vec(deferred_node(inner.clone()), 0..3)
+            )
+                .prop_map(|(primary, deferred)| PlanNode::Defer { primary, deferred }),
+            (subscription_node(), prop::option::of(inner.clone())).prop_map(|(primary, rest)| {
+                PlanNode::Subscription {
+                    primary,
+                    rest: rest.map(Box::new),
+                }
+            }),
+            (
+                prop::sample::select(VARIABLE_NAMES),
+                prop::option::of(inner.clone()),
+                prop::option::of(inner)
+            )
+                .prop_map(|(condition, if_clause, else_clause)| PlanNode::Condition {
+                    condition: condition.to_string(),
+                    if_clause: if_clause.map(Box::new),
+                    else_clause: else_clause.map(Box::new),
+                }),
+        ]
+    })
+}
+
+/// Reorders everything the comparator treats as unordered, which yields an equivalent plan.
+pub fn reordered(node: &PlanNode) -> PlanNode {
+    match node {
+        PlanNode::Sequence { nodes } => PlanNode::Sequence {
+            nodes: nodes.iter().map(reordered).collect(),
+        },
+        PlanNode::Parallel { nodes } => PlanNode::Parallel {
+            nodes: nodes.iter().rev().map(reordered).collect(),
+        },
+        PlanNode::Fetch(fetch) => {
+            let mut fetch = fetch.clone();
+            fetch.variable_usages.reverse();
+            fetch.requires.reverse();
+            for rewrites in [
+                &mut fetch.input_rewrites,
+                &mut fetch.output_rewrites,
+                &mut fetch.context_rewrites,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                rewrites.reverse();
+            }
+            PlanNode::Fetch(fetch)
+        }
+        PlanNode::Flatten(flatten) => PlanNode::Flatten(FlattenNode {
+            path: flatten.path.clone(),
+            node: Box::new(reordered(&flatten.node)),
+        }),
+        _ => node.clone(),
+    }
+}
+
+//==================================================================================================
+// `Arbitrary` impls
+
+// `Selection` is a federation type, so `requires()` can't back an `Arbitrary` impl.
+macro_rules! impl_arbitrary {
+    ($($ty:ty => $strategy:expr,)*) => {$(
+        impl Arbitrary for $ty {
+            type Parameters = ();
+            type Strategy = BoxedStrategy<Self>;
+
+            fn arbitrary_with((): ()) -> Self::Strategy {
+                $strategy.boxed()
+            }
+        }
+    )*};
+}
+
+impl_arbitrary! {
+    PlanNode => plan_node(),
+    FetchNode => fetch_node(),
+    FlattenNode => (path(), plan_node()).prop_map(|(path, node)| FlattenNode {
+        path,
+        node: Box::new(node),
+    }),
+    SubscriptionNode => subscription_node(),
+    Primary => primary(plan_node().boxed()),
+    DeferredNode => deferred_node(plan_node().boxed()),
+    DataRewrite => data_rewrite(),
+    Path => path(),
+    PathElement => path_element(),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depth(node: &PlanNode) -> usize {
+        let children: Vec<&PlanNode> = match node {
+            PlanNode::Sequence { nodes } | PlanNode::Parallel { nodes } => nodes.iter().collect(),
+            PlanNode::Fetch(_) => Vec::new(),
+            PlanNode::Flatten(flatten) => vec![&*flatten.node],
+            PlanNode::Defer { primary, deferred } => primary
+                .node
+                .as_deref()
+                .into_iter()
+                .chain(
+                    deferred
+                        .iter()
+                        .filter_map(|deferred| deferred.node.as_deref()),
+                )
+                .collect(),
+            PlanNode::Subscription { rest, .. } => rest.as_deref().into_iter().collect(),
+            PlanNode::Condition {
+                if_clause,
+                else_clause,
+                ..
+            } => if_clause
+                .as_deref()
+                .into_iter()
+                .chain(else_clause.as_deref())
+                .collect(),
+        };
+        1 + children.into_iter().map(depth).max().unwrap_or(0)
+    }
+
+    proptest! {
+        #[test]
+        fn test_generated_plans_are_bounded(node in any::<PlanNode>()) {
+            prop_assert!(depth(&node) <= 5, "{node:?}");
+        }
+    }
+}
@@ -89,14 +89,18 @@ impl From<&'_ Box<next::FetchNode>> for plan::PlanNode {
 impl From<&'_ next::SequenceNode> for plan::PlanNode {
     fn from(value: &'_ next::SequenceNode) -> Self {
         let next::SequenceNode { nodes } = value;
-        Self::Sequence { nodes: vec(nodes) }
+        Self::Sequence {
+            nodes: splice_nested_sequences(vec(nodes)),
+        }
     }
 }
 
 impl From<&'_ next::ParallelNode> for plan::PlanNode {
     fn from(value: &'_ next::ParallelNode) -> Self {
         let next::ParallelNode { nodes } = value;
-        Self::Parallel { nodes: vec(nodes) }
+        Self::Parallel {
+            nodes: splice_nested_parallels(vec(nodes)),
+        }
     }
 }
 
@@ -291,3 +295,75 @@ where
         Some(vec(value))
     }
 }
+
+// The legacy planner never nests a `Sequence` directly in a `Sequence`, nor a `Parallel` in a
+// `Parallel`. Since both are associative, such children are spliced into their parent. A
+// `Sequence` in a `Parallel` (or vice versa) is kept. Children are converted first, so they are
+// already flat.
+fn splice_nested_sequences(nodes: Vec<plan::PlanNode>) -> Vec<plan::PlanNode> {
+    nodes
+        .into_iter()
+        .flat_map(|node| match node {
+            plan::PlanNode::Sequence { nodes } => nodes,
+            node => vec![node],
+        })
+        .collect()
+}
+
+fn splice_nested_parallels(nodes: Vec<plan::PlanNode>) -> Vec<plan::PlanNode> {
+    nodes
+        .into_iter()
+        .flat_map(|node| match node {
+            plan::PlanNode::Parallel { nodes } => nodes,
+            node => vec![node],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod splice_tests {
+    use super::*;
+    use crate::router::plan::PlanNode;
+    use crate::router::testing::nodes_mut;
+    use crate::router::testing::parallel_plan;
+
+    fn children(mut node: PlanNode) -> Vec<PlanNode> {
+        std::mem::take(nodes_mut(&mut node))
+    }
+
+    #[test]
+    fn test_nested_sequences_are_spliced() {
+        let [fetch, parallel] = <[PlanNode; 2]>::try_from(children(parallel_plan())).unwrap();
+        let nested = vec![
+            PlanNode::Sequence {
+                nodes: vec![fetch.clone()],
+            },
+            parallel.clone(),
+        ];
+        assert_eq!(splice_nested_sequences(nested), [fetch, parallel]);
+    }
+
+    #[test]
+    fn test_nested_parallels_are_spliced() {
+        let [_, parallel] = <[PlanNode; 2]>::try_from(children(parallel_plan())).unwrap();
+        let [flatten, condition] = <[PlanNode; 2]>::try_from(children(parallel)).unwrap();
+        let nested = vec![
+            PlanNode::Parallel {
+                nodes: vec![flatten.clone()],
+            },
+            condition.clone(),
+        ];
+        assert_eq!(splice_nested_parallels(nested), [flatten, condition]);
+    }
+
+    #[test]
+    fn test_different_kinds_are_not_spliced() {
+        let [fetch, parallel] = <[PlanNode; 2]>::try_from(children(parallel_plan())).unwrap();
+        let nodes = vec![fetch, parallel];
+        assert_eq!(splice_nested_sequences(nodes.clone()), nodes);
+        let sequence = PlanNode::Sequence {
+            nodes: nodes.clone(),
+        };
+        assert_eq!(splice_nested_parallels(vec![sequence.clone()]), [sequence]);
+    }
+}
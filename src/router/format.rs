@@ -0,0 +1,431 @@
+// Renders plan trees in a pseudo-GraphQL form, close to the planners' formatted query plans.
+//
+// This is a reading aid: rewrites and variable usages are left out. The JSON form of a plan has
+// every detail.
+
+use std::sync::Arc;
+
+use apollo_compiler::ast;
+use apollo_federation::query_plan::requires_selection::Selection;
+use apollo_federation::query_plan::serializable_document::SerializableDocument;
+
+use super::DeferredNode;
+use super::FetchNode;
+use super::OperationKind;
+use super::PlanNode;
+use super::Primary;
+use super::SubscriptionNode;
+
+/// Renders a plan tree as `QueryPlan { ... }`, like the planners' formatted query plans.
+pub fn format_plan(node: Option<&PlanNode>) -> String {
+    write_plan(node, false)
+}
+
+/// Like `format_plan`, annotating each fetch with a comment summarizing it: its operation kind,
+/// the entities it resolves with their keys (from `requires`), and the variables it uses.
+pub fn format_plan_explained(node: Option<&PlanNode>) -> String {
+    write_plan(node, true)
+}
+
+fn write_plan(node: Option<&PlanNode>, explain: bool) -> String {
+    let mut writer = Writer {
+        explain,
+        ..Default::default()
+    };
+    writer.open("QueryPlan");
+    if let Some(node) = node {
+        writer.node(node, "");
+    }
+    writer.close("");
+    writer.output
+}
+
+#[derive(Default)]
+struct Writer {
+    output: String,
+    indent: usize,
+    /// Annotate fetches with `explanation`.
+    explain: bool,
+}
+
+impl Writer {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.output.push_str("  ");
+        }
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+
+    fn open(&mut self, header: &str) {
+        if header.is_empty() {
+            self.line("{");
+        } else {
+            self.line(&format!("{header} {{"));
+        }
+        self.indent += 1;
+    }
+
+    fn close(&mut self, suffix: &str) {
+        self.indent -= 1;
+        self.line(&format!("}}{suffix}"));
+    }
+
+    // Writes a multi-line text, like a subgraph operation, at the current indentation.
+    fn text(&mut self, text: &str) {
+        for line in text.trim_end().lines() {
+            self.line(line);
+        }
+    }
+
+    fn node(&mut self, node: &PlanNode, suffix: &str) {
+        match node {
+            PlanNode::Sequence { nodes } | PlanNode::Parallel { nodes } => {
+                self.children(node.kind_name(), nodes, suffix)
+            }
+            PlanNode::Fetch(fetch) => self.fetch(fetch, suffix),
+            PlanNode::Flatten(flatten) => {
+                self.open(&format!("Flatten(path: \"{}\")", flatten.path));
+                self.node(&flatten.node, "");
+                self.close(suffix);
+            }
+            PlanNode::Defer { primary, deferred } => {
+                self.open(node.kind_name());
+                self.primary(primary);
+                for deferred in deferred {
+                    self.deferred(deferred);
+                }
+                self.close(suffix);
+            }
+            PlanNode::Subscription { primary, rest } => {
+                self.open(node.kind_name());
+                self.open("Primary:");
+                self.subscription(primary);
+                self.close(",");
+                if let Some(rest) = rest {
+                    self.open("Rest:");
+                    self.node(rest, "");
+                    self.close(",");
+                }
+                self.close(suffix);
+            }
+            PlanNode::Condition {
+                condition,
+                if_clause,
+                else_clause,
+            } => match (if_clause, else_clause) {
+                (Some(if_clause), None) => {
+                    self.open(&format!("Include(if: ${condition})"));
+                    self.node(if_clause, "");
+                    self.close(suffix);
+                }
+                (None, Some(else_clause)) => {
+                    self.open(&format!("Skip(if: ${condition})"));
+                    self.node(else_clause, "");
+                    self.close(suffix);
+                }
+                (if_clause, else_clause) => {
+                    self.open(&format!("Condition(if: ${condition})"));
+                    if let Some(if_clause) = if_clause {
+                        self.node(if_clause, "");
+                    }
+                    self.indent -= 1;
+                    self.open("} Else");
+                    if let Some(else_clause) = else_clause {
+                        self.node(else_clause, "");
+                    }
+                    self.close(suffix);
+                }
+            },
+        }
+    }
+
+    fn children(&mut self, kind: &str, nodes: &[PlanNode], suffix: &str) {
+        self.open(kind);
+        for node in nodes {
+            self.node(node, ",");
+        }
+        self.close(suffix);
+    }
+
+    fn fetch(&mut self, fetch: &FetchNode, suffix: &str) {
+        let header = match &fetch.id {
+            Some(id) => format!("Fetch(service: \"{}\", id: {id})", fetch.service_name),
+            None => format!("Fetch(service: \"{}\")", fetch.service_name),
+        };
+        self.open(&header);
+        if self.explain {
+            self.line(&explanation(
+                fetch.operation_kind,
+                &fetch.requires,
+                &fetch.variable_usages,
+            ));
+        }
+        if !fetch.requires.is_empty() {
+            self.open("");
+            self.selections(&fetch.requires);
+            self.close(" =>");
+        }
+        self.document(&fetch.operation);
+        self.close(suffix);
+    }
+
+    fn subscription(&mut self, primary: &SubscriptionNode) {
+        self.open(&format!("Fetch(service: \"{}\")", primary.service_name));
+        if self.explain {
+            self.line(&explanation(
+                primary.operation_kind,
+                &[],
+                &primary.variable_usages,
+            ));
+        }
+        self.document(&primary.operation);
+        self.close("");
+    }
+
+    fn primary(&mut self, primary: &Primary) {
+        self.open("Primary");
+        if let Some(subselection) = &primary.subselection {
+            self.text(&pretty_document(subselection));
+        }
+        if let Some(node) = &primary.node {
+            self.node(node, "");
+        }
+        self.close(",");
+    }
+
+    fn deferred(&mut self, deferred: &DeferredNode) {
+        let depends: Vec<&str> = deferred.depends.iter().map(|d| d.id.as_str()).collect();
+        let mut header = format!(
+            "Deferred(depends: [{}], path: \"{}\"",
+            depends.join(", "),
+            deferred.query_path
+        );
+        if let Some(label) = &deferred.label {
+            header.push_str(&format!(", label: \"{label}\""));
+        }
+        header.push(')');
+        self.open(&header);
+        if let Some(subselection) = &deferred.subselection {
+            self.text(&pretty_document(subselection));
+        }
+        if let Some(node) = &deferred.node {
+            self.node(node, "");
+        }
+        self.close(",");
+    }
+
+    fn document(&mut self, document: &SerializableDocument) {
+        self.text(&pretty_document(document.as_serialized()));
+    }
+
+    fn selections(&mut self, selections: &[Selection]) {
+        for selection in selections {
+            match selection {
+                Selection::Field(field) => {
+                    let name = match &field.alias {
+                        Some(alias) => format!("{alias}: {}", field.name),
+                        None => field.name.to_string(),
+                    };
+                    if field.selections.is_empty() {
+                        self.line(&name);
+                    } else {
+                        self.open(&name);
+                        self.selections(&field.selections);
+                        self.close("");
+                    }
+                }
+                Selection::InlineFragment(fragment) => {
+                    match &fragment.type_condition {
+                        Some(type_condition) => self.open(&format!("... on {type_condition}")),
+                        None => self.open("..."),
+                    }
+                    self.selections(&fragment.selections);
+                    self.close("");
+                }
+            }
+        }
+    }
+}
+
+// A one-line comment summarizing a fetch, e.g. `# query; resolves Product { upc }; uses
+// $representations`. Entities are written with their key fields, leaving out `__typename`.
+fn explanation(
+    operation_kind: OperationKind,
+    requires: &[Selection],
+    variable_usages: &[Arc<str>],
+) -> String {
+    let operation_kind = match operation_kind {
+        OperationKind::Query => "query",
+        OperationKind::Mutation => "mutation",
+        OperationKind::Subscription => "subscription",
+    };
+    let mut parts = vec![operation_kind.to_string()];
+    if !requires.is_empty() {
+        let entities: Vec<String> = requires
+            .iter()
+            .map(|selection| match selection {
+                Selection::InlineFragment(fragment) => {
+                    let keys = compact_selections(&fragment.selections);
+                    match &fragment.type_condition {
+                        Some(type_condition) => format!("{type_condition} {{ {keys} }}"),
+                        None => format!("{{ {keys} }}"),
+                    }
+                }
+                Selection::Field(_) => compact_selections(std::slice::from_ref(selection)),
+            })
+            .collect();
+        parts.push(format!("resolves {}", entities.join(", ")));
+    }
+    if !variable_usages.is_empty() {
+        let variables: Vec<String> = variable_usages
+            .iter()
+            .map(|variable| format!("${variable}"))
+            .collect();
+        parts.push(format!("uses {}", variables.join(", ")));
+    }
+    format!("# {}", parts.join("; "))
+}
+
+// Writes selections on a single line, without unaliased `__typename` fields.
+fn compact_selections(selections: &[Selection]) -> String {
+    let mut fields = Vec::new();
+    for selection in selections {
+        match selection {
+            Selection::Field(field)
+                if field.alias.is_none() && field.name.as_str() == "__typename" => {}
+            Selection::Field(field) => {
+                let mut text = match &field.alias {
+                    Some(alias) => format!("{alias}: {}", field.name),
+                    None => field.name.to_string(),
+                };
+                if !field.selections.is_empty() {
+                    text.push_str(&format!(" {{ {} }}", compact_selections(&field.selections)));
+                }
+                fields.push(text);
+            }
+            Selection::InlineFragment(fragment) => {
+                let selections = compact_selections(&fragment.selections);
+                fields.push(match &fragment.type_condition {
+                    Some(type_condition) => format!("... on {type_condition} {{ {selections} }}"),
+                    None => format!("... {{ {selections} }}"),
+                });
+            }
+        }
+    }
+    fields.join(" ")
+}
+
+// Documents (and subselections) failing to parse are written as is.
+fn pretty_document(source: &str) -> String {
+    match ast::Document::parse(source, "operation.graphql") {
+        Ok(document) => document.to_string(),
+        Err(_) => source.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::router::testing::defer_plan;
+
+    #[test]
+    fn test_format_plan() {
+        let node: PlanNode = serde_json::from_value(json!({
+            "kind": "Sequence",
+            "nodes": [
+                {
+                    "kind": "Fetch",
+                    "serviceName": "products",
+                    "variableUsages": [],
+                    "operation": "{ topProducts { __typename upc } }",
+                    "operationKind": "query",
+                },
+                {
+                    "kind": "Flatten",
+                    "path": ["topProducts", "@"],
+                    "node": {
+                        "kind": "Fetch",
+                        "serviceName": "reviews",
+                        "requires": [{
+                            "kind": "InlineFragment",
+                            "typeCondition": "Product",
+                            "selections": [
+                                { "kind": "Field", "name": "__typename" },
+                                { "kind": "Field", "name": "upc" },
+                            ],
+                        }],
+                        "variableUsages": [],
+                        "operation": "query($representations: [_Any!]!) { _entities(representations: $representations) { ... on Product { reviews { body } } } }",
+                        "operationKind": "query",
+                    },
+                },
+            ],
+        }))
+        .unwrap();
+        let expected = r#"QueryPlan {
+  Sequence {
+    Fetch(service: "products") {
+      {
+        topProducts {
+          __typename
+          upc
+        }
+      }
+    },
+    Flatten(path: "/topProducts/@") {
+      Fetch(service: "reviews") {
+        {
+          ... on Product {
+            __typename
+            upc
+          }
+        } =>
+        query($representations: [_Any!]!) {
+          _entities(representations: $representations) {
+            ... on Product {
+              reviews {
+                body
+              }
+            }
+          }
+        }
+      }
+    },
+  }
+}
+"#;
+        assert_eq!(format_plan(Some(&node)), expected);
+    }
+
+    #[test]
+    fn test_format_plan_explained() {
+        let node = defer_plan();
+        let explained = format_plan_explained(Some(&node));
+        assert!(
+            explained
+                .contains("Fetch(service: \"products\", id: 0) {\n        # query\n        {\n"),
+            "{explained}"
+        );
+        assert!(
+            explained.contains(
+                "Fetch(service: \"reviews\") {\n          \
+                 # query; resolves Product { upc }; uses $representations\n"
+            ),
+            "{explained}"
+        );
+        // Only the annotations differ.
+        let unexplained: Vec<&str> = explained
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .collect();
+        assert_eq!(unexplained.join("\n") + "\n", format_plan(Some(&node)));
+    }
+
+    #[test]
+    fn test_format_empty_plan() {
+        assert_eq!(format_plan(None), "QueryPlan {\n}\n");
+    }
+}
@@ -0,0 +1,1024 @@
+// Rewrites applied to both plan trees before comparison, erasing the differences that
+// `CompareOptions` asks to ignore.
+//
+// Unlike normalization, these rewrites change the meaning of a plan. They are only applied when
+// explicitly requested.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use apollo_compiler::Name;
+use apollo_compiler::ast;
+use apollo_federation::query_plan::requires_selection::Selection;
+use apollo_federation::query_plan::serializable_document::SerializableDocument;
+
+use super::DeferredNode;
+use super::Depends;
+use super::FetchNode;
+use super::FlattenNode;
+use super::OperationKind;
+use super::PlanNode;
+use super::Primary;
+use super::SubscriptionNode;
+use super::normalize::plan_fingerprint;
+use super::plan_compare::CompareOptions;
+use super::plan_compare::collect_fetches;
+use super::semantic::normalize_operation;
+use super::semantic::normalize_operation_against;
+
+const TYPENAME: &str = "__typename";
+
+/// Returns the plan tree with every difference ignored by `options` erased. The tree is only
+/// copied when some difference is ignored.
+pub(crate) fn apply_compare_options<'a>(
+    node: &'a PlanNode,
+    options: &CompareOptions,
+) -> Cow<'a, PlanNode> {
+    let mut node = Cow::Borrowed(node);
+    if options.assume_independent_fetches {
+        node = Cow::Owned(parallelize_independent_fetches(&node));
+    }
+    if options.negated_conditions {
+        node = Cow::Owned(resolve_negated_conditions(&node));
+    }
+    // Fetch contents are erased entirely, so the other options have nothing left to erase, except
+    // for fetch ids in `Defer` nodes, erased while the fetches still identify them.
+    if options.shape_only {
+        if options.ignore_identifiers {
+            node = Cow::Owned(erase_identifiers(&node));
+        }
+        return Cow::Owned(map_leaves(&node, &blank_fetch, &blank_subscription));
+    }
+    // Before stripping `__typename`, which identifies the fragments to unwrap.
+    if options.semantic_operations && options.semantic_requires {
+        node = Cow::Owned(map_leaves(
+            &node,
+            &unwrap_requires_fragments,
+            &SubscriptionNode::clone,
+        ));
+    }
+    if options.ignore_typename {
+        node = Cow::Owned(map_leaves(
+            &node,
+            &strip_fetch_typename,
+            &strip_subscription_typename,
+        ));
+    }
+    if options.ignore_requires {
+        node = Cow::Owned(map_leaves(&node, &clear_requires, &SubscriptionNode::clone));
+    }
+    if options.semantic_operations || !options.subgraph_schemas.is_empty() {
+        let normalize = |service_name: &str, operation: &SerializableDocument| {
+            let stripped;
+            let operation = if options.semantic_operations && options.ignore_operation_typename {
+                stripped = strip_document_typename(operation);
+                &stripped
+            } else {
+                operation
+            };
+            let schema = options.subgraph_schemas.get(service_name);
+            match schema.and_then(|schema| normalize_operation_against(operation, schema)) {
+                Some(normalized) => normalized,
+                None if options.semantic_operations => normalize_operation(operation),
+                None => operation.clone(),
+            }
+        };
+        node = Cow::Owned(map_leaves(
+            &node,
+            &|fetch| FetchNode {
+                operation: normalize(&*fetch.service_name, &fetch.operation),
+                ..fetch.clone()
+            },
+            &|primary| SubscriptionNode {
+                operation: normalize(&*primary.service_name, &primary.operation),
+                ..primary.clone()
+            },
+        ));
+    }
+    // Last, since fetch ids are derived from the fetches as rewritten by the other options.
+    if options.ignore_identifiers {
+        node = Cow::Owned(erase_identifiers(&node));
+    }
+    node
+}
+
+// Rebuilds a plan tree, rewriting its fetch nodes and subscription primaries.
+pub(super) fn map_leaves(
+    node: &PlanNode,
+    fetch: &impl Fn(&FetchNode) -> FetchNode,
+    subscription: &impl Fn(&SubscriptionNode) -> SubscriptionNode,
+) -> PlanNode {
+    match map_children(node, &|child| map_leaves(child, fetch, subscription)) {
+        PlanNode::Fetch(node) => PlanNode::Fetch(fetch(&node)),
+        PlanNode::Subscription { primary, rest } => PlanNode::Subscription {
+            primary: subscription(&primary),
+            rest,
+        },
+        node => node,
+    }
+}
+
+// Rebuilds a plan node, rewriting its children.
+fn map_children(node: &PlanNode, map: &impl Fn(&PlanNode) -> PlanNode) -> PlanNode {
+    match node {
+        PlanNode::Sequence { nodes } => PlanNode::Sequence {
+            nodes: nodes.iter().map(map).collect(),
+        },
+        PlanNode::Parallel { nodes } => PlanNode::Parallel {
+            nodes: nodes.iter().map(map).collect(),
+        },
+        PlanNode::Fetch(_) => node.clone(),
+        PlanNode::Flatten(flatten) => PlanNode::Flatten(FlattenNode {
+            path: flatten.path.clone(),
+            node: Box::new(map(&flatten.node)),
+        }),
+        PlanNode::Defer { primary, deferred } => PlanNode::Defer {
+            primary: Primary {
+                subselection: primary.subselection.clone(),
+                node: primary.node.as_deref().map(map).map(Box::new),
+            },
+            deferred: deferred
+                .iter()
+                .map(|deferred| DeferredNode {
+                    node: deferred.node.as_deref().map(map).map(Arc::new),
+                    ..deferred.clone()
+                })
+                .collect(),
+        },
+        PlanNode::Subscription { primary, rest } => PlanNode::Subscription {
+            primary: primary.clone(),
+            rest: rest.as_deref().map(map).map(Box::new),
+        },
+        PlanNode::Condition {
+            condition,
+            if_clause,
+            else_clause,
+        } => PlanNode::Condition {
+            condition: condition.clone(),
+            if_clause: if_clause.as_deref().map(map).map(Box::new),
+            else_clause: else_clause.as_deref().map(map).map(Box::new),
+        },
+    }
+}
+
+fn clear_requires(fetch: &FetchNode) -> FetchNode {
+    FetchNode {
+        requires: Vec::new(),
+        ..fetch.clone()
+    }
+}
+
+// Note: Fetch ids are erased as well, though `DeferredNode::depends` still refers to them.
+fn blank_fetch(_: &FetchNode) -> FetchNode {
+    FetchNode {
+        service_name: "".into(),
+        requires: Vec::new(),
+        variable_usages: Vec::new(),
+        operation: empty_document(),
+        operation_name: None,
+        operation_kind: OperationKind::default(),
+        id: None,
+        input_rewrites: None,
+        output_rewrites: None,
+        context_rewrites: None,
+    }
+}
+
+fn blank_subscription(_: &SubscriptionNode) -> SubscriptionNode {
+    SubscriptionNode {
+        service_name: "".into(),
+        variable_usages: Vec::new(),
+        operation: empty_document(),
+        operation_name: None,
+        operation_kind: OperationKind::default(),
+        input_rewrites: None,
+        output_rewrites: None,
+    }
+}
+
+fn empty_document() -> SerializableDocument {
+    serde_json::from_value(serde_json::Value::String(String::new()))
+        .expect("serialized documents deserialize from strings")
+}
+
+//==================================================================================================
+// Independent fetches
+
+// Rewrites the `Sequence` nodes whose children are all independent into `Parallel` nodes, which
+// compare regardless of the order of their children.
+fn parallelize_independent_fetches(node: &PlanNode) -> PlanNode {
+    match map_children(node, &parallelize_independent_fetches) {
+        PlanNode::Sequence { nodes } if nodes.len() > 1 && nodes.iter().all(is_independent) => {
+            PlanNode::Parallel { nodes }
+        }
+        node => node,
+    }
+}
+
+// Heuristic: a node is independent of the nodes before it when it only fetches from the root of
+// the operation, i.e. it's made of fetches without `requires` or context rewrites. `Flatten` nodes
+// always depend on prior results, and other nodes are conservatively assumed to. Mutation fetches
+// are never independent, since mutation fields execute serially.
+fn is_independent(node: &PlanNode) -> bool {
+    match node {
+        PlanNode::Fetch(fetch) => {
+            fetch.requires.is_empty()
+                && fetch.context_rewrites.as_ref().is_none_or(Vec::is_empty)
+                && fetch.operation_kind != OperationKind::Mutation
+        }
+        PlanNode::Sequence { nodes } | PlanNode::Parallel { nodes } => {
+            nodes.iter().all(is_independent)
+        }
+        PlanNode::Flatten(_)
+        | PlanNode::Defer { .. }
+        | PlanNode::Subscription { .. }
+        | PlanNode::Condition { .. } => false,
+    }
+}
+
+//==================================================================================================
+// Identifiers
+
+// Erases operation names and defer labels, and replaces fetch ids with keys derived from the
+// fetches themselves, rewriting `DeferredNode::depends` to match. Plans numbering their fetches
+// differently then compare equal, while a deferred node depending on a different fetch is still
+// reported. Dependencies on unknown ids are kept as is.
+//
+// Note: Identical fetches get the same key, so depending on either of them is the same.
+fn erase_identifiers(node: &PlanNode) -> PlanNode {
+    let mut fetches = Vec::new();
+    collect_fetches(node, &mut fetches);
+    let keys: HashMap<&str, String> = fetches
+        .iter()
+        .filter_map(|fetch| Some((fetch.id.as_deref()?, fetch_key(fetch))))
+        .collect();
+    let rename = |id: &str| keys.get(id).cloned().unwrap_or_else(|| id.to_string());
+    let node = map_leaves(
+        node,
+        &|fetch| FetchNode {
+            id: fetch.id.as_deref().map(rename),
+            operation_name: None,
+            ..fetch.clone()
+        },
+        &|primary| SubscriptionNode {
+            operation_name: None,
+            ..primary.clone()
+        },
+    );
+    rename_depends(&node, &rename)
+}
+
+// The service and fingerprint of a fetch without its identifiers, independent of its position in
+// the plan (which the planners may order differently within `Parallel` nodes).
+fn fetch_key(fetch: &FetchNode) -> String {
+    let anonymous = PlanNode::Fetch(FetchNode {
+        id: None,
+        operation_name: None,
+        ..fetch.clone()
+    });
+    format!(
+        "{}:{:016x}",
+        fetch.service_name,
+        plan_fingerprint(&anonymous)
+    )
+}
+
+fn rename_depends(node: &PlanNode, rename: &impl Fn(&str) -> String) -> PlanNode {
+    match map_children(node, &|child| rename_depends(child, rename)) {
+        PlanNode::Defer { primary, deferred } => PlanNode::Defer {
+            primary,
+            deferred: deferred
+                .into_iter()
+                .map(|deferred| DeferredNode {
+                    depends: deferred
+                        .depends
+                        .iter()
+                        .map(|depends| Depends {
+                            id: rename(&depends.id),
+                        })
+                        .collect(),
+                    label: None,
+                    ..deferred
+                })
+                .collect(),
+        },
+        node => node,
+    }
+}
+
+//==================================================================================================
+// Negated conditions
+
+const NEGATION: char = '!';
+
+// Rewrites conditions on negated variables (`!x`, or `!!!x`) to conditions on the variables,
+// swapping their branches for an odd number of negations.
+fn resolve_negated_conditions(node: &PlanNode) -> PlanNode {
+    match map_children(node, &resolve_negated_conditions) {
+        PlanNode::Condition {
+            condition,
+            if_clause,
+            else_clause,
+        } => {
+            let variable = condition.trim_start_matches(NEGATION);
+            let negated = (condition.len() - variable.len()) % 2 == 1;
+            let (if_clause, else_clause) = if negated {
+                (else_clause, if_clause)
+            } else {
+                (if_clause, else_clause)
+            };
+            PlanNode::Condition {
+                condition: variable.to_string(),
+                if_clause,
+                else_clause,
+            }
+        }
+        node => node,
+    }
+}
+
+//==================================================================================================
+// Equivalent `requires` encodings
+
+fn unwrap_requires_fragments(fetch: &FetchNode) -> FetchNode {
+    let mut fetch = fetch.clone();
+    unwrap_key_fragments(&mut fetch.requires);
+    fetch
+}
+
+// Note: Only the selection sets of fields are unwrapped, not the top-level selections, whose type
+//       conditions select the entities to fetch.
+fn unwrap_key_fragments(selections: &mut [Selection]) {
+    for selection in selections {
+        match selection {
+            Selection::Field(field) => {
+                while let Some(unwrapped) = key_fragment_selections(&field.selections) {
+                    field.selections = unwrapped;
+                }
+                unwrap_key_fragments(&mut field.selections);
+            }
+            Selection::InlineFragment(fragment) => unwrap_key_fragments(&mut fragment.selections),
+        }
+    }
+}
+
+// The selections of a selection set made only of an inline fragment with a type condition, if it
+// selects `__typename`.
+fn key_fragment_selections(selections: &[Selection]) -> Option<Vec<Selection>> {
+    let [Selection::InlineFragment(fragment)] = selections else {
+        return None;
+    };
+    let selects_typename = fragment.selections.iter().any(|selection| {
+        matches!(selection, Selection::Field(field)
+            if is_unaliased_typename(field.alias.as_ref(), &field.name))
+    });
+    (fragment.type_condition.is_some() && selects_typename).then(|| fragment.selections.clone())
+}
+
+//==================================================================================================
+// `__typename` stripping
+
+// Note: Each top-level `requires` selection describes an entity representation, whose `__typename`
+//       is needed to resolve the entity. Those are kept, so that a planner dropping them is still
+//       reported. Aliased `__typename` fields are kept as well, since they are part of the
+//       response.
+fn strip_fetch_typename(fetch: &FetchNode) -> FetchNode {
+    let mut fetch = fetch.clone();
+    for selection in &mut fetch.requires {
+        match selection {
+            Selection::Field(field) => strip_requires_typename(&mut field.selections),
+            Selection::InlineFragment(fragment) => {
+                for selection in &mut fragment.selections {
+                    if let Selection::Field(field) = selection {
+                        strip_requires_typename(&mut field.selections);
+                    }
+                }
+            }
+        }
+    }
+    fetch.operation = strip_document_typename(&fetch.operation);
+    fetch
+}
+
+fn strip_subscription_typename(primary: &SubscriptionNode) -> SubscriptionNode {
+    let mut primary = primary.clone();
+    primary.operation = strip_document_typename(&primary.operation);
+    primary
+}
+
+fn is_unaliased_typename(alias: Option<&Name>, name: &Name) -> bool {
+    alias.is_none() && name.as_str() == TYPENAME
+}
+
+fn strip_requires_typename(selections: &mut Vec<Selection>) {
+    selections.retain(|selection| match selection {
+        Selection::Field(field) => !is_unaliased_typename(field.alias.as_ref(), &field.name),
+        Selection::InlineFragment(_) => true,
+    });
+    for selection in selections {
+        match selection {
+            Selection::Field(field) => strip_requires_typename(&mut field.selections),
+            Selection::InlineFragment(fragment) => {
+                strip_requires_typename(&mut fragment.selections)
+            }
+        }
+    }
+}
+
+// Documents failing to parse are returned unchanged, so the comparator reports them as usual.
+fn strip_document_typename(document: &SerializableDocument) -> SerializableDocument {
+    let Ok(mut ast) = ast::Document::parse(document.as_serialized(), "operation.graphql") else {
+        return document.clone();
+    };
+    for definition in &mut ast.definitions {
+        match definition {
+            ast::Definition::OperationDefinition(operation) => {
+                strip_selection_set_typename(&mut operation.make_mut().selection_set);
+            }
+            ast::Definition::FragmentDefinition(fragment) => {
+                strip_selection_set_typename(&mut fragment.make_mut().selection_set);
+            }
+            _ => {}
+        }
+    }
+    serde_json::from_value(serde_json::Value::String(ast.to_string()))
+        .expect("serialized documents deserialize from strings")
+}
+
+fn strip_selection_set_typename(selections: &mut Vec<ast::Selection>) {
+    selections.retain(|selection| match selection {
+        ast::Selection::Field(field) => !is_unaliased_typename(field.alias.as_ref(), &field.name),
+        _ => true,
+    });
+    for selection in selections {
+        match selection {
+            ast::Selection::Field(field) => {
+                strip_selection_set_typename(&mut field.make_mut().selection_set);
+            }
+            ast::Selection::InlineFragment(fragment) => {
+                strip_selection_set_typename(&mut fragment.make_mut().selection_set);
+            }
+            ast::Selection::FragmentSpread(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod typename_tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::router::testing::matches_with;
+
+    fn fetch(requires_upc_typename: bool, operation: &str) -> PlanNode {
+        let mut product_fields = vec![json!({ "kind": "Field", "name": "upc" })];
+        if requires_upc_typename {
+            product_fields.push(json!({ "kind": "Field", "name": "__typename" }));
+        }
+        serde_json::from_value(json!({
+            "kind": "Fetch",
+            "serviceName": "reviews",
+            "requires": [{
+                "kind": "InlineFragment",
+                "typeCondition": "Review",
+                "selections": [
+                    { "kind": "Field", "name": "__typename" },
+                    { "kind": "Field", "name": "product", "selections": product_fields },
+                ],
+            }],
+            "variableUsages": [],
+            "operation": operation,
+            "operationKind": "query",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_ignore_typename() {
+        let this = fetch(true, "{ topProducts { __typename upc } }");
+        let other = fetch(false, "{ topProducts { upc } }");
+        let options = CompareOptions {
+            ignore_typename: true,
+            ..Default::default()
+        };
+        assert!(!matches_with(&this, &other, &CompareOptions::default()));
+        assert!(matches_with(&this, &other, &options));
+    }
+
+    #[test]
+    fn test_ignore_typename_keeps_entity_representation_typename() {
+        let this = fetch(false, "{ topProducts { upc } }");
+        let PlanNode::Fetch(mut other) = this.clone() else {
+            unreachable!()
+        };
+        let Selection::InlineFragment(fragment) = &mut other.requires[0] else {
+            unreachable!()
+        };
+        fragment.selections.remove(0);
+        let options = CompareOptions {
+            ignore_typename: true,
+            ..Default::default()
+        };
+        assert!(!matches_with(&this, &PlanNode::Fetch(other), &options));
+    }
+
+    #[test]
+    fn test_ignore_typename_keeps_aliased_typename() {
+        let this = fetch(false, "{ topProducts { kind: __typename upc } }");
+        let other = fetch(false, "{ topProducts { upc } }");
+        let options = CompareOptions {
+            ignore_typename: true,
+            ..Default::default()
+        };
+        assert!(!matches_with(&this, &other, &options));
+    }
+}
+
+#[cfg(test)]
+mod requires_tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::router::testing::matches_with;
+    use crate::router::testing::parse_plan;
+
+    fn fetch(requires_field: &str) -> PlanNode {
+        serde_json::from_value(json!({
+            "kind": "Fetch",
+            "serviceName": "reviews",
+            "requires": [{
+                "kind": "InlineFragment",
+                "typeCondition": "Product",
+                "selections": [
+                    { "kind": "Field", "name": "__typename" },
+                    { "kind": "Field", "name": requires_field },
+                ],
+            }],
+            "variableUsages": [],
+            "operation": "{ topProducts { upc } }",
+            "operationKind": "query",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_ignore_requires() {
+        let options = CompareOptions {
+            ignore_requires: true,
+            ..Default::default()
+        };
+        assert!(matches_with(&fetch("upc"), &fetch("id"), &options));
+    }
+
+    #[test]
+    fn test_semantic_requires() {
+        let fragment = parse_plan(include_str!(
+            "../../tests/fixtures/plans/requires_fragment_key.json"
+        ));
+        let field = parse_plan(include_str!(
+            "../../tests/fixtures/plans/requires_field_key.json"
+        ));
+        let semantic_operations = CompareOptions {
+            semantic_operations: true,
+            ..Default::default()
+        };
+        assert!(!matches_with(&fragment, &field, &semantic_operations));
+        let options = CompareOptions {
+            semantic_requires: true,
+            ..semantic_operations
+        };
+        assert!(matches_with(&fragment, &field, &options));
+        // Only with semantic operations.
+        assert!(!matches_with(
+            &fragment,
+            &field,
+            &CompareOptions {
+                semantic_requires: true,
+                ..Default::default()
+            }
+        ));
+    }
+
+    #[test]
+    fn test_semantic_requires_keeps_other_fragments() {
+        let PlanNode::Fetch(fetch) = parse_plan(include_str!(
+            "../../tests/fixtures/plans/requires_fragment_key.json"
+        )) else {
+            unreachable!()
+        };
+        // The entity representation's fragment is kept.
+        let unwrapped = unwrap_requires_fragments(&fetch);
+        assert!(matches!(
+            unwrapped.requires.as_slice(),
+            [Selection::InlineFragment(_)]
+        ));
+        // So are fragments without `__typename`, and those next to other selections.
+        let Selection::InlineFragment(entity) = &fetch.requires[0] else {
+            unreachable!()
+        };
+        let Selection::Field(dimensions) = &entity.selections[2] else {
+            unreachable!()
+        };
+        let Selection::InlineFragment(key) = &dimensions.selections[0] else {
+            unreachable!()
+        };
+        let mut without_typename = key.clone();
+        without_typename.selections.remove(0);
+        let without_typename = [Selection::InlineFragment(without_typename)];
+        assert_eq!(key_fragment_selections(&without_typename), None);
+        let with_sibling = [
+            dimensions.selections[0].clone(),
+            entity.selections[1].clone(),
+        ];
+        assert_eq!(key_fragment_selections(&with_sibling), None);
+    }
+}
+
+#[cfg(test)]
+mod semantic_operations_tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::router::testing::matches_with;
+    use crate::router::testing::parse_plan;
+    use crate::subgraph::SubgraphSchemas;
+
+    fn fetch(operation: &str) -> PlanNode {
+        serde_json::from_value(json!({
+            "kind": "Fetch",
+            "serviceName": "reviews",
+            "variableUsages": [],
+            "operation": operation,
+            "operationKind": "query",
+        }))
+        .unwrap()
+    }
+
+    fn operations_match(this: &str, other: &str, options: &CompareOptions) -> bool {
+        matches_with(&fetch(this), &fetch(other), options)
+    }
+
+    #[test]
+    fn test_semantic_operations() {
+        let options = CompareOptions {
+            semantic_operations: true,
+            ..Default::default()
+        };
+        let this = "{ me { ... on User { name } id id } }";
+        let other = "{ me { id ... on User { name } } }";
+        assert!(!operations_match(this, other, &CompareOptions::default()));
+        assert!(operations_match(this, other, &options));
+        assert!(!operations_match(this, "{ me { id } }", &options));
+    }
+
+    #[test]
+    fn test_subgraph_schemas() {
+        let mut subgraph_schemas = SubgraphSchemas::default();
+        subgraph_schemas
+            .insert(
+                "reviews",
+                "type Query { me: User } type User { id: ID! name: String }",
+            )
+            .unwrap();
+        let options = CompareOptions {
+            subgraph_schemas,
+            ..Default::default()
+        };
+        // The type of `me` is known from the schema.
+        let this = "{ me { ... on User { name } id } }";
+        let other = "{ me { id name } }";
+        assert!(!operations_match(this, other, &CompareOptions::default()));
+        assert!(operations_match(this, other, &options));
+        // Invalid operations are compared without the schema.
+        assert!(!operations_match(
+            "{ me { ... on User { email } id } }",
+            "{ me { id email } }",
+            &options
+        ));
+    }
+
+    #[test]
+    fn test_semantic_operations_expand_fragments() {
+        let legacy = parse_plan(include_str!(
+            "../../tests/fixtures/plans/fragments_legacy.json"
+        ));
+        let native = parse_plan(include_str!(
+            "../../tests/fixtures/plans/fragments_native.json"
+        ));
+        let options = CompareOptions {
+            semantic_operations: true,
+            ..Default::default()
+        };
+        assert!(!matches_with(&legacy, &native, &CompareOptions::default()));
+        assert!(matches_with(&legacy, &native, &options));
+    }
+
+    #[test]
+    fn test_unparsable_operations_are_compared_as_strings() {
+        let options = CompareOptions {
+            semantic_operations: true,
+            ..Default::default()
+        };
+        assert!(operations_match("{ me { id }", "{ me { id }", &options));
+        assert!(!operations_match("{ me { id }", "{ me { name }", &options));
+    }
+
+    #[test]
+    fn test_ignore_operation_typename() {
+        let legacy = parse_plan(include_str!(
+            "../../tests/fixtures/plans/typename_legacy.json"
+        ));
+        let native = parse_plan(include_str!(
+            "../../tests/fixtures/plans/typename_native.json"
+        ));
+        let matches = |options: &CompareOptions| matches_with(&legacy, &native, options);
+        let options = CompareOptions {
+            semantic_operations: true,
+            ignore_operation_typename: true,
+            ..Default::default()
+        };
+        assert!(!matches(&CompareOptions {
+            semantic_operations: true,
+            ..Default::default()
+        }));
+        assert!(matches(&options));
+        // Only with semantic operations.
+        assert!(!matches(&CompareOptions {
+            ignore_operation_typename: true,
+            ..Default::default()
+        }));
+        // Aliased `__typename` fields are part of the response.
+        assert!(!operations_match(
+            "{ me { kind: __typename id } }",
+            "{ me { id } }",
+            &options
+        ));
+    }
+
+    #[test]
+    fn test_ignore_operation_typename_keeps_requires_typename() {
+        let options = CompareOptions {
+            semantic_operations: true,
+            ignore_operation_typename: true,
+            ..Default::default()
+        };
+        let fetch = |requires: serde_json::Value| -> PlanNode {
+            serde_json::from_value(json!({
+                "kind": "Fetch",
+                "serviceName": "reviews",
+                "requires": [{
+                    "kind": "InlineFragment",
+                    "typeCondition": "Review",
+                    "selections": [
+                        { "kind": "Field", "name": "__typename" },
+                        { "kind": "Field", "name": "product", "selections": requires },
+                    ],
+                }],
+                "variableUsages": [],
+                "operation": "{ topProducts { upc } }",
+                "operationKind": "query",
+            }))
+            .unwrap()
+        };
+        let this = fetch(json!([
+            { "kind": "Field", "name": "__typename" },
+            { "kind": "Field", "name": "upc" },
+        ]));
+        let other = fetch(json!([{ "kind": "Field", "name": "upc" }]));
+        assert!(!matches_with(&this, &other, &options));
+    }
+}
+
+#[cfg(test)]
+mod erasure_tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::router::testing::defer_plan;
+    use crate::router::testing::matches_with;
+
+    fn fetch(service_name: &str, operation: &str) -> serde_json::Value {
+        json!({
+            "kind": "Fetch",
+            "serviceName": service_name,
+            "variableUsages": [],
+            "operation": operation,
+            "operationKind": "query",
+        })
+    }
+
+    fn matches_shape(this: serde_json::Value, other: serde_json::Value) -> bool {
+        let options = CompareOptions {
+            shape_only: true,
+            ..Default::default()
+        };
+        let this: PlanNode = serde_json::from_value(this).unwrap();
+        let other: PlanNode = serde_json::from_value(other).unwrap();
+        matches_with(&this, &other, &options)
+    }
+
+    // The defer plan with its fetch numbered `id`, and a different label and operation name.
+    fn renumbered(id: &str) -> PlanNode {
+        let PlanNode::Defer {
+            mut primary,
+            mut deferred,
+        } = defer_plan()
+        else {
+            panic!("expected a Defer node");
+        };
+        let Some(PlanNode::Fetch(fetch)) = primary.node.as_deref_mut() else {
+            panic!("expected a Fetch node");
+        };
+        fetch.id = Some(id.to_string());
+        fetch.operation_name = Some("TopProducts__products__0".into());
+        deferred[0].depends[0].id = id.to_string();
+        deferred[0].label = Some("productReviews".to_string());
+        PlanNode::Defer { primary, deferred }
+    }
+
+    fn ignoring_identifiers(shape_only: bool) -> CompareOptions {
+        CompareOptions {
+            shape_only,
+            ignore_identifiers: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_shape_only_ignores_fetch_contents() {
+        let sequence = |first: serde_json::Value, second: serde_json::Value| {
+            json!({
+                "kind": "Sequence",
+                "nodes": [
+                    first,
+                    { "kind": "Flatten", "path": ["topProducts", "@"], "node": second },
+                ],
+            })
+        };
+        assert!(matches_shape(
+            sequence(
+                fetch("products", "{ topProducts { __typename upc } }"),
+                fetch("reviews", "{ a }"),
+            ),
+            sequence(
+                fetch("inventory", "{ topProducts { upc } }"),
+                fetch("accounts", "{ b }"),
+            ),
+        ));
+    }
+
+    #[test]
+    fn test_shape_only_compares_structure() {
+        let parallel = json!({
+            "kind": "Parallel",
+            "nodes": [fetch("products", "{ a }"), fetch("reviews", "{ b }")],
+        });
+        let sequence = json!({
+            "kind": "Sequence",
+            "nodes": [fetch("products", "{ a }"), fetch("reviews", "{ b }")],
+        });
+        assert!(!matches_shape(parallel.clone(), sequence));
+        assert!(!matches_shape(parallel, fetch("products", "{ a }")));
+    }
+
+    #[test]
+    fn test_ignore_identifiers() {
+        let (this, other) = (defer_plan(), renumbered("7"));
+        assert!(!matches_with(&this, &other, &CompareOptions::default()));
+        assert!(matches_with(&this, &other, &ignoring_identifiers(false)));
+    }
+
+    #[test]
+    fn test_shape_only_ignore_identifiers() {
+        let (this, other) = (defer_plan(), renumbered("7"));
+        let shape_only = CompareOptions {
+            shape_only: true,
+            ..Default::default()
+        };
+        assert!(!matches_with(&this, &other, &shape_only));
+        assert!(matches_with(&this, &other, &ignoring_identifiers(true)));
+    }
+
+    #[test]
+    fn test_ignore_identifiers_keeps_dangling_depends() {
+        let mut other = renumbered("7");
+        let PlanNode::Defer { deferred, .. } = &mut other else {
+            unreachable!()
+        };
+        deferred[0].depends[0].id = "8".to_string();
+        assert!(!matches_with(
+            &defer_plan(),
+            &other,
+            &ignoring_identifiers(false)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod plan_structure_tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::router::testing::matches_with;
+    use crate::router::testing::parse_plan;
+
+    fn independent_sequence() -> PlanNode {
+        parse_plan(include_str!(
+            "../../tests/fixtures/plans/independent_sequence.json"
+        ))
+    }
+
+    fn fetch(service_name: &str, operation_kind: &str) -> serde_json::Value {
+        json!({
+            "kind": "Fetch",
+            "serviceName": service_name,
+            "variableUsages": [],
+            "operation": "{ a }",
+            "operationKind": operation_kind,
+        })
+    }
+
+    #[test]
+    fn test_independent_fetches_in_any_order() {
+        let sequence = independent_sequence();
+        let parallel = parse_plan(include_str!(
+            "../../tests/fixtures/plans/independent_parallel.json"
+        ));
+        let options = CompareOptions {
+            assume_independent_fetches: true,
+            ..Default::default()
+        };
+        assert!(!matches_with(
+            &sequence,
+            &parallel,
+            &CompareOptions::default()
+        ));
+        assert!(matches_with(&sequence, &parallel, &options));
+    }
+
+    #[test]
+    fn test_dependent_nodes_stay_in_sequence() {
+        // The outer sequence ends with a `Flatten` node depending on the fetches before it.
+        let PlanNode::Sequence { nodes } = parallelize_independent_fetches(&independent_sequence())
+        else {
+            panic!("expected a Sequence node");
+        };
+        assert!(matches!(nodes[0], PlanNode::Parallel { .. }));
+
+        let mutations: PlanNode = serde_json::from_value(json!({
+            "kind": "Sequence",
+            "nodes": [fetch("accounts", "mutation"), fetch("products", "mutation")],
+        }))
+        .unwrap();
+        assert_eq!(parallelize_independent_fetches(&mutations), mutations);
+    }
+
+    #[test]
+    fn test_negated_conditions() {
+        let legacy = parse_plan(include_str!(
+            "../../tests/fixtures/plans/condition_swapped_legacy.json"
+        ));
+        let native = parse_plan(include_str!(
+            "../../tests/fixtures/plans/condition_swapped_native.json"
+        ));
+        let options = CompareOptions {
+            negated_conditions: true,
+            ..Default::default()
+        };
+        assert!(!matches_with(&legacy, &native, &CompareOptions::default()));
+        assert!(matches_with(&legacy, &native, &options));
+    }
+
+    #[test]
+    fn test_double_negation_keeps_branches() {
+        let legacy = parse_plan(include_str!(
+            "../../tests/fixtures/plans/condition_swapped_legacy.json"
+        ));
+        let PlanNode::Condition {
+            condition,
+            if_clause,
+            else_clause,
+        } = &legacy
+        else {
+            panic!("expected a Condition node");
+        };
+        let double_negation = PlanNode::Condition {
+            condition: format!("!!{condition}"),
+            if_clause: if_clause.clone(),
+            else_clause: else_clause.clone(),
+        };
+        assert_eq!(resolve_negated_conditions(&double_negation), legacy);
+        // Swapped branches on the same variable are a different plan.
+        let swapped = PlanNode::Condition {
+            condition: condition.clone(),
+            if_clause: else_clause.clone(),
+            else_clause: if_clause.clone(),
+        };
+        let options = CompareOptions {
+            negated_conditions: true,
+            ..Default::default()
+        };
+        assert!(!matches_with(&legacy, &swapped, &options));
+    }
+}
@@ -1,46 +1,220 @@
 //! In order to avoid importing the `apollo-router` crate, some of its code is duplicated here.
 
+#[cfg(any(test, feature = "arbitrary"))]
+pub mod arbitrary;
 mod convert;
-mod path;
-mod plan;
+pub(crate) mod format;
+mod ignore;
+pub(crate) mod normalize;
+pub(crate) mod path;
+pub(crate) mod plan;
 pub(crate) mod plan_compare;
+pub(crate) mod semantic;
+#[cfg(test)]
+pub(crate) mod testing;
 
 use std::sync::Arc;
 
 use apollo_federation::query_plan::QueryPlan as NativeQueryPlan;
 pub(crate) use plan::*;
 use serde::Deserialize;
+use serde::Serialize;
 
 //=================================================================================================
 // This section is copied from `apollo-router/src/query_planner/bridge_query_planner.rs`.
 
 /// Data coming from the `plan` method on the router_bridge
 // Note: Reexported under `apollo_compiler::_private`
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct QueryPlanResult {
     pub formatted_query_plan: Option<Arc<String>>,
     query_plan: self::plan::QueryPlan,
 }
 
+impl QueryPlanResult {
+    /// Parses a legacy plan captured from the router, in its camel-case JSON shape (with
+    /// `formattedQueryPlan` and `queryPlan` fields). Other fields are ignored.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// The legacy plan tree.
+    pub fn plan(&self) -> &QueryPlan {
+        &self.query_plan
+    }
+
+    /// The root node of the legacy plan tree, `None` for an empty plan.
+    pub fn root_node(&self) -> Option<&PlanNode> {
+        self.query_plan.root_node()
+    }
+}
+
+//=================================================================================================
+// Convert native plans into the plan tree shared with the legacy planner.
+
+/// Converts a native query plan into the `PlanNode` tree that legacy plans deserialize into.
+///
+/// Returns `None` for an empty plan, i.e. one without a root node (for example, an
+/// introspection-only operation).
+pub fn convert_native_plan(rust_plan: &NativeQueryPlan) -> Option<PlanNode> {
+    convert::convert_root_query_plan_node(rust_plan)
+}
+
 //=================================================================================================
 // Render plans in the same formatting used by `diff_plan`.
 
 type LegacyQueryPlanResult = QueryPlanResult;
 
-pub fn render_legacy_plan(js_plan: &LegacyQueryPlanResult) -> String {
-    let js_root_node = &js_plan.query_plan.node;
-    match js_root_node {
+/// Renders a plan tree of either planner, empty for an empty plan. Both planners' plans are
+/// rendered by this function only, so that their renderings can be diffed line by line.
+pub fn render_plan(node: Option<&PlanNode>) -> String {
+    match node {
         None => String::from(""),
-        Some(js) => format!("{js:#?}"),
+        Some(node) => format!("{node:#?}"),
     }
 }
 
+pub fn render_legacy_plan(js_plan: &LegacyQueryPlanResult) -> String {
+    render_plan(js_plan.root_node())
+}
+
 pub fn render_native_plan(rust_plan: &NativeQueryPlan) -> String {
-    let rust_root_node = convert::convert_root_query_plan_node(rust_plan);
+    render_plan(convert_native_plan(rust_plan).as_ref())
+}
 
-    match rust_root_node {
-        None => String::from(""),
-        Some(rust) => format!("{rust:#?}"),
+#[cfg(test)]
+mod render_golden_tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::native_planner;
+    use crate::render_diff;
+    use crate::run_native_planner;
+
+    // Compares `actual` with the golden file `tests/fixtures/golden/{name}.txt`. Set the
+    // `UPDATE_GOLDENS` environment variable to write the golden files instead, to be reviewed and
+    // committed. A missing golden file fails the test, so that a checkout without it guards nothing
+    // silently.
+    fn assert_golden(name: &str, actual: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/golden")
+            .join(format!("{name}.txt"));
+        if std::env::var_os("UPDATE_GOLDENS").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, actual).unwrap();
+            return;
+        }
+        let expected = std::fs::read_to_string(&path).unwrap_or_else(|err| {
+            panic!(
+                "Failed to read {} (rerun with UPDATE_GOLDENS=1 to write it): {err}",
+                path.display()
+            )
+        });
+        assert!(
+            expected == actual,
+            "{} is out of date (rerun with UPDATE_GOLDENS=1 to update it):\n{}",
+            path.display(),
+            render_diff(&diff::lines(&expected, actual))
+        );
+    }
+
+    fn legacy_plan(node: &str) -> LegacyQueryPlanResult {
+        let node: serde_json::Value = serde_json::from_str(node).unwrap();
+        serde_json::from_value(serde_json::json!({ "queryPlan": { "node": node } })).unwrap()
+    }
+
+    fn native_plan(operation: &str) -> NativeQueryPlan {
+        run_native_planner(
+            include_str!("../../tests/fixtures/supergraph.graphql"),
+            operation,
+            None,
+            "operation.graphql",
+            native_planner::QueryPlannerConfig {
+                incremental_delivery: native_planner::QueryPlanIncrementalDeliveryConfig {
+                    enable_defer: true,
+                },
+                ..Default::default()
+            },
+            Default::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_render_legacy_sequence_and_parallel() {
+        let plan = legacy_plan(include_str!("../../tests/fixtures/plans/parallel.json"));
+        assert_golden("legacy_parallel", &render_legacy_plan(&plan));
+    }
+
+    #[test]
+    fn test_render_legacy_type_conditioned_flatten() {
+        let plan = legacy_plan(include_str!(
+            "../../tests/fixtures/plans/type_conditions.json"
+        ));
+        assert_golden("legacy_type_conditions", &render_legacy_plan(&plan));
+    }
+
+    #[test]
+    fn test_render_legacy_defer() {
+        let plan = legacy_plan(include_str!("../../tests/fixtures/plans/defer.json"));
+        assert_golden("legacy_defer", &render_legacy_plan(&plan));
+    }
+
+    #[test]
+    fn test_render_native_entity_join() {
+        let plan = native_plan(include_str!(
+            "../../tests/fixtures/operations/entity_join.graphql"
+        ));
+        assert_golden("native_entity_join", &render_native_plan(&plan));
+    }
+
+    #[test]
+    fn test_render_native_defer() {
+        let plan = native_plan(include_str!(
+            "../../tests/fixtures/operations/defer.graphql"
+        ));
+        assert_golden("native_defer", &render_native_plan(&plan));
+    }
+
+    #[test]
+    fn test_native_and_legacy_plans_render_identically() {
+        for operation in [
+            include_str!("../../tests/fixtures/operations/entity_join.graphql"),
+            include_str!("../../tests/fixtures/operations/defer.graphql"),
+        ] {
+            let native = native_plan(operation);
+            // The native plan tree, as the legacy planner would have serialized it.
+            let node = serde_json::to_string(&convert_native_plan(&native)).unwrap();
+            let legacy = legacy_plan(&node);
+            assert_eq!(render_legacy_plan(&legacy), render_native_plan(&native));
+        }
+    }
+}
+
+#[cfg(test)]
+mod serialize_tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_result_round_trip() {
+        let json = include_str!("../../tests/fixtures/plans/legacy_result.json");
+        let result = QueryPlanResult::from_json(json).unwrap();
+        assert!(result.formatted_query_plan.is_some());
+        assert!(matches!(
+            result.root_node(),
+            Some(PlanNode::Sequence { .. })
+        ));
+        assert_eq!(result.plan().root_node(), result.root_node());
+        let serialized = serde_json::to_string(&result).unwrap();
+        let round_tripped: QueryPlanResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(round_tripped, result);
+    }
+
+    #[test]
+    fn test_from_json_rejects_other_shapes() {
+        // A bare plan node, rather than the router's plan result.
+        let json = include_str!("../../tests/fixtures/plans/parallel.json");
+        assert!(QueryPlanResult::from_json(json).is_err());
     }
 }
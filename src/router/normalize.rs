@@ -0,0 +1,434 @@
+// Canonical form of plan trees and plan fingerprints.
+//
+// Normalization only rewrites what the comparator already treats as insignificant (the order of
+// `Parallel` children, `Sequence` and `Parallel` nodes wrapping a single child, empty `Condition`
+// branches, variable usages, requires selections and rewrites, fetch operation names, and the
+// formatting of subgraph operations). Hence, two plans with equal normalized forms (and equal
+// fingerprints) always match.
+
+use std::hash::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use super::DeferredNode;
+use super::FetchNode;
+use super::FlattenNode;
+use super::PlanNode;
+use super::Primary;
+use super::SubscriptionNode;
+use super::ignore::apply_compare_options;
+use super::ignore::map_leaves;
+use super::plan_compare::CompareOptions;
+use super::plan_compare::condition_branch;
+use super::semantic::canonical_operation;
+
+/// Returns the canonical form of a plan tree.
+pub fn normalize(node: &PlanNode) -> PlanNode {
+    canonicalize(node, true)
+}
+
+/// Returns the canonical form of a plan tree once the differences ignored by `options` are erased:
+/// what the comparator compares with these options, up to the orders it ignores.
+pub fn normalize_with_options(node: &PlanNode, options: &CompareOptions) -> PlanNode {
+    normalize(&apply_compare_options(node, options))
+}
+
+/// Returns true if two plan trees only differ in the order of `Parallel` children, requires
+/// selections, variable usages or rewrites: they match, but only because the comparison ignores
+/// these orders.
+pub fn is_trivially_divergent(this: &PlanNode, other: &PlanNode) -> bool {
+    canonicalize(this, false) != canonicalize(other, false) && normalize(this) == normalize(other)
+}
+
+/// Returns the plan tree with its subgraph operations re-serialized in a canonical format (see
+/// `canonical_operation_str`), so that the same operations always print the same. Operations
+/// failing to parse are left as they are.
+pub fn canonicalize_operations(node: &PlanNode) -> PlanNode {
+    map_leaves(
+        node,
+        &|fetch| FetchNode {
+            operation: canonical_operation(&fetch.operation),
+            ..fetch.clone()
+        },
+        &|primary| SubscriptionNode {
+            operation: canonical_operation(&primary.operation),
+            ..primary.clone()
+        },
+    )
+}
+
+// With `sort`, the orders ignored by the comparator are made canonical as well. Subgraph
+// operations are always reformatted canonically.
+fn canonicalize(node: &PlanNode, sort: bool) -> PlanNode {
+    let normalize = |node: &PlanNode| canonicalize(node, sort);
+    match node {
+        PlanNode::Sequence { nodes } | PlanNode::Parallel { nodes } if nodes.len() == 1 => {
+            normalize(&nodes[0])
+        }
+        PlanNode::Sequence { nodes } => PlanNode::Sequence {
+            nodes: nodes.iter().map(normalize).collect(),
+        },
+        PlanNode::Parallel { nodes } => {
+            let mut nodes: Vec<PlanNode> = nodes.iter().map(normalize).collect();
+            if sort {
+                nodes.sort_by_cached_key(canonical_json);
+            }
+            PlanNode::Parallel { nodes }
+        }
+        PlanNode::Fetch(fetch) => PlanNode::Fetch(normalize_fetch(fetch, sort)),
+        PlanNode::Flatten(flatten) => PlanNode::Flatten(FlattenNode {
+            path: flatten.path.clone(),
+            node: Box::new(normalize(&flatten.node)),
+        }),
+        PlanNode::Defer { primary, deferred } => PlanNode::Defer {
+            primary: Primary {
+                subselection: primary.subselection.clone(),
+                node: primary.node.as_deref().map(normalize).map(Box::new),
+            },
+            deferred: deferred
+                .iter()
+                .map(|deferred| DeferredNode {
+                    node: deferred.node.as_deref().map(normalize).map(Arc::new),
+                    ..deferred.clone()
+                })
+                .collect(),
+        },
+        PlanNode::Subscription { primary, rest } => PlanNode::Subscription {
+            primary: normalize_subscription(primary, sort),
+            rest: rest.as_deref().map(normalize).map(Box::new),
+        },
+        PlanNode::Condition {
+            condition,
+            if_clause,
+            else_clause,
+        } => PlanNode::Condition {
+            condition: condition.clone(),
+            if_clause: condition_branch(if_clause).map(normalize).map(Box::new),
+            else_clause: condition_branch(else_clause).map(normalize).map(Box::new),
+        },
+    }
+}
+
+fn normalize_fetch(fetch: &FetchNode, sort: bool) -> FetchNode {
+    let mut fetch = fetch.clone();
+    // ignored by the comparator: reordered parallel fetches may have different names
+    fetch.operation_name = None;
+    fetch.operation = canonical_operation(&fetch.operation);
+    if !sort {
+        return fetch;
+    }
+    fetch.variable_usages.sort();
+    fetch.requires.sort_by_cached_key(canonical_json);
+    for rewrites in [
+        &mut fetch.input_rewrites,
+        &mut fetch.output_rewrites,
+        &mut fetch.context_rewrites,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        rewrites.sort_by_cached_key(canonical_json);
+    }
+    fetch
+}
+
+fn normalize_subscription(primary: &SubscriptionNode, sort: bool) -> SubscriptionNode {
+    let mut primary = primary.clone();
+    primary.operation_name = None;
+    primary.operation = canonical_operation(&primary.operation);
+    if !sort {
+        return primary;
+    }
+    primary.variable_usages.sort();
+    for rewrites in [&mut primary.input_rewrites, &mut primary.output_rewrites]
+        .into_iter()
+        .flatten()
+    {
+        rewrites.sort_by_cached_key(canonical_json);
+    }
+    primary
+}
+
+fn canonical_json(value: &impl Serialize) -> String {
+    serde_json::to_string(value).expect("plan nodes always serialize to JSON")
+}
+
+/// A plan tree in canonical form (see `normalize`), usable in sets and as a map key: plans with
+/// equal canonical forms are equal, and hash equally.
+///
+/// `PlanNode` itself only compares its raw structure, which is order-sensitive, so it isn't `Eq`
+/// nor `Hash`.
+#[derive(Debug, Clone)]
+pub struct CanonicalPlan {
+    node: PlanNode,
+    // The canonical JSON form of `node`, which equality and hashing use.
+    json: String,
+}
+
+impl CanonicalPlan {
+    pub fn new(node: &PlanNode) -> Self {
+        let node = normalize(node);
+        let json = canonical_json(&node);
+        Self { node, json }
+    }
+
+    /// Like `new`, once the differences ignored by `options` are erased (see
+    /// `normalize_with_options`).
+    pub fn with_options(node: &PlanNode, options: &CompareOptions) -> Self {
+        Self::new(&apply_compare_options(node, options))
+    }
+
+    /// The normalized plan tree.
+    pub fn node(&self) -> &PlanNode {
+        &self.node
+    }
+
+    /// See `plan_fingerprint`.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl PartialEq for CanonicalPlan {
+    fn eq(&self, other: &Self) -> bool {
+        self.json == other.json
+    }
+}
+
+impl Eq for CanonicalPlan {}
+
+impl Hash for CanonicalPlan {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.json.hash(state);
+    }
+}
+
+/// A hash of the normalized plan tree.
+///
+/// Equal fingerprints imply that the plans match, so comparing fingerprints is a cheap way to skip
+/// the detailed comparison. The converse does not hold: matching plans may still have different
+/// fingerprints (e.g. due to cosmetic differences in subgraph operations).
+///
+/// Note: Fingerprints are only stable within a single build of this crate.
+pub fn plan_fingerprint(node: &PlanNode) -> u64 {
+    CanonicalPlan::new(node).fingerprint()
+}
+
+#[cfg(test)]
+mod fingerprint_tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::router::arbitrary;
+    use crate::router::plan_compare::opt_plan_node_matches;
+    use crate::router::testing::parse_plan;
+
+    #[test]
+    fn test_empty_branches_are_omitted() {
+        let legacy = parse_plan(include_str!(
+            "../../tests/fixtures/plans/condition_omitted_legacy.json"
+        ));
+        let native = parse_plan(include_str!(
+            "../../tests/fixtures/plans/condition_omitted_native.json"
+        ));
+        assert_eq!(normalize(&legacy), normalize(&native));
+        assert_eq!(plan_fingerprint(&legacy), plan_fingerprint(&native));
+    }
+
+    proptest! {
+        #[test]
+        fn test_reordered_plans_have_same_fingerprint(node in arbitrary::plan_node()) {
+            prop_assert_eq!(plan_fingerprint(&node), plan_fingerprint(&arbitrary::reordered(&node)));
+        }
+
+        #[test]
+        fn test_same_fingerprint_implies_match(
+            this in arbitrary::plan_node(),
+            other in arbitrary::plan_node(),
+        ) {
+            // Compare each plan against a reordered copy of itself and against an unrelated plan.
+            for other in [arbitrary::reordered(&this), other] {
+                if plan_fingerprint(&this) == plan_fingerprint(&other) {
+                    prop_assert!(opt_plan_node_matches(&Some(&this), &Some(&other)).is_ok());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod canonical_operation_tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn fetch(operation: &str) -> PlanNode {
+        serde_json::from_value(json!({
+            "kind": "Fetch",
+            "serviceName": "products",
+            "variableUsages": [],
+            "operation": operation,
+            "operationKind": "query",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_reformatted_operations_have_same_fingerprint() {
+        let compact = fetch("query Top__products__0{topProducts{upc name}}");
+        let indented =
+            fetch("query Top__products__0 {\n  topProducts {\n    upc\n    name\n  }\n}\n");
+        assert_eq!(plan_fingerprint(&compact), plan_fingerprint(&indented));
+        assert_eq!(
+            canonicalize_operations(&compact),
+            canonicalize_operations(&indented)
+        );
+        assert_ne!(
+            plan_fingerprint(&compact),
+            plan_fingerprint(&fetch(
+                "query Top__products__0 { topProducts { name upc } }"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_unparsable_operations_are_kept() {
+        let invalid = fetch("query {");
+        assert_eq!(canonicalize_operations(&invalid), invalid);
+    }
+}
+
+#[cfg(test)]
+mod idempotency_tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::router::arbitrary;
+
+    const FIXTURE_PLANS: &[&str] = &[
+        include_str!("../../tests/fixtures/plans/defer.json"),
+        include_str!("../../tests/fixtures/plans/parallel.json"),
+    ];
+
+    #[test]
+    fn test_normalize_is_idempotent_on_fixtures() {
+        for fixture in FIXTURE_PLANS {
+            let node: PlanNode = serde_json::from_str(fixture).unwrap();
+            let normalized = normalize(&node);
+            assert_eq!(normalize(&normalized), normalized);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_normalize_is_idempotent(node in arbitrary::plan_node()) {
+            let normalized = normalize(&node);
+            prop_assert_eq!(normalize(&normalized), normalized);
+        }
+    }
+}
+
+#[cfg(test)]
+mod trivially_divergent_tests {
+    use super::*;
+    use crate::router::testing::nodes_mut;
+    use crate::router::testing::parallel_plan;
+
+    #[test]
+    fn test_reordered_parallel_is_trivially_divergent() {
+        let plan = parallel_plan();
+        let mut reordered = plan.clone();
+        nodes_mut(&mut nodes_mut(&mut reordered)[1]).reverse();
+        assert!(is_trivially_divergent(&plan, &reordered));
+        assert!(!is_trivially_divergent(&plan, &plan.clone()));
+    }
+
+    #[test]
+    fn test_other_differences_are_not_trivial() {
+        let plan = parallel_plan();
+        let mut other = plan.clone();
+        nodes_mut(&mut other).pop();
+        assert!(!is_trivially_divergent(&plan, &other));
+    }
+}
+
+#[cfg(test)]
+mod normalize_with_options_tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_with_options() {
+        let sequence: PlanNode = serde_json::from_str(include_str!(
+            "../../tests/fixtures/plans/independent_sequence.json"
+        ))
+        .unwrap();
+        let parallel: PlanNode = serde_json::from_str(include_str!(
+            "../../tests/fixtures/plans/independent_parallel.json"
+        ))
+        .unwrap();
+        let default = CompareOptions::default();
+        assert_eq!(
+            normalize_with_options(&sequence, &default),
+            normalize(&sequence)
+        );
+        assert_ne!(
+            normalize_with_options(&sequence, &default),
+            normalize_with_options(&parallel, &default)
+        );
+        let options = CompareOptions {
+            assume_independent_fetches: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize_with_options(&sequence, &options),
+            normalize_with_options(&parallel, &options)
+        );
+    }
+}
+
+#[cfg(test)]
+mod canonical_plan_tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::router::testing::defer_plan;
+    use crate::router::testing::parallel_plan;
+
+    #[test]
+    fn test_equivalent_plans_share_a_bucket() {
+        let parallel = parallel_plan();
+        let PlanNode::Sequence { nodes } = &parallel else {
+            panic!("expected a Sequence node");
+        };
+        let PlanNode::Parallel { nodes: children } = &nodes[1] else {
+            panic!("expected a Parallel node");
+        };
+        let mut reordered_children = children.clone();
+        reordered_children.reverse();
+        let reordered = PlanNode::Sequence {
+            nodes: vec![
+                nodes[0].clone(),
+                PlanNode::Parallel {
+                    nodes: reordered_children,
+                },
+            ],
+        };
+        assert_ne!(parallel, reordered);
+
+        let plans: HashSet<CanonicalPlan> = [&parallel, &reordered, &defer_plan()]
+            .into_iter()
+            .map(CanonicalPlan::new)
+            .collect();
+        assert_eq!(plans.len(), 2);
+        assert!(plans.contains(&CanonicalPlan::new(&reordered)));
+        assert_eq!(
+            CanonicalPlan::new(&parallel).fingerprint(),
+            plan_fingerprint(&reordered)
+        );
+    }
+}
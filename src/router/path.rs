@@ -1,6 +1,7 @@
 // Copied from `apollo-router/src/json_ext.rs` (commit: d9336e43f)
 
 use std::fmt;
+use std::str::FromStr;
 
 use once_cell::sync::Lazy;
 use regex::Captures;
@@ -209,18 +210,16 @@ impl fmt::Display for Path {
                 PathElement::Index(index) => write!(f, "{index}")?,
                 PathElement::Key(key, type_conditions) => {
                     write!(f, "{key}")?;
+                    // Note: An empty condition list is written (as `|[]`) to tell it apart from
+                    //       no conditions, like in the serialized form.
                     if let Some(c) = type_conditions {
-                        if !c.is_empty() {
-                            write!(f, "|[{}]", c.join(","))?;
-                        }
+                        write!(f, "|[{}]", c.join(","))?;
                     };
                 }
                 PathElement::Flatten(type_conditions) => {
                     write!(f, "@")?;
                     if let Some(c) = type_conditions {
-                        if !c.is_empty() {
-                            write!(f, "|[{}]", c.join(","))?;
-                        }
+                        write!(f, "|[{}]", c.join(","))?;
                     };
                 }
                 PathElement::Fragment(name) => {
@@ -231,3 +230,92 @@ impl fmt::Display for Path {
         Ok(())
     }
 }
+
+/// Parses the `Display` form of a path, e.g. `/topProducts/@|[Book,Movie]/... on Book/0`.
+///
+/// Note: The `Display` form is ambiguous for keys that are not GraphQL names (e.g. `@`, `0`, or
+///       keys containing `/` or `|[`). Since response keys are always GraphQL names, this only
+///       matters for hand-written paths.
+impl FromStr for Path {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(Path::default());
+        }
+        let Some(rest) = s.strip_prefix('/') else {
+            return Err(format!("path `{s}` does not start with `/`"));
+        };
+        rest.split('/')
+            .map(parse_path_element)
+            .collect::<Result<_, _>>()
+            .map(Path)
+    }
+}
+
+fn parse_path_element(s: &str) -> Result<PathElement, String> {
+    if let Some(name) = s.strip_prefix(FRAGMENT_PREFIX) {
+        return Ok(PathElement::Fragment(name.to_string()));
+    }
+    let (path_element, type_conditions) = split_path_element_and_type_conditions(s);
+    if path_element == "@" {
+        return Ok(PathElement::Flatten(type_conditions));
+    }
+    match (path_element.parse::<usize>(), type_conditions) {
+        (Ok(index), None) => Ok(PathElement::Index(index)),
+        (Ok(_), Some(_)) => Err(format!("index path element `{s}` has type conditions")),
+        (Err(_), type_conditions) => Ok(PathElement::Key(path_element, type_conditions)),
+    }
+}
+
+#[cfg(test)]
+mod from_str_tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const GRAPHQL_NAME: &str = "[_A-Za-z][_0-9A-Za-z]{0,8}";
+
+    fn type_conditions() -> impl Strategy<Value = Option<TypeConditions>> {
+        prop::option::of(prop::collection::vec(GRAPHQL_NAME, 0..4))
+    }
+
+    fn path_element() -> impl Strategy<Value = PathElement> {
+        prop_oneof![
+            type_conditions().prop_map(PathElement::Flatten),
+            any::<usize>().prop_map(PathElement::Index),
+            GRAPHQL_NAME.prop_map(PathElement::Fragment),
+            (GRAPHQL_NAME, type_conditions())
+                .prop_map(|(key, conditions)| PathElement::Key(key, conditions)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_display_from_str_round_trip(
+            path in prop::collection::vec(path_element(), 0..6).prop_map(Path),
+        ) {
+            prop_assert_eq!(path.to_string().parse::<Path>(), Ok(path));
+        }
+    }
+
+    #[test]
+    fn test_tricky_paths() {
+        let path = Path(vec![
+            PathElement::Key("".to_string(), None),
+            PathElement::Key("on".to_string(), Some(vec![])),
+            PathElement::Flatten(Some(vec![])),
+            PathElement::Flatten(Some(vec!["Book".to_string(), "Movie".to_string()])),
+            PathElement::Fragment("on".to_string()),
+            PathElement::Index(0),
+        ]);
+        assert_eq!(path.to_string(), "//on|[]/@|[]/@|[Book,Movie]/... on on/0");
+        assert_eq!(path.to_string().parse::<Path>(), Ok(path));
+    }
+
+    #[test]
+    fn test_invalid_paths() {
+        assert!("topProducts".parse::<Path>().is_err());
+        assert!("/0|[Book]".parse::<Path>().is_err());
+    }
+}
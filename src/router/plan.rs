@@ -13,18 +13,25 @@ use serde_json_bytes::Value;
 
 use crate::router::path::Path;
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 /// The root query plan container.
-pub(super) struct QueryPlan {
+pub struct QueryPlan {
     /// The hierarchical nodes that make up the query plan
     pub(super) node: Option<Arc<PlanNode>>,
 }
 
+impl QueryPlan {
+    /// The root node of the plan, `None` for an empty plan (e.g. an introspection-only operation).
+    pub fn root_node(&self) -> Option<&PlanNode> {
+        self.node.as_deref()
+    }
+}
+
 /// Query plans are composed of a set of nodes.
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase", tag = "kind")]
-pub(crate) enum PlanNode {
+pub enum PlanNode {
     /// These nodes must be executed in order.
     Sequence {
         /// The plan nodes that make up the sequence execution.
@@ -61,27 +68,82 @@ pub(crate) enum PlanNode {
     },
 }
 
+impl PlanNode {
+    /// The name of the kind of node, as in the `kind` field of its JSON form, e.g. `"Fetch"`.
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            PlanNode::Sequence { .. } => "Sequence",
+            PlanNode::Parallel { .. } => "Parallel",
+            PlanNode::Fetch(_) => "Fetch",
+            PlanNode::Flatten(_) => "Flatten",
+            PlanNode::Defer { .. } => "Defer",
+            PlanNode::Subscription { .. } => "Subscription",
+            PlanNode::Condition { .. } => "Condition",
+        }
+    }
+
+    /// The child nodes, in plan order: the primary node of a `Defer` node before its deferred
+    /// nodes, and the `if` branch of a `Condition` node before its `else` branch.
+    pub(crate) fn children(&self) -> Vec<&PlanNode> {
+        match self {
+            PlanNode::Sequence { nodes } | PlanNode::Parallel { nodes } => nodes.iter().collect(),
+            PlanNode::Fetch(_) => Vec::new(),
+            PlanNode::Flatten(flatten) => vec![&flatten.node],
+            PlanNode::Defer { primary, deferred } => primary
+                .node
+                .as_deref()
+                .into_iter()
+                .chain(
+                    deferred
+                        .iter()
+                        .filter_map(|deferred| deferred.node.as_deref()),
+                )
+                .collect(),
+            PlanNode::Subscription { primary: _, rest } => rest.as_deref().into_iter().collect(),
+            PlanNode::Condition {
+                condition: _,
+                if_clause,
+                else_clause,
+            } => [if_clause, else_clause]
+                .into_iter()
+                .filter_map(|clause| clause.as_deref())
+                .collect(),
+        }
+    }
+
+    /// The number of levels of the plan tree, computed without recursion.
+    pub(crate) fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((node, level)) = stack.pop() {
+            depth = depth.max(level);
+            stack.extend(node.children().into_iter().map(|child| (child, level + 1)));
+        }
+        depth
+    }
+}
+
 /// A flatten node.
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct FlattenNode {
+pub struct FlattenNode {
     /// The path when result should be merged.
-    pub(crate) path: Path,
+    pub path: Path,
 
     /// The child execution plan.
-    pub(crate) node: Box<PlanNode>,
+    pub node: Box<PlanNode>,
 }
 
 /// A primary query for a Defer node, the non deferred part
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct Primary {
+pub struct Primary {
     /// The part of the original query that "selects" the data to
     /// send in that primary response (once the plan in `node` completes).
-    pub(crate) subselection: Option<String>,
+    pub subselection: Option<String>,
 
     // The plan to get all the data for that primary part
-    pub(crate) node: Option<Box<PlanNode>>,
+    pub node: Option<Box<PlanNode>>,
 }
 
 /// The "deferred" parts of the defer (note that it's an array). Each
@@ -89,29 +151,29 @@ pub(crate) struct Primary {
 /// the response to the client (after the initial non-deferred one that is).
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct DeferredNode {
+pub struct DeferredNode {
     /// References one or more fetch node(s) (by `id`) within
     /// `primary.node`. The plan of this deferred part should not
     /// be started before all those fetches returns.
-    pub(crate) depends: Vec<Depends>,
+    pub depends: Vec<Depends>,
 
     /// The optional defer label.
-    pub(crate) label: Option<String>,
+    pub label: Option<String>,
     /// Path to the @defer this correspond to. `subselection` start at that `path`.
-    pub(crate) query_path: Path,
+    pub query_path: Path,
     /// The part of the original query that "selects" the data to send
     /// in that deferred response (once the plan in `node` completes).
     /// Will be set _unless_ `node` is a `DeferNode` itself.
-    pub(crate) subselection: Option<String>,
+    pub subselection: Option<String>,
     /// The plan to get all the data for that deferred part
-    pub(crate) node: Option<Arc<PlanNode>>,
+    pub node: Option<Arc<PlanNode>>,
 }
 
 /// A deferred node.
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct Depends {
-    pub(crate) id: String,
+pub struct Depends {
+    pub id: String,
 }
 
 /// GraphQL operation type.
@@ -148,82 +210,313 @@ impl From<ast::OperationType> for OperationKind {
 /// A fetch node.
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct FetchNode {
+pub struct FetchNode {
     /// The name of the service or subgraph that the fetch is querying.
-    pub(crate) service_name: Arc<str>,
+    pub service_name: Arc<str>,
 
     /// The data that is required for the subgraph fetch.
     #[serde(skip_serializing_if = "Vec::is_empty")]
     #[serde(default)]
-    pub(crate) requires: Vec<Selection>,
+    pub requires: Vec<Selection>,
 
     /// The variables that are used for the subgraph fetch.
-    pub(crate) variable_usages: Vec<Arc<str>>,
+    pub variable_usages: Vec<Arc<str>>,
 
     /// The GraphQL subquery that is used for the fetch.
-    pub(crate) operation: SerializableDocument,
+    pub operation: SerializableDocument,
 
     /// The GraphQL subquery operation name.
-    pub(crate) operation_name: Option<Arc<str>>,
+    pub operation_name: Option<Arc<str>>,
 
     /// The GraphQL operation kind that is used for the fetch.
-    pub(crate) operation_kind: OperationKind,
+    pub operation_kind: OperationKind,
 
     /// Optional id used by Deferred nodes
-    pub(crate) id: Option<String>,
+    pub id: Option<String>,
 
     // Optionally describes a number of "rewrites" that query plan executors should apply to the data that is sent as input of this fetch.
-    pub(crate) input_rewrites: Option<Vec<DataRewrite>>,
+    pub input_rewrites: Option<Vec<DataRewrite>>,
 
     // Optionally describes a number of "rewrites" to apply to the data that received from a fetch (and before it is applied to the current in-memory results).
-    pub(crate) output_rewrites: Option<Vec<DataRewrite>>,
+    pub output_rewrites: Option<Vec<DataRewrite>>,
 
     // Optionally describes a number of "rewrites" to apply to the data that has already been received further up the tree
-    pub(crate) context_rewrites: Option<Vec<DataRewrite>>,
+    pub context_rewrites: Option<Vec<DataRewrite>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase", tag = "kind")]
-pub(crate) enum DataRewrite {
+pub enum DataRewrite {
     ValueSetter(DataValueSetter),
     KeyRenamer(DataKeyRenamer),
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct DataValueSetter {
-    pub(crate) path: Path,
-    pub(crate) set_value_to: Value,
+pub struct DataValueSetter {
+    pub path: Path,
+    pub set_value_to: Value,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct DataKeyRenamer {
-    pub(crate) path: Path,
-    pub(crate) rename_key_to: Name,
+pub struct DataKeyRenamer {
+    pub path: Path,
+    pub rename_key_to: Name,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct SubscriptionNode {
+pub struct SubscriptionNode {
     /// The name of the service or subgraph that the subscription is querying.
-    pub(crate) service_name: Arc<str>,
+    pub service_name: Arc<str>,
 
     /// The variables that are used for the subgraph subscription.
-    pub(crate) variable_usages: Vec<Arc<str>>,
+    pub variable_usages: Vec<Arc<str>>,
 
     /// The GraphQL subquery that is used for the subscription.
-    pub(crate) operation: SerializableDocument,
+    pub operation: SerializableDocument,
 
     /// The GraphQL subquery operation name.
-    pub(crate) operation_name: Option<Arc<str>>,
+    pub operation_name: Option<Arc<str>>,
 
     /// The GraphQL operation kind that is used for the fetch.
-    pub(crate) operation_kind: OperationKind,
+    pub operation_kind: OperationKind,
 
     // Optionally describes a number of "rewrites" that query plan executors should apply to the data that is sent as input of this subscription.
-    pub(crate) input_rewrites: Option<Vec<DataRewrite>>,
+    pub input_rewrites: Option<Vec<DataRewrite>>,
 
     // Optionally describes a number of "rewrites" to apply to the data that received from a subscription (and before it is applied to the current in-memory results).
-    pub(crate) output_rewrites: Option<Vec<DataRewrite>>,
+    pub output_rewrites: Option<Vec<DataRewrite>>,
+}
+
+#[cfg(test)]
+mod kind_name_tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_kind_names_match_json_kinds() {
+        let fetch = json!({
+            "kind": "Fetch",
+            "serviceName": "products",
+            "variableUsages": [],
+            "operation": "{ topProducts { upc } }",
+            "operationKind": "query",
+        });
+        let subscription_primary = json!({
+            "serviceName": "products",
+            "variableUsages": [],
+            "operation": "subscription { priceChanged { upc } }",
+            "operationKind": "subscription",
+        });
+        let nodes = [
+            json!({ "kind": "Sequence", "nodes": [fetch] }),
+            json!({ "kind": "Parallel", "nodes": [fetch] }),
+            fetch.clone(),
+            json!({ "kind": "Flatten", "path": ["topProducts", "@"], "node": fetch }),
+            json!({ "kind": "Defer", "primary": { "node": fetch }, "deferred": [] }),
+            json!({ "kind": "Subscription", "primary": subscription_primary }),
+            json!({ "kind": "Condition", "condition": "withPrice", "ifClause": fetch }),
+        ];
+        let kinds: Vec<&str> = nodes
+            .iter()
+            .map(|json| {
+                let node: PlanNode = serde_json::from_value(json.clone()).unwrap();
+                assert_eq!(node.kind_name(), json["kind"]);
+                node.kind_name()
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "Sequence",
+                "Parallel",
+                "Fetch",
+                "Flatten",
+                "Defer",
+                "Subscription",
+                "Condition"
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod serde_round_trip_tests {
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    use super::*;
+    use crate::router::arbitrary;
+    use crate::router::path::PathElement;
+
+    fn assert_round_trip(node: &PlanNode) {
+        let serialized = serde_json::to_value(node).unwrap();
+        let deserialized: PlanNode = serde_json::from_value(serialized).unwrap();
+        assert_eq!(*node, deserialized);
+    }
+
+    fn fetch_json(service_name: &str) -> serde_json::Value {
+        json!({
+            "kind": "Fetch",
+            "serviceName": service_name,
+            "variableUsages": [],
+            "operation": "{ topProducts { upc } }",
+            "operationName": "TopProducts__products__0",
+            "operationKind": "query",
+        })
+    }
+
+    #[test]
+    fn test_sequence_and_parallel_round_trip() {
+        let node: PlanNode = serde_json::from_value(json!({
+            "kind": "Sequence",
+            "nodes": [
+                fetch_json("products"),
+                { "kind": "Parallel", "nodes": [fetch_json("reviews"), fetch_json("inventory")] },
+            ],
+        }))
+        .unwrap();
+        assert_round_trip(&node);
+    }
+
+    #[test]
+    fn test_fetch_with_requires_and_rewrites_round_trip() {
+        let node: PlanNode = serde_json::from_value(json!({
+            "kind": "Fetch",
+            "serviceName": "reviews",
+            "requires": [
+                {
+                    "kind": "InlineFragment",
+                    "typeCondition": "Product",
+                    "selections": [
+                        { "kind": "Field", "name": "__typename" },
+                        { "kind": "Field", "name": "upc" },
+                    ],
+                },
+            ],
+            "variableUsages": ["first"],
+            "operation": "query($representations: [_Any!]!, $first: Int) { _entities(representations: $representations) { ... on Product { reviews(first: $first) { body } } } }",
+            "operationName": "TopProducts__reviews__1",
+            "operationKind": "query",
+            "id": "1",
+            "inputRewrites": [
+                { "kind": "ValueSetter", "path": ["... on Book", "__typename"], "setValueTo": "Product" },
+            ],
+            "outputRewrites": [
+                { "kind": "KeyRenamer", "path": ["... on Product", "reviews__alias_0"], "renameKeyTo": "reviews" },
+            ],
+            "contextRewrites": [
+                { "kind": "KeyRenamer", "path": ["..", "@|[Product]", "upc"], "renameKeyTo": "contextualArgument_1_0" },
+            ],
+        }))
+        .unwrap();
+        assert_round_trip(&node);
+    }
+
+    #[test]
+    fn test_flatten_with_type_conditioned_path_round_trip() {
+        let node: PlanNode = serde_json::from_value(json!({
+            "kind": "Flatten",
+            "path": ["", "topProducts|[Book,Movie]", "@|[Book]", 0, "reviews|[]", "@|[]", "... on Review"],
+            "node": fetch_json("reviews"),
+        }))
+        .unwrap();
+        let PlanNode::Flatten(flatten) = &node else {
+            panic!("expected a Flatten node");
+        };
+        assert_eq!(
+            flatten.path.0,
+            vec![
+                PathElement::Key("".to_string(), None),
+                PathElement::Key(
+                    "topProducts".to_string(),
+                    Some(vec!["Book".to_string(), "Movie".to_string()])
+                ),
+                PathElement::Flatten(Some(vec!["Book".to_string()])),
+                PathElement::Index(0),
+                PathElement::Key("reviews".to_string(), Some(vec![])),
+                PathElement::Flatten(Some(vec![])),
+                PathElement::Fragment("Review".to_string()),
+            ]
+        );
+        assert_round_trip(&node);
+    }
+
+    #[test]
+    fn test_defer_round_trip() {
+        let node: PlanNode = serde_json::from_value(json!({
+            "kind": "Defer",
+            "primary": {
+                "subselection": "{ topProducts { upc } }",
+                "node": fetch_json("products"),
+            },
+            "deferred": [
+                {
+                    "depends": [{ "id": "0" }],
+                    "label": "reviews",
+                    "queryPath": ["topProducts", "@"],
+                    "subselection": "{ reviews { body } }",
+                    "node": {
+                        "kind": "Flatten",
+                        "path": ["topProducts", "@"],
+                        "node": fetch_json("reviews"),
+                    },
+                },
+                {
+                    "depends": [],
+                    "label": null,
+                    "queryPath": [],
+                    "subselection": null,
+                    "node": null,
+                },
+            ],
+        }))
+        .unwrap();
+        assert_round_trip(&node);
+    }
+
+    #[test]
+    fn test_subscription_round_trip() {
+        let node: PlanNode = serde_json::from_value(json!({
+            "kind": "Subscription",
+            "primary": {
+                "serviceName": "reviews",
+                "variableUsages": [],
+                "operation": "subscription { reviewAdded { id } }",
+                "operationName": "ReviewAdded__reviews__0",
+                "operationKind": "subscription",
+            },
+            "rest": {
+                "kind": "Flatten",
+                "path": ["reviewAdded"],
+                "node": fetch_json("products"),
+            },
+        }))
+        .unwrap();
+        assert_round_trip(&node);
+    }
+
+    #[test]
+    fn test_condition_round_trip() {
+        let node: PlanNode = serde_json::from_value(json!({
+            "kind": "Condition",
+            "condition": "withReviews",
+            "ifClause": fetch_json("reviews"),
+            "elseClause": null,
+        }))
+        .unwrap();
+        assert_round_trip(&node);
+    }
+
+    proptest! {
+        #[test]
+        fn test_generated_plans_round_trip(node in arbitrary::plan_node()) {
+            let serialized = serde_json::to_value(&node).unwrap();
+            let deserialized: PlanNode = serde_json::from_value(serialized).unwrap();
+            prop_assert_eq!(node, deserialized);
+        }
+    }
 }
@@ -5,11 +5,13 @@
 // Semantic comparison of JS and Rust query plans
 
 use std::borrow::Borrow;
+use std::collections::BTreeSet;
 use std::collections::hash_map::HashMap;
 use std::fmt::Write;
 use std::hash::DefaultHasher;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::sync::Arc;
 
 use apollo_compiler::Name;
 use apollo_compiler::Node;
@@ -22,28 +24,97 @@ use super::DataRewrite;
 use super::DeferredNode;
 use super::FetchNode;
 use super::FlattenNode;
+use super::OperationKind;
 use super::PlanNode;
 use super::Primary;
 use super::QueryPlanResult;
 use super::SubscriptionNode;
 use super::convert::convert_root_query_plan_node;
+use super::format::format_plan;
+use super::format::format_plan_explained;
+use super::ignore::apply_compare_options;
+use super::normalize::is_trivially_divergent;
+use super::normalize::plan_fingerprint;
 use super::path::Path;
 use super::path::PathElement;
+use super::render_plan;
+use super::semantic::normalize_subselection_str;
+use crate::render::DiffStyle;
+use crate::render::Style;
+use crate::subgraph::SubgraphSchemas;
 
 //==================================================================================================
 // Public interface
 
-#[derive(Debug)]
 pub struct MatchFailure {
     description: String,
+    // From the first divergent node up to the root.
+    path: Vec<Breadcrumb>,
+    // The first divergent node, from the legacy plan.
+    node: Option<Box<PlanNode>>,
+    // The keys of the `Flatten` paths from the root down to the first divergent node.
+    response_path: Vec<String>,
     backtrace: std::backtrace::Backtrace,
 }
 
+// The divergent node and response path are left out, since mismatch reports show them (in the
+// diff and source snippet) already.
+impl std::fmt::Debug for MatchFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MatchFailure")
+            .field("description", &self.description)
+            .field("path", &self.path)
+            .field("backtrace", &self.backtrace)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+enum Breadcrumb {
+    // A plan node, labelled from the legacy plan.
+    Node(String),
+    // Which child of the enclosing node diverges: an index, or a part like `if` or `primary`.
+    Child(String),
+}
+
 impl MatchFailure {
     pub fn description(&self) -> String {
         self.description.clone()
     }
 
+    /// The path from the root to the first divergent plan node, e.g.
+    /// `Sequence[0] > Flatten(/topProducts/@) > Fetch(reviews)`.
+    ///
+    /// Returns `None` when the plans already diverge on having a root node.
+    pub fn divergence_path(&self) -> Option<String> {
+        let mut path = String::new();
+        for breadcrumb in self.path.iter().rev() {
+            match breadcrumb {
+                Breadcrumb::Node(label) => {
+                    if !path.is_empty() {
+                        path.push_str(" > ");
+                    }
+                    path.push_str(label);
+                }
+                Breadcrumb::Child(child) => write!(path, "[{child}]").unwrap(),
+            }
+        }
+        (!path.is_empty()).then_some(path)
+    }
+
+    /// The first divergent plan node, from the legacy plan (as compared, after the ignored
+    /// differences are erased). Returns `None` when the plans already diverge on having a root
+    /// node.
+    pub fn divergent_node(&self) -> Option<&PlanNode> {
+        self.node.as_deref()
+    }
+
+    /// Where the first divergent node merges its data in the response: the keys of the paths of
+    /// the `Flatten` nodes down to it (itself included), e.g. `["topProducts", "reviews"]`.
+    pub fn divergence_response_path(&self) -> &[String] {
+        &self.response_path
+    }
+
     pub fn full_description(&self) -> String {
         format!("{}\n\nBacktrace:\n{}", self.description, self.backtrace)
     }
@@ -51,6 +122,9 @@ impl MatchFailure {
     fn new(description: String) -> MatchFailure {
         MatchFailure {
             description,
+            path: Vec::new(),
+            node: None,
+            response_path: Vec::new(),
             backtrace: std::backtrace::Backtrace::force_capture(),
         }
     }
@@ -58,8 +132,62 @@ impl MatchFailure {
     fn add_description(self: MatchFailure, description: &str) -> MatchFailure {
         MatchFailure {
             description: format!("{}\n{}", self.description, description),
-            backtrace: self.backtrace,
+            ..self
+        }
+    }
+
+    fn under_node(mut self, node: &PlanNode) -> MatchFailure {
+        if self.node.is_none() {
+            self.node = Some(Box::new(node.clone()));
         }
+        if let PlanNode::Flatten(flatten) = node {
+            let keys = flatten.path.iter().filter_map(|element| match element {
+                PathElement::Key(key, _) if !key.is_empty() => Some(key.clone()),
+                _ => None,
+            });
+            self.response_path.splice(0..0, keys);
+        }
+        self.path.push(Breadcrumb::Node(plan_node_label(node)));
+        self
+    }
+
+    fn under_child(mut self, child: impl ToString) -> MatchFailure {
+        self.path.push(Breadcrumb::Child(child.to_string()));
+        self
+    }
+}
+
+/// How mismatch reports name the two plans compared, e.g. `legacy` and `native`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sides {
+    /// The plan compared first (the left side of diffs).
+    pub this: &'static str,
+    pub other: &'static str,
+}
+
+impl Sides {
+    /// A legacy plan compared with a native one, the usual comparison.
+    pub const PLANNERS: Sides = Sides {
+        this: "legacy",
+        other: "native",
+    };
+
+    /// Two legacy plans, planned with the usual config (A) and with `--legacy-b-config` (B).
+    pub const LEGACY_AB: Sides = Sides {
+        this: "A",
+        other: "B",
+    };
+
+    /// Two native plans, planned against `--schema` and `--schema-b`.
+    pub const SCHEMA_DRIFT: Sides = Sides {
+        this: "schema",
+        other: "schema-b",
+    };
+}
+
+impl Default for Sides {
+    fn default() -> Self {
+        Self::PLANNERS
     }
 }
 
@@ -94,57 +222,362 @@ pub fn plan_matches(
     js_plan: &QueryPlanResult,
     rust_plan: &NativeQueryPlan,
 ) -> Result<(), MatchFailure> {
-    let js_root_node = &js_plan.query_plan.node;
+    plan_matches_with_options(js_plan, rust_plan, &CompareOptions::default())
+}
+
+/// Differences to ignore when comparing plans. The default compares plans strictly.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompareOptions {
+    /// Ignore `__typename` field selections in `requires` and in subgraph operations, since the
+    /// planners differ in where they add them.
+    ///
+    /// Caveat: `__typename` is still compared directly under the entity representations of
+    /// `requires` (needed to resolve entities), and when aliased (part of the response). Elsewhere,
+    /// a planner dropping a `__typename` the subgraph actually needs goes unnoticed.
+    pub ignore_typename: bool,
+
+    /// Ignore `FetchNode::requires` entirely.
+    ///
+    /// This is a triage aid for bucketing mismatches into `requires`-only and structural ones, not
+    /// a correctness-preserving comparison: plans fetching different entity representations match.
+    pub ignore_requires: bool,
+
+    /// Normalize subgraph operations before comparing them, without their subgraph schemas: expand
+    /// named fragments, merge duplicate fields and inline fragments, and sort selection sets.
+    ///
+    /// This is heuristic and lossy: operations that normalize the same may not be equivalent for
+    /// every schema. Operations failing to parse are compared as strings.
+    pub semantic_operations: bool,
+
+    /// With `semantic_operations`, also ignore `__typename` field selections in subgraph
+    /// operations, since executors add them anyway. Unlike `ignore_typename`, `requires` is still
+    /// compared strictly.
+    ///
+    /// Aliased `__typename` fields (part of the response) are kept. Without `semantic_operations`,
+    /// this has no effect.
+    pub ignore_operation_typename: bool,
+
+    /// With `semantic_operations`, also treat the two encodings of a key the planners use in
+    /// `requires` as equal: below a field, a selection set made only of an inline fragment with a
+    /// type condition, which selects (unaliased) `__typename`, matches the fragment's selections
+    /// without it, e.g. `dimensions { ... on Dimensions { __typename weight } }` matches
+    /// `dimensions { __typename weight }`.
+    ///
+    /// The type condition is assumed to be the type of the field, which it can't check without the
+    /// schema. Top-level `requires` selections (the entity representations) are compared as usual,
+    /// since their type conditions select the entities. Without `semantic_operations`, this has no
+    /// effect.
+    pub semantic_requires: bool,
+
+    /// Compare the shape of the plan trees only: node kinds, their positions, flatten paths and
+    /// conditions, treating the contents of all fetches (and subscription primaries) as equal.
+    ///
+    /// This is a coarse triage mode for separating structurally identical plans from divergent
+    /// ones, not a correctness check: plans fetching entirely different data from different
+    /// subgraphs match. It makes the other options moot.
+    pub shape_only: bool,
+
+    /// Compare the children of `Sequence` nodes made only of independent fetches in any order, as
+    /// if they were `Parallel` nodes, since the planners differ in whether they parallelize them.
+    ///
+    /// This is a risky heuristic: fetches are assumed independent when they have no `requires`
+    /// (nor context rewrites) and aren't mutations, ignoring data dependencies it can't see, e.g.
+    /// side effects of one subgraph observed by another. Sequences containing any other node,
+    /// such as `Flatten` nodes, keep their order.
+    pub assume_independent_fetches: bool,
+
+    /// Read a `Condition` node on a `!`-prefixed variable (`!x`) as the negation of the variable,
+    /// equivalent to the condition on `x` with the `if` and `else` branches swapped.
+    ///
+    /// Conditions of the plan format are bare variable names, and neither planner negates them.
+    /// This recognizes plans written with negated conditions (e.g. by other tools, or by hand) and
+    /// nothing else: conditions on different variables never match, even if the operation makes
+    /// one the negation of the other. Missing and empty branches always match regardless.
+    pub negated_conditions: bool,
+
+    /// Ignore the identifiers the planners assign: fetch ids, defer labels and the operation
+    /// names of fetches and subscription primaries.
+    ///
+    /// Fetch ids are replaced with keys derived from the fetch contents, and
+    /// `DeferredNode::depends` is rewritten to match, so a deferred node depending on a different
+    /// fetch is still reported. Labels are only the defer labels of the operation, so this hides a
+    /// planner dropping one.
+    pub ignore_identifiers: bool,
+
+    /// Normalize the subgraph operations of these services with the types of their subgraph
+    /// schema, once validated against it, like `semantic_operations` but without its guesswork:
+    /// inline fragments on the type of their enclosing field are flattened as well.
+    ///
+    /// Operations of other services, or invalid against their schema, are compared as without it.
+    pub subgraph_schemas: SubgraphSchemas,
+}
+
+/// Like `plan_matches`, but ignoring the differences selected by `options`.
+pub fn plan_matches_with_options(
+    js_plan: &QueryPlanResult,
+    rust_plan: &NativeQueryPlan,
+    options: &CompareOptions,
+) -> Result<(), MatchFailure> {
+    let rust_root_node = convert_root_query_plan_node(rust_plan);
+    compare_root_nodes(
+        js_plan.root_node(),
+        rust_root_node.as_ref(),
+        options,
+        Sides::PLANNERS,
+    )
+}
+
+/// Compares two legacy plans, e.g. produced under two legacy planner configs, ignoring the
+/// differences selected by `options`.
+pub fn legacy_plans_match(
+    this: &QueryPlanResult,
+    other: &QueryPlanResult,
+    options: &CompareOptions,
+) -> Result<(), MatchFailure> {
+    compare_root_nodes(
+        this.root_node(),
+        other.root_node(),
+        options,
+        Sides::LEGACY_AB,
+    )
+}
+
+/// Compares two native plans, e.g. planned against two schema versions, ignoring the differences
+/// selected by `options`.
+pub fn native_plans_match(
+    this: &NativeQueryPlan,
+    other: &NativeQueryPlan,
+    options: &CompareOptions,
+) -> Result<(), MatchFailure> {
+    let this = convert_root_query_plan_node(this);
+    let other = convert_root_query_plan_node(other);
+    compare_root_nodes(this.as_ref(), other.as_ref(), options, Sides::SCHEMA_DRIFT)
+}
+
+/// Compares two plan trees, ignoring the differences selected by `options`.
+///
+/// This is the comparator behind the planner-specific entry points above, for plans already in
+/// `PlanNode` form (native plans are converted with `convert_native_plan`).
+pub fn compare_nodes(
+    this: &PlanNode,
+    other: &PlanNode,
+    options: &CompareOptions,
+) -> Result<(), MatchFailure> {
+    compare_root_nodes(Some(this), Some(other), options, Sides::PLANNERS)
+}
+
+/// Like `plan_matches_with_options`, comparing the plan fingerprints first: equal fingerprints
+/// guarantee a match, and are much cheaper to compare. Both steps run on a stack sized for the
+/// depth of the plans.
+pub fn plan_matches_with_fingerprint(
+    js_plan: &QueryPlanResult,
+    rust_plan: &NativeQueryPlan,
+    options: &CompareOptions,
+) -> Result<(), MatchFailure> {
+    let rust_root_node = convert_root_query_plan_node(rust_plan);
+    let (this, other) = (js_plan.root_node(), rust_root_node.as_ref());
+    with_stack_for_depth(max_depth(this, other), || {
+        if this.map(plan_fingerprint) == other.map(plan_fingerprint) {
+            return Ok(());
+        }
+        compare_trees(this, other, options, Sides::PLANNERS)
+    })
+}
+
+// Like `compare_nodes`, for plans that may have no root node, naming them `sides` in reports.
+fn compare_root_nodes(
+    this: Option<&PlanNode>,
+    other: Option<&PlanNode>,
+    options: &CompareOptions,
+    sides: Sides,
+) -> Result<(), MatchFailure> {
+    with_stack_for_depth(max_depth(this, other), || {
+        compare_trees(this, other, options, sides)
+    })
+}
+
+// Compares plan trees on the current stack: see `with_stack_for_depth`.
+fn compare_trees(
+    this: Option<&PlanNode>,
+    other: Option<&PlanNode>,
+    options: &CompareOptions,
+    sides: Sides,
+) -> Result<(), MatchFailure> {
+    let this = this.map(|node| apply_compare_options(node, options));
+    let other = other.map(|node| apply_compare_options(node, options));
+    opt_plan_node_matches_with_sides(&this, &other, sides)
+}
+
+fn max_depth(this: Option<&PlanNode>, other: Option<&PlanNode>) -> usize {
+    this.into_iter()
+        .chain(other)
+        .map(PlanNode::depth)
+        .max()
+        .unwrap_or(0)
+}
+
+// The comparison (like fingerprinting) walks plan trees recursively, which would overflow the
+// stack of the calling thread on pathologically deep plans (e.g. machine-generated `Sequence`
+// chains). Plans deeper than this are compared on a thread with a stack sized for their depth.
+const MAX_INLINE_DEPTH: usize = 256;
+
+// A generous bound on the stack used by the comparison per level of a plan tree.
+const STACK_BYTES_PER_LEVEL: usize = 16 * 1024;
+
+fn with_stack_for_depth<T: Send>(depth: usize, f: impl FnOnce() -> T + Send) -> T {
+    if depth <= MAX_INLINE_DEPTH {
+        return f();
+    }
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name("deep-plan".to_string())
+            .stack_size(depth.saturating_mul(STACK_BYTES_PER_LEVEL))
+            .spawn_scoped(scope, f)
+            .expect("failed to spawn a thread for comparing a deep plan")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// Returns true if both plans have the same fingerprint, in which case they are guaranteed to
+/// match.
+///
+/// This is much cheaper than `plan_matches` and meant as a fast path before it. Plans with
+/// different fingerprints may still match.
+pub fn same_plan_fingerprint(js_plan: &QueryPlanResult, rust_plan: &NativeQueryPlan) -> bool {
     let rust_root_node = convert_root_query_plan_node(rust_plan);
-    opt_plan_node_matches(js_root_node, &rust_root_node)
+    let (this, other) = (js_plan.root_node(), rust_root_node.as_ref());
+    with_stack_for_depth(max_depth(this, other), || {
+        this.map(plan_fingerprint) == other.map(plan_fingerprint)
+    })
 }
 
 // Note: Reexported under `apollo_router::_private`
 pub fn diff_plan(js_plan: &QueryPlanResult, rust_plan: &NativeQueryPlan) -> String {
-    let js_root_node = &js_plan.query_plan.node;
+    diff_plan_with_style(js_plan, rust_plan, &Style::plain())
+}
+
+pub fn diff_plan_with_style(
+    js_plan: &QueryPlanResult,
+    rust_plan: &NativeQueryPlan,
+    style: &Style,
+) -> String {
     let rust_root_node = convert_root_query_plan_node(rust_plan);
+    diff_root_nodes(js_plan.root_node(), rust_root_node.as_ref(), style)
+}
 
-    match (js_root_node, rust_root_node) {
-        (None, None) => String::from(""),
-        (None, Some(rust)) => {
-            let rust = &format!("{rust:#?}");
-            let differences = diff::lines("", rust);
-            render_diff(&differences)
-        }
-        (Some(js), None) => {
-            let js = &format!("{js:#?}");
-            let differences = diff::lines(js, "");
-            render_diff(&differences)
-        }
-        (Some(js), Some(rust)) => {
-            let rust = &format!("{rust:#?}");
-            let js = &format!("{js:#?}");
-            let differences = diff::lines(js, rust);
-            render_diff(&differences)
+/// Like `diff_plan_with_style`, between two legacy plans.
+pub fn diff_legacy_plans_with_style(
+    this: &QueryPlanResult,
+    other: &QueryPlanResult,
+    style: &Style,
+) -> String {
+    diff_root_nodes(this.root_node(), other.root_node(), style)
+}
+
+/// Like `diff_plan_with_style`, between two native plans.
+pub fn diff_native_plans_with_style(
+    this: &NativeQueryPlan,
+    other: &NativeQueryPlan,
+    style: &Style,
+) -> String {
+    let this = convert_root_query_plan_node(this);
+    let other = convert_root_query_plan_node(other);
+    diff_root_nodes(this.as_ref(), other.as_ref(), style)
+}
+
+/// Diffs two plan trees in the given diff style. `labels` name the plans in the header of unified
+/// diffs.
+pub fn diff_nodes_with_style(
+    this: Option<&PlanNode>,
+    other: Option<&PlanNode>,
+    diff_style: DiffStyle,
+    labels: [&str; 2],
+    style: &Style,
+) -> String {
+    match diff_style {
+        DiffStyle::Structural => diff_root_nodes(this, other, style),
+        DiffStyle::Unified => {
+            let [this_label, other_label] = labels;
+            let format = if style.explain {
+                format_plan_explained
+            } else {
+                format_plan
+            };
+            let this = format(this);
+            let other = format(other);
+            let mut output = String::new();
+            if this != other {
+                writeln!(&mut output, "--- {this_label}").expect("write will never fail");
+                writeln!(&mut output, "+++ {other_label}").expect("write will never fail");
+            }
+            output.push_str(&unified_diff_with_style(&this, &other, style));
+            output
         }
     }
 }
 
+fn diff_root_nodes(this: Option<&PlanNode>, other: Option<&PlanNode>, style: &Style) -> String {
+    if this.is_none() && other.is_none() {
+        return String::from("");
+    }
+    let differences = diff::lines(&render_plan(this), &render_plan(other));
+    render_diff_with_style(&differences, style)
+}
+
 // Note: Reexported under `apollo_router::_private`
 pub fn render_diff(differences: &[diff::Result<&str>]) -> String {
+    render_diff_with_style(differences, &Style::plain())
+}
+
+pub fn render_diff_with_style(differences: &[diff::Result<&str>], style: &Style) -> String {
+    let mut elided = if style.elide_matching {
+        matching_subtrees(differences)
+    } else {
+        Vec::new()
+    }
+    .into_iter()
+    .peekable();
     let mut output = String::new();
-    for diff_line in differences {
+    let mut write_line = |marker: char, line: &str| {
+        let line = style.reindent(line);
+        for chunk in style.wrap(&line, 1) {
+            let line = format!("{marker}{chunk}");
+            let line = match marker {
+                '-' => style.removed(&line),
+                '+' => style.added(&line),
+                _ => line,
+            };
+            writeln!(&mut output, "{line}").expect("write will never fail");
+        }
+    };
+    let mut index = 0;
+    while let Some(diff_line) = differences.get(index) {
+        if let Some((range, nodes)) = elided.next_if(|(range, _)| range.start == index) {
+            let line = diff_line_text(diff_line);
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let plural = if nodes == 1 { "" } else { "s" };
+            write_line(' ', &format!("{indent}… {nodes} matching node{plural} …"));
+            index = range.end;
+            continue;
+        }
+        index += 1;
         match diff_line {
             diff::Result::Left(l) => {
                 let trimmed = l.trim();
                 if !trimmed.starts_with('#') && !trimmed.is_empty() {
-                    writeln!(&mut output, "-{l}").expect("write will never fail");
+                    write_line('-', l);
                 } else {
-                    writeln!(&mut output, " {l}").expect("write will never fail");
+                    write_line(' ', l);
                 }
             }
             diff::Result::Both(l, _) => {
-                writeln!(&mut output, " {l}").expect("write will never fail");
+                write_line(' ', l);
             }
             diff::Result::Right(r) => {
                 let trimmed = r.trim();
                 if trimmed != "---" && !trimmed.is_empty() {
-                    writeln!(&mut output, "+{r}").expect("write will never fail");
+                    write_line('+', r);
                 }
             }
         }
@@ -152,6 +585,150 @@ pub fn render_diff(differences: &[diff::Result<&str>]) -> String {
     output
 }
 
+// The ranges of `differences` (a line diff of `Debug`-formatted plans) spanning plan node subtrees
+// whose lines all match, with the number of plan nodes in each. Adjacent subtrees are merged, and
+// subtrees containing a difference are searched for smaller matching subtrees.
+fn matching_subtrees(differences: &[diff::Result<&str>]) -> Vec<(std::ops::Range<usize>, usize)> {
+    const PLAN_NODES: [&str; 7] = [
+        "Sequence {",
+        "Parallel {",
+        "Fetch(",
+        "Flatten(",
+        "Defer {",
+        "Subscription {",
+        "Condition {",
+    ];
+    let is_plan_node = |line: &str| PLAN_NODES.contains(&line.trim());
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let mut subtrees: Vec<(std::ops::Range<usize>, usize)> = Vec::new();
+    let mut start = 0;
+    while start < differences.len() {
+        let line = diff_line_text(&differences[start]);
+        if !is_plan_node(line) {
+            start += 1;
+            continue;
+        }
+        // The subtree ends at the closing line at the same indentation, e.g. `},` or `),`.
+        let end = differences[start + 1..].iter().position(|difference| {
+            let closing = diff_line_text(difference);
+            indent(closing) == indent(line) && closing.trim_start().starts_with(['}', ')'])
+        });
+        let Some(end) = end.map(|end| start + 1 + end + 1) else {
+            start += 1;
+            continue;
+        };
+        let subtree = &differences[start..end];
+        if !subtree
+            .iter()
+            .all(|difference| matches!(difference, diff::Result::Both(..)))
+        {
+            start += 1;
+            continue;
+        }
+        let nodes = subtree
+            .iter()
+            .filter(|difference| is_plan_node(diff_line_text(difference)))
+            .count();
+        match subtrees.last_mut() {
+            Some((range, count)) if range.end == start => {
+                range.end = end;
+                *count += nodes;
+            }
+            _ => subtrees.push((start..end, nodes)),
+        }
+        start = end;
+    }
+    subtrees
+}
+
+fn diff_line_text<'a>(difference: &diff::Result<&'a str>) -> &'a str {
+    match difference {
+        diff::Result::Left(line) | diff::Result::Both(line, _) | diff::Result::Right(line) => line,
+    }
+}
+
+/// Renders the line differences between two texts as the hunks of a unified diff (as `diff -u`),
+/// with three lines of context around each change.
+pub fn unified_diff_with_style(this: &str, other: &str, style: &Style) -> String {
+    const CONTEXT: usize = 3;
+    // Note: `diff::lines` reports a final newline as an extra empty line.
+    let differences = diff::lines(
+        this.strip_suffix('\n').unwrap_or(this),
+        other.strip_suffix('\n').unwrap_or(other),
+    );
+    // Ranges of `differences`, each a change with its context. Overlapping ranges are merged.
+    let mut hunks: Vec<std::ops::Range<usize>> = Vec::new();
+    for (index, difference) in differences.iter().enumerate() {
+        if matches!(difference, diff::Result::Both(..)) {
+            continue;
+        }
+        let start = index.saturating_sub(CONTEXT);
+        let end = (index + CONTEXT + 1).min(differences.len());
+        match hunks.last_mut() {
+            Some(hunk) if start <= hunk.end => hunk.end = end,
+            _ => hunks.push(start..end),
+        }
+    }
+    // The (this, other) line numbers, from 1, of each entry of `differences`.
+    let mut line_numbers = Vec::with_capacity(differences.len());
+    let (mut this_line, mut other_line) = (1, 1);
+    for difference in &differences {
+        line_numbers.push((this_line, other_line));
+        match difference {
+            diff::Result::Left(_) => this_line += 1,
+            diff::Result::Right(_) => other_line += 1,
+            diff::Result::Both(..) => {
+                this_line += 1;
+                other_line += 1;
+            }
+        }
+    }
+
+    let mut output = String::new();
+    let mut write_line = |marker: char, line: &str| {
+        for chunk in style.wrap(line, 1) {
+            let line = format!("{marker}{chunk}");
+            let line = match marker {
+                '-' => style.removed(&line),
+                '+' => style.added(&line),
+                _ => line,
+            };
+            writeln!(&mut output, "{line}").expect("write will never fail");
+        }
+    };
+    for hunk in hunks {
+        let lines = &differences[hunk.clone()];
+        let this_len = lines
+            .iter()
+            .filter(|line| !matches!(line, diff::Result::Right(_)))
+            .count();
+        let other_len = lines
+            .iter()
+            .filter(|line| !matches!(line, diff::Result::Left(_)))
+            .count();
+        // Note: As in `diff -u`, an empty range starts at the line before it.
+        let range = |start: usize, len: usize| {
+            let start = if len == 0 { start - 1 } else { start };
+            format!("{start},{len}")
+        };
+        let (this_start, other_start) = line_numbers[hunk.start];
+        let header = format!(
+            "@@ -{} +{} @@",
+            range(this_start, this_len),
+            range(other_start, other_len)
+        );
+        writeln!(&mut output, "{}", style.hunk_header(&header)).expect("write will never fail");
+        for line in lines {
+            match line {
+                diff::Result::Left(l) => write_line('-', l),
+                diff::Result::Both(l, _) => write_line(' ', l),
+                diff::Result::Right(r) => write_line('+', r),
+            }
+        }
+    }
+    output
+}
+
 //==================================================================================================
 // Vec comparison functions
 
@@ -169,8 +746,10 @@ fn vec_matches_result<T>(
     std::iter::zip(this, other)
         .enumerate()
         .try_fold((), |_acc, (index, (this, other))| {
-            item_matches(this, other)
-                .map_err(|err| err.add_description(&format!("under item[{}]", index)))
+            item_matches(this, other).map_err(|err| {
+                err.add_description(&format!("under item[{}]", index))
+                    .under_child(index)
+            })
         })?;
     Ok(())
 }
@@ -352,10 +931,12 @@ fn vec_matches_result_as_set<T>(
                     message
                 )));
             };
-            Err(err.add_description(&format!(
-                "under a sole unmatched pair ({} -> {}) in a set comparison",
-                unmatched_pair.0, unmatched_pair.1
-            )))
+            Err(err
+                .add_description(&format!(
+                    "under a sole unmatched pair ({} -> {}) in a set comparison",
+                    unmatched_pair.0, unmatched_pair.1
+                ))
+                .under_child(unmatched_pair.0))
         } else {
             Err(MatchFailure::new(format!("mismatched sets:\n{}", message)))
         }
@@ -369,18 +950,74 @@ fn option_to_string(name: Option<impl ToString>) -> String {
     name.map_or_else(|| "<none>".to_string(), |name| name.to_string())
 }
 
-fn plan_node_matches(this: &PlanNode, other: &PlanNode) -> Result<(), MatchFailure> {
+fn plan_node_matches(this: &PlanNode, other: &PlanNode, sides: Sides) -> Result<(), MatchFailure> {
+    let (this, other) = (unwrap_single_child(this), unwrap_single_child(other));
+    plan_node_contents_match(this, other, sides).map_err(|err| err.under_node(this))
+}
+
+// A `Sequence` or `Parallel` node with a single child is equivalent to its child, and the
+// planners differ in whether they wrap a lone branch.
+pub(crate) fn unwrap_single_child(mut node: &PlanNode) -> &PlanNode {
+    while let PlanNode::Sequence { nodes } | PlanNode::Parallel { nodes } = node {
+        match nodes.as_slice() {
+            [child] => node = child,
+            _ => break,
+        }
+    }
+    node
+}
+
+// A branch of a `Condition` node, or `None` if it is missing or empty (executing nothing), since
+// the planners differ in omitting empty branches.
+pub(crate) fn condition_branch(branch: &Option<Box<PlanNode>>) -> Option<&PlanNode> {
+    branch
+        .as_deref()
+        .map(unwrap_single_child)
+        .filter(|node| !is_empty_container(node))
+}
+
+fn is_empty_container(node: &PlanNode) -> bool {
+    matches!(node, PlanNode::Sequence { nodes } | PlanNode::Parallel { nodes } if nodes.is_empty())
+}
+
+// Labels a node in divergence paths.
+fn plan_node_label(node: &PlanNode) -> String {
+    let kind = node.kind_name();
+    match node {
+        PlanNode::Sequence { .. } | PlanNode::Parallel { .. } | PlanNode::Defer { .. } => {
+            kind.to_string()
+        }
+        PlanNode::Fetch(fetch) => format!("{kind}({})", fetch.service_name),
+        PlanNode::Flatten(flatten) => {
+            // Skip the empty key root from the JS query planner, like `same_path`.
+            let path = match flatten.path.0.split_first() {
+                Some((PathElement::Key(key, None), rest)) if key.is_empty() => Path(rest.to_vec()),
+                _ => flatten.path.clone(),
+            };
+            format!("{kind}({path})")
+        }
+        PlanNode::Subscription { primary, .. } => format!("{kind}({})", primary.service_name),
+        PlanNode::Condition { condition, .. } => format!("{kind}({condition})"),
+    }
+}
+
+fn plan_node_contents_match(
+    this: &PlanNode,
+    other: &PlanNode,
+    sides: Sides,
+) -> Result<(), MatchFailure> {
+    let nodes_match = |this: &PlanNode, other: &PlanNode| plan_node_matches(this, other, sides);
     match (this, other) {
         (PlanNode::Sequence { nodes: this }, PlanNode::Sequence { nodes: other }) => {
-            vec_matches_result(this, other, plan_node_matches)
+            vec_matches_result(this, other, nodes_match)
                 .map_err(|err| err.add_description("under Sequence node"))?;
         }
         (PlanNode::Parallel { nodes: this }, PlanNode::Parallel { nodes: other }) => {
-            vec_matches_result_as_set(this, other, plan_node_matches)
+            vec_matches_result_as_set(this, other, nodes_match)
                 .map_err(|err| err.add_description("under Parallel node"))?;
         }
         (PlanNode::Fetch(this), PlanNode::Fetch(other)) => {
-            fetch_node_matches(this, other).map_err(|err| {
+            fetch_node_matches(this, other, sides).map_err(|err| {
                 err.add_description(&format!(
                     "under Fetch node (operation name: {})",
                     option_to_string(this.operation_name.as_ref())
@@ -388,7 +1025,7 @@ fn plan_node_matches(this: &PlanNode, other: &PlanNode) -> Result<(), MatchFailu
             })?;
         }
         (PlanNode::Flatten(this), PlanNode::Flatten(other)) => {
-            flatten_node_matches(this, other).map_err(|err| {
+            flatten_node_matches(this, other, sides).map_err(|err| {
                 err.add_description(&format!("under Flatten node (path: {})", this.path))
             })?;
         }
@@ -399,8 +1036,11 @@ fn plan_node_matches(this: &PlanNode, other: &PlanNode) -> Result<(), MatchFailu
                 deferred: other_deferred,
             },
         ) => {
-            defer_primary_node_matches(primary, other_primary)?;
-            vec_matches_result(deferred, other_deferred, deferred_node_matches)?;
+            defer_primary_node_matches(primary, other_primary, sides)
+                .map_err(|err| err.under_child("primary"))?;
+            vec_matches_result(deferred, other_deferred, |this, other| {
+                deferred_node_matches(this, other, sides)
+            })?;
         }
         (
             PlanNode::Subscription { primary, rest },
@@ -409,9 +1049,11 @@ fn plan_node_matches(this: &PlanNode, other: &PlanNode) -> Result<(), MatchFailu
                 rest: other_rest,
             },
         ) => {
-            subscription_primary_matches(primary, other_primary)?;
-            opt_plan_node_matches(rest, other_rest)
-                .map_err(|err| err.add_description("under Subscription"))?;
+            subscription_primary_matches(primary, other_primary, sides)?;
+            opt_plan_node_matches_with_sides(rest, other_rest, sides).map_err(|err| {
+                err.add_description("under Subscription")
+                    .under_child("rest")
+            })?;
         }
         (
             PlanNode::Condition {
@@ -426,15 +1068,46 @@ fn plan_node_matches(this: &PlanNode, other: &PlanNode) -> Result<(), MatchFailu
             },
         ) => {
             check_match_eq!(condition, other_condition);
-            opt_plan_node_matches(if_clause, other_if_clause)
-                .map_err(|err| err.add_description("under Condition node (if_clause)"))?;
-            opt_plan_node_matches(else_clause, other_else_clause)
-                .map_err(|err| err.add_description("under Condition node (else_clause)"))?;
+            let (if_clause, other_if_clause) = (
+                condition_branch(if_clause),
+                condition_branch(other_if_clause),
+            );
+            let (else_clause, other_else_clause) = (
+                condition_branch(else_clause),
+                condition_branch(other_else_clause),
+            );
+            opt_plan_node_matches_with_sides(&if_clause, &other_if_clause, sides).map_err(
+                |err| {
+                    err.add_description("under Condition node (if_clause)")
+                        .under_child("if")
+                },
+            )?;
+            opt_plan_node_matches_with_sides(&else_clause, &other_else_clause, sides).map_err(
+                |err| {
+                    err.add_description("under Condition node (else_clause)")
+                        .under_child("else")
+                },
+            )?;
         }
         _ => {
+            // Planners aren't expected to produce empty `Sequence` or `Parallel` nodes.
+            if let Some(empty) = [this, other]
+                .into_iter()
+                .find(|node| is_empty_container(node))
+            {
+                return Err(MatchFailure::new(format!(
+                    "empty {} node, matching no other node\nleft: {:?}\nright: {:?}",
+                    plan_node_label(empty),
+                    this,
+                    other
+                )));
+            }
             return Err(MatchFailure::new(format!(
-                "mismatched plan node types\nleft: {:?}\nright: {:?}",
-                this, other
+                "mismatched plan node types: {} vs {}\nleft: {:?}\nright: {:?}",
+                this.kind_name(),
+                other.kind_name(),
+                this,
+                other
             )));
         }
     };
@@ -444,6 +1117,14 @@ fn plan_node_matches(this: &PlanNode, other: &PlanNode) -> Result<(), MatchFailu
 pub(crate) fn opt_plan_node_matches(
     this: &Option<impl Borrow<PlanNode>>,
     other: &Option<impl Borrow<PlanNode>>,
+) -> Result<(), MatchFailure> {
+    opt_plan_node_matches_with_sides(this, other, Sides::PLANNERS)
+}
+
+fn opt_plan_node_matches_with_sides(
+    this: &Option<impl Borrow<PlanNode>>,
+    other: &Option<impl Borrow<PlanNode>>,
+    sides: Sides,
 ) -> Result<(), MatchFailure> {
     match (this, other) {
         (None, None) => Ok(()),
@@ -452,11 +1133,15 @@ pub(crate) fn opt_plan_node_matches(
             this.is_some(),
             other.is_some()
         ))),
-        (Some(this), Some(other)) => plan_node_matches(this.borrow(), other.borrow()),
+        (Some(this), Some(other)) => plan_node_matches(this.borrow(), other.borrow(), sides),
     }
 }
 
-fn fetch_node_matches(this: &FetchNode, other: &FetchNode) -> Result<(), MatchFailure> {
+fn fetch_node_matches(
+    this: &FetchNode,
+    other: &FetchNode,
+    sides: Sides,
+) -> Result<(), MatchFailure> {
     let FetchNode {
         service_name,
         requires,
@@ -483,17 +1168,33 @@ fn fetch_node_matches(this: &FetchNode, other: &FetchNode) -> Result<(), MatchFa
     check_match_eq!(*operation_kind, other.operation_kind);
     check_match_eq!(*id, other.id);
     check_match!(same_requires(requires, &other.requires));
-    check_match!(vec_matches_sorted(variable_usages, &other.variable_usages));
-    check_match!(same_rewrites(input_rewrites, &other.input_rewrites));
-    check_match!(same_rewrites(output_rewrites, &other.output_rewrites));
-    check_match!(same_rewrites(context_rewrites, &other.context_rewrites));
-    operation_matches(operation, &other.operation)?;
+    variable_usages_match(variable_usages, &other.variable_usages, sides)?;
+    rewrites_match(
+        "input rewrites",
+        input_rewrites,
+        &other.input_rewrites,
+        sides,
+    )?;
+    rewrites_match(
+        "output rewrites",
+        output_rewrites,
+        &other.output_rewrites,
+        sides,
+    )?;
+    rewrites_match(
+        "context rewrites",
+        context_rewrites,
+        &other.context_rewrites,
+        sides,
+    )?;
+    operation_matches(operation, &other.operation, sides)?;
     Ok(())
 }
 
 fn subscription_primary_matches(
     this: &SubscriptionNode,
     other: &SubscriptionNode,
+    sides: Sides,
 ) -> Result<(), MatchFailure> {
     let SubscriptionNode {
         service_name,
@@ -506,22 +1207,63 @@ fn subscription_primary_matches(
     } = this;
     check_match_eq!(*service_name, other.service_name);
     check_match_eq!(*operation_kind, other.operation_kind);
-    check_match!(vec_matches_sorted(variable_usages, &other.variable_usages));
-    check_match!(same_rewrites(input_rewrites, &other.input_rewrites));
-    check_match!(same_rewrites(output_rewrites, &other.output_rewrites));
-    operation_matches(operation, &other.operation)?;
+    variable_usages_match(variable_usages, &other.variable_usages, sides)?;
+    rewrites_match(
+        "input rewrites",
+        input_rewrites,
+        &other.input_rewrites,
+        sides,
+    )?;
+    rewrites_match(
+        "output rewrites",
+        output_rewrites,
+        &other.output_rewrites,
+        sides,
+    )?;
+    operation_matches(operation, &other.operation, sides)?;
     Ok(())
 }
 
-fn defer_primary_node_matches(this: &Primary, other: &Primary) -> Result<(), MatchFailure> {
+// Compares variable usages as sets, naming the variables used by only one of the fetches.
+fn variable_usages_match(
+    this: &[Arc<str>],
+    other: &[Arc<str>],
+    sides: Sides,
+) -> Result<(), MatchFailure> {
+    let this_variables: BTreeSet<&str> = this.iter().map(|name| &**name).collect();
+    let other_variables: BTreeSet<&str> = other.iter().map(|name| &**name).collect();
+    if this_variables == other_variables {
+        return Ok(());
+    }
+    let mut message = String::from("mismatch between variable usages:");
+    for name in this_variables.difference(&other_variables) {
+        let Sides { this, other } = sides;
+        write!(message, "\n{this} fetch uses `${name}` not used by {other}").unwrap();
+    }
+    for name in other_variables.difference(&this_variables) {
+        let Sides { this, other } = sides;
+        write!(message, "\n{other} fetch uses `${name}` not used by {this}").unwrap();
+    }
+    Err(MatchFailure::new(message))
+}
+
+fn defer_primary_node_matches(
+    this: &Primary,
+    other: &Primary,
+    sides: Sides,
+) -> Result<(), MatchFailure> {
     let Primary { subselection, node } = this;
-    opt_document_string_matches(subselection, &other.subselection)
+    opt_subselection_matches(subselection, &other.subselection)
         .map_err(|err| err.add_description("under defer primary subselection"))?;
-    opt_plan_node_matches(node, &other.node)
+    opt_plan_node_matches_with_sides(node, &other.node, sides)
         .map_err(|err| err.add_description("under defer primary plan node"))
 }
 
-fn deferred_node_matches(this: &DeferredNode, other: &DeferredNode) -> Result<(), MatchFailure> {
+fn deferred_node_matches(
+    this: &DeferredNode,
+    other: &DeferredNode,
+    sides: Sides,
+) -> Result<(), MatchFailure> {
     let DeferredNode {
         depends,
         label,
@@ -533,16 +1275,20 @@ fn deferred_node_matches(this: &DeferredNode, other: &DeferredNode) -> Result<()
     check_match_eq!(*depends, other.depends);
     check_match_eq!(*label, other.label);
     check_match_eq!(*query_path, other.query_path);
-    opt_document_string_matches(subselection, &other.subselection)
+    opt_subselection_matches(subselection, &other.subselection)
         .map_err(|err| err.add_description("under deferred subselection"))?;
-    opt_plan_node_matches(node, &other.node)
+    opt_plan_node_matches_with_sides(node, &other.node, sides)
         .map_err(|err| err.add_description("under deferred node"))
 }
 
-fn flatten_node_matches(this: &FlattenNode, other: &FlattenNode) -> Result<(), MatchFailure> {
+fn flatten_node_matches(
+    this: &FlattenNode,
+    other: &FlattenNode,
+    sides: Sides,
+) -> Result<(), MatchFailure> {
     let FlattenNode { path, node } = this;
     check_match!(same_path(path, &other.path));
-    plan_node_matches(node, &other.node)
+    plan_node_matches(node, &other.node, sides)
 }
 
 fn same_path(this: &Path, other: &Path) -> bool {
@@ -675,15 +1421,86 @@ fn same_rewrites(x: &Option<Vec<DataRewrite>>, y: &Option<Vec<DataRewrite>>) ->
     }
 }
 
+// Compares rewrites like `same_rewrites`, describing the rewrites found on only one side.
+fn rewrites_match(
+    kind: &str,
+    this: &Option<Vec<DataRewrite>>,
+    other: &Option<Vec<DataRewrite>>,
+    sides: Sides,
+) -> Result<(), MatchFailure> {
+    if same_rewrites(this, other) {
+        return Ok(());
+    }
+    let (this, other) = (
+        this.as_deref().unwrap_or_default(),
+        other.as_deref().unwrap_or_default(),
+    );
+    let mut message = format!("mismatch between {kind}:");
+    let only_this = this.iter().filter(|rewrite| !other.contains(rewrite));
+    let only_other = other.iter().filter(|rewrite| !this.contains(rewrite));
+    let mut described = false;
+    for rewrite in only_this {
+        write!(
+            message,
+            "\n{} fetch only: {}",
+            sides.this,
+            describe_rewrite(rewrite)
+        )
+        .unwrap();
+        described = true;
+    }
+    for rewrite in only_other {
+        write!(
+            message,
+            "\n{} fetch only: {}",
+            sides.other,
+            describe_rewrite(rewrite)
+        )
+        .unwrap();
+        described = true;
+    }
+    if !described {
+        message.push_str(
+            "\nthe same rewrites, repeated a different number of times (or missing vs. empty)",
+        );
+    }
+    Err(MatchFailure::new(message))
+}
+
+pub(crate) fn describe_rewrite(rewrite: &DataRewrite) -> String {
+    match rewrite {
+        DataRewrite::ValueSetter(setter) => format!(
+            "set the value at `{}` to {}",
+            setter.path,
+            serde_json::to_string(&setter.set_value_to).expect("values serialize to JSON")
+        ),
+        DataRewrite::KeyRenamer(renamer) => format!(
+            "rename the key at `{}` to `{}`",
+            renamer.path, renamer.rename_key_to
+        ),
+    }
+}
+
 fn operation_matches(
     this: &SerializableDocument,
     other: &SerializableDocument,
+    sides: Sides,
 ) -> Result<(), MatchFailure> {
-    document_str_matches(this.as_serialized(), other.as_serialized())
+    let (this, other) = (this.as_serialized(), other.as_serialized());
+    document_str_matches(this, other).map_err(|err| {
+        match field_selection_differences(this, other, sides) {
+            Some(differences) => err.add_description(&differences),
+            None => err,
+        }
+    })
 }
 
-// Compare operation document strings such as query or just selection set.
+// Compare operation document strings such as query or just selection set. Documents failing to
+// parse still match when their strings are equal.
 fn document_str_matches(this: &str, other: &str) -> Result<(), MatchFailure> {
+    if this == other {
+        return Ok(());
+    }
     let this_ast = match ast::Document::parse(this, "this_operation.graphql") {
         Ok(document) => document,
         Err(_) => {
@@ -703,56 +1520,478 @@ fn document_str_matches(this: &str, other: &str) -> Result<(), MatchFailure> {
     same_ast_document(&this_ast, &other_ast)
 }
 
-fn opt_document_string_matches(
+// Compares defer subselections normalized (see `semantic`), since the planners format and merge
+// them differently. A `None` subselection (the node is itself a `Defer`) only matches `None`.
+// Subselections failing to parse are compared as they are.
+fn opt_subselection_matches(
     this: &Option<String>,
     other: &Option<String>,
 ) -> Result<(), MatchFailure> {
     match (this, other) {
         (None, None) => Ok(()),
-        (Some(this_sel), Some(other_sel)) => document_str_matches(this_sel, other_sel),
+        (Some(this_sel), Some(other_sel)) => {
+            let normalize = |selection: &str| {
+                normalize_subselection_str(selection).unwrap_or_else(|| selection.to_string())
+            };
+            document_str_matches(&normalize(this_sel), &normalize(other_sel)).map_err(|err| {
+                err.add_description(&format!("left: {this_sel:?}\nright: {other_sel:?}"))
+            })
+        }
         _ => Err(MatchFailure::new(format!(
-            "mismatched at opt_document_string_matches\nleft: {:?}\nright: {:?}",
+            "mismatched at opt_subselection_matches\nleft: {:?}\nright: {:?}",
             this, other
         ))),
     }
 }
 
 //==================================================================================================
-// AST comparison functions
+// Field selection differences
+
+// Reports the fields selected by only one of the operations (e.g. "native fetch selects
+// `Product.price` that legacy doesn't"), or `None` if either fails to parse or they select the
+// same fields (differing only in arguments, directives or fragment structure).
+fn field_selection_differences(this: &str, other: &str, sides: Sides) -> Option<String> {
+    let this = ast::Document::parse(this, "this_operation.graphql").ok()?;
+    let other = ast::Document::parse(other, "other_operation.graphql").ok()?;
+    let this_fields = selected_fields(&this);
+    let other_fields = selected_fields(&other);
+    let Sides {
+        this: this_side,
+        other: other_side,
+    } = sides;
+    let mut report = Vec::new();
+    for field in this_fields.difference(&other_fields) {
+        report.push(format!(
+            "{this_side} fetch selects `{field}` that {other_side} doesn't"
+        ));
+    }
+    for field in other_fields.difference(&this_fields) {
+        report.push(format!(
+            "{other_side} fetch selects `{field}` that {this_side} doesn't"
+        ));
+    }
+    (!report.is_empty()).then(|| report.join("\n"))
+}
 
-fn same_ast_document(x: &ast::Document, y: &ast::Document) -> Result<(), MatchFailure> {
-    fn split_definitions(
-        doc: &ast::Document,
-    ) -> (
-        Vec<&ast::OperationDefinition>,
-        Vec<&ast::FragmentDefinition>,
-        Vec<&ast::Definition>,
-    ) {
-        let mut operations: Vec<&ast::OperationDefinition> = Vec::new();
-        let mut fragments: Vec<&ast::FragmentDefinition> = Vec::new();
-        let mut others: Vec<&ast::Definition> = Vec::new();
-        for def in doc.definitions.iter() {
-            match def {
-                ast::Definition::OperationDefinition(op) => operations.push(op),
-                ast::Definition::FragmentDefinition(frag) => fragments.push(frag),
-                _ => others.push(def),
+// Field selections of a document as `Type.path.to.field` strings (using response names), where
+// `Type` is the innermost type condition, or the root operation type. Fragment spreads are
+// expanded, so fragment names and structure don't matter.
+fn selected_fields(document: &ast::Document) -> BTreeSet<String> {
+    let fragments: HashMap<&Name, &Node<ast::FragmentDefinition>> = document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            ast::Definition::FragmentDefinition(fragment) => Some((&fragment.name, fragment)),
+            _ => None,
+        })
+        .collect();
+    let mut fields = BTreeSet::new();
+    for definition in &document.definitions {
+        if let ast::Definition::OperationDefinition(operation) = definition {
+            let root_type = match operation.operation_type {
+                ast::OperationType::Query => "Query",
+                ast::OperationType::Mutation => "Mutation",
+                ast::OperationType::Subscription => "Subscription",
+            };
+            collect_selected_fields(
+                &operation.selection_set,
+                root_type,
+                &fragments,
+                &mut Vec::new(),
+                &mut fields,
+            );
+        }
+    }
+    fields
+}
+
+fn collect_selected_fields<'a>(
+    selections: &'a [ast::Selection],
+    prefix: &str,
+    fragments: &HashMap<&Name, &'a Node<ast::FragmentDefinition>>,
+    spreads: &mut Vec<&'a Name>, // fragments being expanded, to break cycles
+    fields: &mut BTreeSet<String>,
+) {
+    for selection in selections {
+        match selection {
+            ast::Selection::Field(field) => {
+                let path = format!("{prefix}.{}", field.response_name());
+                collect_selected_fields(&field.selection_set, &path, fragments, spreads, fields);
+                fields.insert(path);
+            }
+            ast::Selection::InlineFragment(fragment) => {
+                let prefix = fragment
+                    .type_condition
+                    .as_ref()
+                    .map_or(prefix, |type_condition| type_condition.as_str());
+                collect_selected_fields(
+                    &fragment.selection_set,
+                    prefix,
+                    fragments,
+                    spreads,
+                    fields,
+                );
+            }
+            ast::Selection::FragmentSpread(spread) => {
+                let Some(&fragment) = fragments.get(&spread.fragment_name) else {
+                    continue;
+                };
+                if spreads.contains(&&spread.fragment_name) {
+                    continue;
+                }
+                spreads.push(&spread.fragment_name);
+                collect_selected_fields(
+                    &fragment.selection_set,
+                    fragment.type_condition.as_str(),
+                    fragments,
+                    spreads,
+                    fields,
+                );
+                spreads.pop();
             }
         }
-        (operations, fragments, others)
     }
+}
 
-    let (x_ops, x_frags, x_others) = split_definitions(x);
-    let (y_ops, y_frags, y_others) = split_definitions(y);
+//==================================================================================================
+// Fetch set comparison
+
+/// Fetches that have no equivalent in the other plan, regardless of where they sit in the tree.
+///
+/// Fetches are paired up as a multiset: two fetches are equivalent if they target the same service
+/// with semantically matching operations.
+#[derive(Debug, Default)]
+pub struct FetchDifferences {
+    pub only_legacy: Vec<FetchNode>,
+    pub only_native: Vec<FetchNode>,
+    /// How the report names the plans, `only_legacy` being in `sides.this`.
+    pub sides: Sides,
+}
 
-    debug_assert!(x_others.is_empty(), "Unexpected definition types");
-    debug_assert!(y_others.is_empty(), "Unexpected definition types");
-    debug_assert!(
-        x_ops.len() == y_ops.len(),
-        "Different number of operation definitions"
-    );
+impl FetchDifferences {
+    pub fn is_empty(&self) -> bool {
+        self.only_legacy.is_empty() && self.only_native.is_empty()
+    }
+}
 
-    check_match_eq!(x_frags.len(), y_frags.len());
-    let mut fragment_map: HashMap<Name, Name> = HashMap::new();
+impl std::fmt::Display for FetchDifferences {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn write_fetches(
+            f: &mut std::fmt::Formatter<'_>,
+            heading: &str,
+            fetches: &[FetchNode],
+        ) -> std::fmt::Result {
+            writeln!(f, "{heading}")?;
+            if fetches.is_empty() {
+                writeln!(f, "  (none)")?;
+            }
+            for fetch in fetches {
+                writeln!(
+                    f,
+                    "  - {}: {}",
+                    fetch.service_name,
+                    fetch.operation.as_serialized()
+                )?;
+            }
+            Ok(())
+        }
+
+        let Sides { this, other } = self.sides;
+        write_fetches(
+            f,
+            &format!("Fetches only in the {this} plan:"),
+            &self.only_legacy,
+        )?;
+        write_fetches(
+            f,
+            &format!("Fetches only in the {other} plan:"),
+            &self.only_native,
+        )
+    }
+}
+
+pub fn fetch_differences(
+    js_plan: &QueryPlanResult,
+    rust_plan: &NativeQueryPlan,
+) -> FetchDifferences {
+    let rust_root_node = convert_root_query_plan_node(rust_plan);
+    node_fetch_differences(
+        js_plan.root_node(),
+        rust_root_node.as_ref(),
+        Sides::PLANNERS,
+    )
+}
+
+pub(crate) fn node_fetch_differences(
+    this: Option<&PlanNode>,
+    other: Option<&PlanNode>,
+    sides: Sides,
+) -> FetchDifferences {
+    let mut this_fetches = Vec::new();
+    let mut other_fetches = Vec::new();
+    if let Some(this) = this {
+        collect_fetches(this, &mut this_fetches);
+    }
+    if let Some(other) = other {
+        collect_fetches(other, &mut other_fetches);
+    }
+
+    let mut only_legacy = Vec::new();
+    for this_fetch in this_fetches {
+        if let Some(pos) = other_fetches
+            .iter()
+            .position(|other_fetch| same_fetch_operation(this_fetch, other_fetch))
+        {
+            other_fetches.swap_remove(pos);
+        } else {
+            only_legacy.push(this_fetch.clone());
+        }
+    }
+    FetchDifferences {
+        only_legacy,
+        only_native: other_fetches.into_iter().cloned().collect(),
+        sides,
+    }
+}
+
+// Iterative, so that deep plans don't overflow the stack. Children are pushed in reverse, to be
+// popped in plan order.
+pub(crate) fn collect_fetches<'a>(node: &'a PlanNode, fetches: &mut Vec<&'a FetchNode>) {
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        if let PlanNode::Fetch(fetch) = node {
+            fetches.push(fetch);
+        }
+        stack.extend(node.children().into_iter().rev());
+    }
+}
+
+fn same_fetch_operation(this: &FetchNode, other: &FetchNode) -> bool {
+    this.service_name == other.service_name
+        && operation_matches(&this.operation, &other.operation, Sides::PLANNERS).is_ok()
+}
+
+//==================================================================================================
+// Mismatch classification
+
+/// The root cause of a mismatch between two plans, from the most to the least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MismatchCategory {
+    /// The plans have different numbers of fetches.
+    MissingFetch,
+    /// The plans have as many fetches, but not to the same services.
+    ServiceName,
+    /// Fetches to the same service differ in operation kind.
+    OperationKind,
+    /// Fetches to the same service differ in their subgraph operations.
+    FetchSelection,
+    /// The plans have the same fetches at the same paths, but arrange them differently (e.g. in
+    /// sequence rather than in parallel).
+    OrderingOnly,
+    /// Any other difference, e.g. in `requires` selections or in `Defer` nodes.
+    Other,
+}
+
+impl MismatchCategory {
+    /// A snake-case identifier, for machine-readable output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::MissingFetch => "missing_fetch",
+            Self::ServiceName => "service_name",
+            Self::OperationKind => "operation_kind",
+            Self::FetchSelection => "fetch_selection",
+            Self::OrderingOnly => "ordering_only",
+            Self::Other => "other",
+        }
+    }
+}
+
+impl std::fmt::Display for MismatchCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::MissingFetch => "missing fetches",
+            Self::ServiceName => "fetch services",
+            Self::OperationKind => "fetch operation kinds",
+            Self::FetchSelection => "fetch selections",
+            Self::OrderingOnly => "ordering only",
+            Self::Other => "other differences",
+        })
+    }
+}
+
+/// Classifies the mismatch between two plans, once the differences ignored by `options` are
+/// erased. Meant for plans known not to match.
+pub fn classify_mismatch(
+    js_plan: &QueryPlanResult,
+    rust_plan: &NativeQueryPlan,
+    options: &CompareOptions,
+) -> MismatchCategory {
+    let rust_root_node = convert_root_query_plan_node(rust_plan);
+    let js_root_node = js_plan
+        .root_node()
+        .map(|node| apply_compare_options(node, options));
+    let rust_root_node = rust_root_node
+        .as_ref()
+        .map(|node| apply_compare_options(node, options));
+    node_mismatch_category(js_root_node.as_deref(), rust_root_node.as_deref())
+}
+
+/// Returns true if the plans, once the differences ignored by `options` are erased, only differ in
+/// orders that the comparison ignores (see `normalize::is_trivially_divergent`). Meant for plans
+/// known to match, to measure how many of them differ cosmetically.
+pub fn trivially_divergent(
+    js_plan: &QueryPlanResult,
+    rust_plan: &NativeQueryPlan,
+    options: &CompareOptions,
+) -> bool {
+    let rust_root_node = convert_root_query_plan_node(rust_plan);
+    let js_root_node = js_plan
+        .root_node()
+        .map(|node| apply_compare_options(node, options));
+    let rust_root_node = rust_root_node
+        .as_ref()
+        .map(|node| apply_compare_options(node, options));
+    match (js_root_node, rust_root_node) {
+        (Some(this), Some(other)) => is_trivially_divergent(&this, &other),
+        _ => false,
+    }
+}
+
+pub(crate) fn node_mismatch_category(
+    this: Option<&PlanNode>,
+    other: Option<&PlanNode>,
+) -> MismatchCategory {
+    let mut this_leaves = Vec::new();
+    let mut other_leaves = Vec::new();
+    if let Some(this) = this {
+        collect_fetch_leaves(this, None, &mut this_leaves);
+    }
+    if let Some(other) = other {
+        collect_fetch_leaves(other, None, &mut other_leaves);
+    }
+    if this_leaves.len() != other_leaves.len() {
+        return MismatchCategory::MissingFetch;
+    }
+    if fetch_counts(&this_leaves, false) != fetch_counts(&other_leaves, false) {
+        return MismatchCategory::ServiceName;
+    }
+    if fetch_counts(&this_leaves, true) != fetch_counts(&other_leaves, true) {
+        return MismatchCategory::OperationKind;
+    }
+    if !node_fetch_differences(this, other, Sides::PLANNERS).is_empty() {
+        return MismatchCategory::FetchSelection;
+    }
+    if vec_matches_as_set(&this_leaves, &other_leaves, same_fetch_leaf) {
+        MismatchCategory::OrderingOnly
+    } else {
+        MismatchCategory::Other
+    }
+}
+
+// Counts the fetches per service (and per operation kind if `by_kind` is true).
+fn fetch_counts<'a>(
+    leaves: &[FetchLeaf<'a>],
+    by_kind: bool,
+) -> HashMap<(&'a str, Option<OperationKind>), usize> {
+    let mut counts = HashMap::new();
+    for (_, fetch) in leaves {
+        let kind = by_kind.then_some(fetch.operation_kind);
+        *counts.entry((&*fetch.service_name, kind)).or_default() += 1;
+    }
+    counts
+}
+
+// A fetch, with the path of its innermost enclosing `Flatten` node.
+type FetchLeaf<'a> = (Option<&'a Path>, &'a FetchNode);
+
+fn same_fetch_leaf(
+    (this_path, this_fetch): &FetchLeaf<'_>,
+    (other_path, other_fetch): &FetchLeaf<'_>,
+) -> bool {
+    let same_paths = match (this_path, other_path) {
+        (None, None) => true,
+        (Some(this_path), Some(other_path)) => same_path(this_path, other_path),
+        _ => false,
+    };
+    same_paths && fetch_node_matches(this_fetch, other_fetch, Sides::PLANNERS).is_ok()
+}
+
+fn collect_fetch_leaves<'a>(
+    node: &'a PlanNode,
+    path: Option<&'a Path>,
+    leaves: &mut Vec<FetchLeaf<'a>>,
+) {
+    match node {
+        PlanNode::Sequence { nodes } | PlanNode::Parallel { nodes } => {
+            for node in nodes {
+                collect_fetch_leaves(node, path, leaves);
+            }
+        }
+        PlanNode::Fetch(fetch) => leaves.push((path, fetch)),
+        PlanNode::Flatten(flatten) => {
+            collect_fetch_leaves(&flatten.node, Some(&flatten.path), leaves);
+        }
+        PlanNode::Defer { primary, deferred } => {
+            let deferred = deferred
+                .iter()
+                .filter_map(|deferred| deferred.node.as_deref());
+            for node in primary.node.as_deref().into_iter().chain(deferred) {
+                collect_fetch_leaves(node, path, leaves);
+            }
+        }
+        PlanNode::Subscription { primary: _, rest } => {
+            if let Some(node) = rest {
+                collect_fetch_leaves(node, path, leaves);
+            }
+        }
+        PlanNode::Condition {
+            condition: _,
+            if_clause,
+            else_clause,
+        } => {
+            for node in [if_clause, else_clause].into_iter().flatten() {
+                collect_fetch_leaves(node, path, leaves);
+            }
+        }
+    }
+}
+
+//==================================================================================================
+// AST comparison functions
+
+fn same_ast_document(x: &ast::Document, y: &ast::Document) -> Result<(), MatchFailure> {
+    fn split_definitions(
+        doc: &ast::Document,
+    ) -> (
+        Vec<&ast::OperationDefinition>,
+        Vec<&ast::FragmentDefinition>,
+        Vec<&ast::Definition>,
+    ) {
+        let mut operations: Vec<&ast::OperationDefinition> = Vec::new();
+        let mut fragments: Vec<&ast::FragmentDefinition> = Vec::new();
+        let mut others: Vec<&ast::Definition> = Vec::new();
+        for def in doc.definitions.iter() {
+            match def {
+                ast::Definition::OperationDefinition(op) => operations.push(op),
+                ast::Definition::FragmentDefinition(frag) => fragments.push(frag),
+                _ => others.push(def),
+            }
+        }
+        (operations, fragments, others)
+    }
+
+    let (x_ops, x_frags, x_others) = split_definitions(x);
+    let (y_ops, y_frags, y_others) = split_definitions(y);
+
+    debug_assert!(x_others.is_empty(), "Unexpected definition types");
+    debug_assert!(y_others.is_empty(), "Unexpected definition types");
+    debug_assert!(
+        x_ops.len() == y_ops.len(),
+        "Different number of operation definitions"
+    );
+
+    check_match_eq!(x_frags.len(), y_frags.len());
+    let mut fragment_map: HashMap<Name, Name> = HashMap::new();
     // Assumption: x_frags and y_frags are topologically sorted.
     //             Thus, we can build the fragment name mapping in a single pass and compare
     //             fragment definitions using the mapping at the same time, since earlier fragments
@@ -1306,3 +2545,716 @@ mod path_comparison_tests {
         assert_path_differ!(json!(["k|[]", "v"]), json!(["k", "v"]));
     }
 }
+
+#[cfg(test)]
+mod fetch_differences_tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn fetch(service_name: &str, operation: &str) -> serde_json::Value {
+        json!({
+            "kind": "Fetch",
+            "serviceName": service_name,
+            "variableUsages": [],
+            "operation": operation,
+            "operationKind": "query",
+        })
+    }
+
+    #[test]
+    fn test_fetch_differences_ignore_tree_shape() {
+        let this: PlanNode = serde_json::from_value(json!({
+            "kind": "Sequence",
+            "nodes": [fetch("products", "{ a b }"), fetch("reviews", "{ c }")],
+        }))
+        .unwrap();
+        let other: PlanNode = serde_json::from_value(json!({
+            "kind": "Parallel",
+            "nodes": [fetch("reviews", "{ c }"), fetch("products", "{ b a }")],
+        }))
+        .unwrap();
+        assert!(node_fetch_differences(Some(&this), Some(&other), Sides::PLANNERS).is_empty());
+    }
+
+    #[test]
+    fn test_fetch_differences_report_both_directions() {
+        let this: PlanNode = serde_json::from_value(json!({
+            "kind": "Sequence",
+            "nodes": [fetch("products", "{ a }"), fetch("products", "{ a }")],
+        }))
+        .unwrap();
+        let other: PlanNode = serde_json::from_value(json!({
+            "kind": "Sequence",
+            "nodes": [fetch("products", "{ a }"), fetch("inventory", "{ a }")],
+        }))
+        .unwrap();
+        let differences = node_fetch_differences(Some(&this), Some(&other), Sides::LEGACY_AB);
+        assert_eq!(differences.only_legacy.len(), 1);
+        assert_eq!(&*differences.only_legacy[0].service_name, "products");
+        assert_eq!(differences.only_native.len(), 1);
+        assert_eq!(&*differences.only_native[0].service_name, "inventory");
+        let report = differences.to_string();
+        assert!(report.contains("Fetches only in the A plan:"));
+        assert!(report.contains("Fetches only in the B plan:"));
+    }
+}
+
+#[cfg(test)]
+mod plan_matches_property_tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::router::arbitrary;
+
+    proptest! {
+        #[test]
+        fn test_plan_matches_is_reflexive(node in arbitrary::plan_node()) {
+            prop_assert!(plan_node_matches(&node, &node, Sides::PLANNERS).is_ok());
+        }
+
+        #[test]
+        fn test_plan_matches_is_symmetric(
+            this in arbitrary::plan_node(),
+            other in arbitrary::plan_node(),
+        ) {
+            prop_assert_eq!(
+                plan_node_matches(&this, &other, Sides::PLANNERS).is_ok(),
+                plan_node_matches(&other, &this, Sides::PLANNERS).is_ok()
+            );
+        }
+
+        #[test]
+        fn test_plan_matches_is_symmetric_on_reordered_plans(node in arbitrary::plan_node()) {
+            let other = arbitrary::reordered(&node);
+            prop_assert!(plan_node_matches(&node, &other, Sides::PLANNERS).is_ok());
+            prop_assert!(plan_node_matches(&other, &node, Sides::PLANNERS).is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod variable_usages_tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<Arc<str>> {
+        names.iter().map(|name| Arc::from(*name)).collect()
+    }
+
+    #[test]
+    fn test_variable_usages_ignore_order_and_duplicates() {
+        let this = names(&["first", "id", "id"]);
+        let other = names(&["id", "first"]);
+        assert!(variable_usages_match(&this, &other, Sides::PLANNERS).is_ok());
+    }
+
+    #[test]
+    fn test_variable_usages_report_set_difference() {
+        let this = names(&["first", "id"]);
+        let other = names(&["id", "after"]);
+        let description = variable_usages_match(&this, &other, Sides::PLANNERS)
+            .unwrap_err()
+            .description();
+        assert!(description.contains("legacy fetch uses `$first` not used by native"));
+        assert!(description.contains("native fetch uses `$after` not used by legacy"));
+        assert!(!description.contains("`$id`"));
+        let description = variable_usages_match(&this, &other, Sides::LEGACY_AB)
+            .unwrap_err()
+            .description();
+        assert!(description.contains("A fetch uses `$first` not used by B"));
+        assert!(description.contains("B fetch uses `$after` not used by A"));
+    }
+}
+
+#[cfg(test)]
+mod defer_tests {
+    use super::*;
+    use crate::router::testing::defer_plan;
+
+    fn deferred_mut(node: &mut PlanNode) -> &mut DeferredNode {
+        let PlanNode::Defer { deferred, .. } = node else {
+            panic!("expected a Defer node");
+        };
+        &mut deferred[0]
+    }
+
+    #[test]
+    fn test_defer_plan_matches_itself() {
+        let plan = defer_plan();
+        assert!(opt_plan_node_matches(&Some(&plan), &Some(&plan)).is_ok());
+    }
+
+    #[test]
+    fn test_defer_depends_are_compared() {
+        let mut other = defer_plan();
+        deferred_mut(&mut other).depends[0].id = "1".to_string();
+        let failure = opt_plan_node_matches(&Some(defer_plan()), &Some(other)).unwrap_err();
+        assert!(failure.description().contains("depends"));
+    }
+
+    #[test]
+    fn test_defer_subselections_are_compared() {
+        let mut other = defer_plan();
+        deferred_mut(&mut other).subselection = Some("{ reviews { id } }".to_string());
+        assert!(opt_plan_node_matches(&Some(defer_plan()), &Some(other)).is_err());
+
+        let mut other = defer_plan();
+        let PlanNode::Defer { primary, .. } = &mut other else {
+            unreachable!()
+        };
+        primary.subselection = Some("{ topProducts { name upc } }".to_string());
+        assert!(opt_plan_node_matches(&Some(defer_plan()), &Some(other)).is_ok());
+    }
+
+    // The same plan as `defer.json`, with subselections printed on several lines, repeating
+    // fields, and with an inline fragment without type condition.
+    #[test]
+    fn test_defer_subselections_are_compared_normalized() {
+        let reformatted: PlanNode = serde_json::from_str(include_str!(
+            "../../tests/fixtures/plans/defer_reformatted.json"
+        ))
+        .unwrap();
+        assert!(opt_plan_node_matches(&Some(defer_plan()), &Some(reformatted)).is_ok());
+    }
+
+    #[test]
+    fn test_defer_missing_subselection() {
+        let mut other = defer_plan();
+        deferred_mut(&mut other).subselection = None;
+        let failure = opt_plan_node_matches(&Some(defer_plan()), &Some(other)).unwrap_err();
+        assert!(
+            failure
+                .description()
+                .contains("under deferred subselection")
+        );
+    }
+}
+
+#[cfg(test)]
+mod field_selection_tests {
+    use super::*;
+
+    #[test]
+    fn test_field_selection_differences() {
+        let report = field_selection_differences(
+            "{ topProducts { upc name } }",
+            "{ topProducts { upc price } }",
+            Sides::PLANNERS,
+        )
+        .unwrap();
+        assert_eq!(
+            report,
+            "legacy fetch selects `Query.topProducts.name` that native doesn't\n\
+             native fetch selects `Query.topProducts.price` that legacy doesn't"
+        );
+    }
+
+    #[test]
+    fn test_field_selection_differences_name_the_schemas() {
+        let report = field_selection_differences(
+            "{ topProducts { upc name } }",
+            "{ topProducts { upc } }",
+            Sides::SCHEMA_DRIFT,
+        )
+        .unwrap();
+        assert_eq!(
+            report,
+            "schema fetch selects `Query.topProducts.name` that schema-b doesn't"
+        );
+    }
+
+    #[test]
+    fn test_field_selection_differences_use_type_conditions() {
+        let report = field_selection_differences(
+            "query($r: [_Any!]!) { _entities(representations: $r) { ... on Product { reviews { body } } } }",
+            "query($r: [_Any!]!) { _entities(representations: $r) { ...F } } fragment F on Product { reviews { body id } }",
+            Sides::PLANNERS,
+        )
+        .unwrap();
+        assert_eq!(
+            report,
+            "native fetch selects `Product.reviews.id` that legacy doesn't"
+        );
+    }
+
+    #[test]
+    fn test_no_field_selection_differences() {
+        // Argument differences are reported by the regular comparison only.
+        assert!(
+            field_selection_differences(
+                "{ topProducts(first: 1) { upc } }",
+                "{ topProducts(first: 2) { upc } }",
+                Sides::PLANNERS
+            )
+            .is_none()
+        );
+        assert!(
+            field_selection_differences(
+                "{ topProducts { upc } }",
+                "{ topProducts {",
+                Sides::PLANNERS
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_operation_mismatch_includes_field_selection_differences() {
+        let this: SerializableDocument =
+            serde_json::from_value(serde_json::json!("{ topProducts { upc } }")).unwrap();
+        let other: SerializableDocument =
+            serde_json::from_value(serde_json::json!("{ topProducts { upc price } }")).unwrap();
+        let failure = operation_matches(&this, &other, Sides::PLANNERS).unwrap_err();
+        assert!(
+            failure
+                .description()
+                .contains("native fetch selects `Query.topProducts.price` that legacy doesn't")
+        );
+    }
+}
+
+#[cfg(test)]
+mod legacy_plans_tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn legacy_plan(fixture: &str) -> QueryPlanResult {
+        let node: serde_json::Value = serde_json::from_str(fixture).unwrap();
+        serde_json::from_value(json!({ "queryPlan": { "node": node } })).unwrap()
+    }
+
+    #[test]
+    fn test_legacy_plans_match() {
+        let parallel = legacy_plan(include_str!("../../tests/fixtures/plans/parallel.json"));
+        let defer = legacy_plan(include_str!("../../tests/fixtures/plans/defer.json"));
+        let options = CompareOptions::default();
+        assert!(legacy_plans_match(&parallel, &parallel.clone(), &options).is_ok());
+        assert!(legacy_plans_match(&parallel, &defer, &options).is_err());
+        assert!(!diff_legacy_plans_with_style(&parallel, &defer, &Style::plain()).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod compare_nodes_tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::router::testing::nodes_mut;
+    use crate::router::testing::parallel_plan;
+    use crate::router::testing::parse_plan;
+
+    fn fetch() -> serde_json::Value {
+        json!({
+            "kind": "Fetch",
+            "serviceName": "products",
+            "variableUsages": [],
+            "operation": "{ topProducts { upc } }",
+            "operationKind": "query",
+        })
+    }
+
+    fn plan(value: serde_json::Value) -> PlanNode {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn omitted_branch_native() -> PlanNode {
+        parse_plan(include_str!(
+            "../../tests/fixtures/plans/condition_omitted_native.json"
+        ))
+    }
+
+    #[test]
+    fn test_compare_nodes() {
+        let plan = parallel_plan();
+        let PlanNode::Sequence { nodes } = &plan else {
+            panic!("expected a Sequence node");
+        };
+        let options = CompareOptions::default();
+        assert!(compare_nodes(&plan, &plan.clone(), &options).is_ok());
+        assert!(compare_nodes(&plan, &nodes[0], &options).is_err());
+    }
+
+    #[test]
+    fn test_nested_divergence_path() {
+        let mut other = parallel_plan();
+        let PlanNode::Condition {
+            if_clause: Some(if_clause),
+            ..
+        } = &mut nodes_mut(&mut nodes_mut(&mut other)[1])[1]
+        else {
+            panic!("expected a Condition node");
+        };
+        let PlanNode::Flatten(flatten) = &mut **if_clause else {
+            panic!("expected a Flatten node");
+        };
+        let PlanNode::Fetch(fetch) = &mut *flatten.node else {
+            panic!("expected a Fetch node");
+        };
+        fetch.service_name = "warehouse".into();
+        let failure = compare_nodes(&parallel_plan(), &other, &Default::default()).unwrap_err();
+        assert_eq!(
+            failure.divergence_path().as_deref(),
+            Some(
+                "Sequence[1] > Parallel[1] > Condition(withPrice)[if] > Flatten(/topProducts/@) \
+                 > Fetch(inventory)"
+            )
+        );
+        assert!(matches!(
+            failure.divergent_node(),
+            Some(PlanNode::Fetch(fetch)) if &*fetch.service_name == "inventory"
+        ));
+        assert_eq!(failure.divergence_response_path(), ["topProducts"]);
+    }
+
+    #[test]
+    fn test_no_divergence_path_without_root_node() {
+        let failure = compare_root_nodes(
+            Some(&parallel_plan()),
+            None,
+            &Default::default(),
+            Sides::PLANNERS,
+        )
+        .unwrap_err();
+        assert_eq!(failure.divergence_path(), None);
+        assert!(failure.divergent_node().is_none());
+    }
+
+    #[test]
+    fn test_single_child_parallel_matches_its_child() {
+        let wrapped = plan(json!({ "kind": "Parallel", "nodes": [fetch()] }));
+        assert!(opt_plan_node_matches(&Some(wrapped), &Some(plan(fetch()))).is_ok());
+        let nested = plan(json!({
+            "kind": "Sequence",
+            "nodes": [{ "kind": "Parallel", "nodes": [fetch()] }],
+        }));
+        assert!(opt_plan_node_matches(&Some(plan(fetch())), &Some(nested)).is_ok());
+    }
+
+    #[test]
+    fn test_empty_parallel_is_reported() {
+        let empty = plan(json!({ "kind": "Parallel", "nodes": [] }));
+        let failure = opt_plan_node_matches(&Some(&empty), &Some(plan(fetch()))).unwrap_err();
+        assert!(
+            failure.description().starts_with("empty Parallel node"),
+            "{}",
+            failure.description()
+        );
+        assert!(opt_plan_node_matches(&Some(&empty), &Some(empty.clone())).is_ok());
+    }
+
+    #[test]
+    fn test_empty_branch_matches_omitted_branch() {
+        let legacy = parse_plan(include_str!(
+            "../../tests/fixtures/plans/condition_omitted_legacy.json"
+        ));
+        let native = omitted_branch_native();
+        assert!(opt_plan_node_matches(&Some(&legacy), &Some(&native)).is_ok());
+    }
+
+    #[test]
+    fn test_non_empty_branch_does_not_match_omitted_branch() {
+        let native = omitted_branch_native();
+        let PlanNode::Condition {
+            condition,
+            if_clause,
+            else_clause: _,
+        } = &native
+        else {
+            panic!("expected a Condition node");
+        };
+        let with_else = PlanNode::Condition {
+            condition: condition.clone(),
+            if_clause: if_clause.clone(),
+            else_clause: if_clause.clone(),
+        };
+        let failure = opt_plan_node_matches(&Some(&with_else), &Some(&native)).unwrap_err();
+        assert_eq!(
+            failure.divergence_path().as_deref(),
+            Some("Condition(withStock)[else]")
+        );
+    }
+}
+
+#[cfg(test)]
+mod mismatch_category_tests {
+    use super::*;
+    use crate::router::testing::nodes_mut;
+    use crate::router::testing::parallel_plan;
+
+    fn first_fetch_mut(node: &mut PlanNode) -> &mut FetchNode {
+        let PlanNode::Fetch(fetch) = &mut nodes_mut(node)[0] else {
+            panic!("expected a Fetch node");
+        };
+        fetch
+    }
+
+    fn category(this: &PlanNode, other: &PlanNode) -> MismatchCategory {
+        node_mismatch_category(Some(this), Some(other))
+    }
+
+    #[test]
+    fn test_missing_fetch() {
+        let mut other = parallel_plan();
+        nodes_mut(&mut nodes_mut(&mut other)[1]).pop();
+        assert_eq!(
+            category(&parallel_plan(), &other),
+            MismatchCategory::MissingFetch
+        );
+    }
+
+    #[test]
+    fn test_service_name() {
+        let mut other = parallel_plan();
+        first_fetch_mut(&mut other).service_name = "catalog".into();
+        assert_eq!(
+            category(&parallel_plan(), &other),
+            MismatchCategory::ServiceName
+        );
+    }
+
+    #[test]
+    fn test_fetch_selection() {
+        let mut other = parallel_plan();
+        first_fetch_mut(&mut other).operation = serde_json::from_value(serde_json::json!(
+            "query TopProducts__products__0($first: Int) { topProducts(first: $first) { __typename upc } }"
+        ))
+        .unwrap();
+        assert_eq!(
+            category(&parallel_plan(), &other),
+            MismatchCategory::FetchSelection
+        );
+    }
+
+    #[test]
+    fn test_ordering_only() {
+        // Running the entity fetches in sequence rather than in parallel.
+        let mut other = parallel_plan();
+        let parallel = nodes_mut(&mut other).pop().unwrap();
+        let PlanNode::Parallel { nodes } = parallel else {
+            panic!("expected a Parallel node");
+        };
+        nodes_mut(&mut other).extend(nodes);
+        assert_eq!(
+            category(&parallel_plan(), &other),
+            MismatchCategory::OrderingOnly
+        );
+    }
+
+    #[test]
+    fn test_other() {
+        let mut other = parallel_plan();
+        first_fetch_mut(&mut other).id = Some("0".to_string());
+        assert_eq!(category(&parallel_plan(), &other), MismatchCategory::Other);
+    }
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+    use crate::router::testing::parallel_plan;
+
+    fn elided_diff(this: &PlanNode, other: &PlanNode) -> String {
+        let style = Style {
+            elide_matching: true,
+            ..Style::plain()
+        };
+        diff_root_nodes(Some(this), Some(other), &style)
+    }
+
+    #[test]
+    fn test_unified_diff_hunks() {
+        let this = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n";
+        let other = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\n";
+        let expected = "@@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n\
+                        @@ -10,3 +10,4 @@\n j\n k\n l\n+m\n";
+        assert_eq!(
+            unified_diff_with_style(this, other, &Style::plain()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_unified_diff_of_equal_texts_is_empty() {
+        assert_eq!(
+            unified_diff_with_style("a\nb\n", "a\nb\n", &Style::plain()),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_unified_diff_of_added_lines() {
+        assert_eq!(
+            unified_diff_with_style("", "a\n", &Style::plain()),
+            "@@ -0,0 +1,1 @@\n+a\n"
+        );
+    }
+
+    #[test]
+    fn test_matching_subtrees_are_elided() {
+        let this = parallel_plan();
+        let mut other = parallel_plan();
+        let PlanNode::Sequence { nodes } = &mut other else {
+            panic!("expected a Sequence node");
+        };
+        let PlanNode::Fetch(fetch) = &mut nodes[0] else {
+            panic!("expected a Fetch node");
+        };
+        fetch.service_name = "warehouse".into();
+
+        let elided = elided_diff(&this, &other);
+        assert!(elided.contains("-                service_name: \"products\",\n"));
+        assert!(elided.contains("+                service_name: \"warehouse\",\n"));
+        assert!(elided.contains("         … "), "{elided}");
+        assert!(!elided.contains("Condition {"), "{elided}");
+        let full = diff_root_nodes(Some(&this), Some(&other), &Style::plain());
+        assert!(full.contains("Condition {"));
+        assert!(elided.lines().count() < full.lines().count());
+    }
+
+    #[test]
+    fn test_matching_plans_are_elided_to_one_line() {
+        let plan = parallel_plan();
+        let nodes = matching_subtrees(&diff::lines(&format!("{plan:#?}"), &format!("{plan:#?}")));
+        assert_eq!(nodes.len(), 1);
+        let elided = elided_diff(&plan, &plan);
+        assert_eq!(elided, format!(" … {} matching nodes …\n", nodes[0].1));
+    }
+}
+
+#[cfg(test)]
+mod rewrite_report_tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn rewrites(rewrites: serde_json::Value) -> Option<Vec<DataRewrite>> {
+        serde_json::from_value(rewrites).unwrap()
+    }
+
+    #[test]
+    fn test_rewrite_mismatch_names_the_differing_rewrites() {
+        let this = rewrites(json!([
+            { "kind": "ValueSetter", "path": ["... on Book", "__typename"], "setValueTo": "Product" },
+            { "kind": "KeyRenamer", "path": ["... on Product", "reviews__alias_0"], "renameKeyTo": "reviews" },
+        ]));
+        let other = rewrites(json!([
+            { "kind": "KeyRenamer", "path": ["... on Product", "reviews__alias_0"], "renameKeyTo": "reviews" },
+            { "kind": "ValueSetter", "path": ["... on Movie", "__typename"], "setValueTo": "Product" },
+        ]));
+        assert!(rewrites_match("input rewrites", &this, &this, Sides::PLANNERS).is_ok());
+        let description = rewrites_match("input rewrites", &this, &other, Sides::PLANNERS)
+            .unwrap_err()
+            .description();
+        assert_eq!(
+            description,
+            "mismatch between input rewrites:\n\
+             legacy fetch only: set the value at `/... on Book/__typename` to \"Product\"\n\
+             native fetch only: set the value at `/... on Movie/__typename` to \"Product\""
+        );
+        let description = rewrites_match("input rewrites", &this, &other, Sides::LEGACY_AB)
+            .unwrap_err()
+            .description();
+        assert!(description.contains("\nA fetch only: "), "{description}");
+        assert!(description.contains("\nB fetch only: "), "{description}");
+    }
+
+    #[test]
+    fn test_rewrite_mismatch_of_repetitions() {
+        let this = rewrites(json!([
+            { "kind": "KeyRenamer", "path": ["a"], "renameKeyTo": "b" },
+        ]));
+        let description = rewrites_match("output rewrites", &this, &None, Sides::PLANNERS)
+            .unwrap_err()
+            .description();
+        assert!(description.contains("legacy fetch only: rename the key at `/a` to `b`"));
+        let description =
+            rewrites_match("output rewrites", &Some(Vec::new()), &None, Sides::PLANNERS)
+                .unwrap_err()
+                .description();
+        assert!(description.contains("missing vs. empty"), "{description}");
+    }
+}
+
+#[cfg(test)]
+mod deep_plan_tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::outcome::ComparisonOutcome;
+    use crate::run_native_planner;
+
+    // Deep enough to overflow the stack of a test thread when compared recursively on it, and
+    // shallow enough to drop on it.
+    const DEPTH: usize = 2_000;
+
+    fn fetch(service_name: &str) -> PlanNode {
+        serde_json::from_value(json!({
+            "kind": "Fetch",
+            "serviceName": service_name,
+            "variableUsages": [],
+            "operation": "{ a }",
+            "operationKind": "query",
+        }))
+        .unwrap()
+    }
+
+    // `DEPTH` nested `Sequence` nodes, each starting with a fetch (so that none is unwrapped as a
+    // single child), around a fetch to `service_name`. Built without recursion.
+    fn deep_plan(service_name: &str) -> PlanNode {
+        let mut node = fetch(service_name);
+        for _ in 0..DEPTH {
+            node = PlanNode::Sequence {
+                nodes: vec![fetch("products"), node],
+            };
+        }
+        node
+    }
+
+    fn legacy_plan(node: PlanNode) -> QueryPlanResult {
+        QueryPlanResult {
+            formatted_query_plan: None,
+            query_plan: crate::router::QueryPlan {
+                node: Some(Arc::new(node)),
+            },
+        }
+    }
+
+    #[test]
+    fn test_deep_plans() {
+        let this = deep_plan("reviews");
+        let same = deep_plan("reviews");
+        let other = deep_plan("inventory");
+        assert_eq!(this.depth(), DEPTH + 1);
+        let options = CompareOptions::default();
+        assert!(compare_root_nodes(Some(&this), Some(&same), &options, Sides::PLANNERS).is_ok());
+        let failure =
+            compare_root_nodes(Some(&this), Some(&other), &options, Sides::PLANNERS).unwrap_err();
+        assert!(matches!(failure.divergent_node(), Some(PlanNode::Fetch(_))));
+        let mut fetches = Vec::new();
+        collect_fetches(&this, &mut fetches);
+        assert_eq!(fetches.len(), DEPTH + 1);
+        assert_eq!(&*fetches[DEPTH].service_name, "reviews");
+    }
+
+    #[test]
+    fn test_deep_legacy_plan_outcome() {
+        // Fingerprinting the deep legacy plan is the first step of the comparison.
+        let js_plan = legacy_plan(deep_plan("reviews"));
+        let rust_plan = run_native_planner(
+            include_str!("../../tests/fixtures/supergraph.graphql"),
+            include_str!("../../tests/fixtures/operations/entity_join.graphql"),
+            None,
+            "operation.graphql",
+            Default::default(),
+            Default::default(),
+        )
+        .unwrap();
+        let outcome = ComparisonOutcome::from_plans(&js_plan, &rust_plan, &Default::default());
+        assert!(
+            matches!(outcome, ComparisonOutcome::Mismatch(_)),
+            "{outcome:?}"
+        );
+        assert!(!same_plan_fingerprint(&js_plan, &rust_plan));
+    }
+}
@@ -0,0 +1,424 @@
+// Schema-less normalization of subgraph operations, applied with
+// `CompareOptions::semantic_operations`, and of defer subselections, which are always compared
+// normalized.
+//
+// Subgraph schemas are not available during comparison, so only rewrites that hold without type
+// information are applied:
+// - Named fragments are expanded into inline fragments, and their definitions removed, since the
+//   planners differ in whether they generate fragments. Inline fragments on their enclosing type
+//   (as far as it is known: the root operation type, or an enclosing type condition) without
+//   directives are flattened into it. Since the native planner only generates fragments in place
+//   of inline fragments, its expanded operations line up with inlined ones.
+// - Fields with the same alias, name, arguments and directives are merged (as in GraphQL field
+//   merging), and so are inline fragments with the same type condition and directives. Repeated
+//   fragment spreads are removed.
+// - Selection sets are sorted: fields by response name, then inline fragments by type condition,
+//   then fragment spreads by name.
+//
+// This is a heuristic, and a lossy one: the normalized operation is not guaranteed to be
+// equivalent for every schema (e.g. merged fields are assumed to have compatible arguments), and
+// equivalences that need types (e.g. an inline fragment on the type of its enclosing field) are
+// missed, unless the subgraph schema is given (see `normalize_operation_with_schema`). Expanding
+// fragments also costs time and memory proportional to the expanded operation,
+// which can be much larger than the original for heavily reused fragments.
+
+use std::collections::HashMap;
+
+use apollo_compiler::ExecutableDocument;
+use apollo_compiler::Name;
+use apollo_compiler::Node;
+use apollo_compiler::Schema;
+use apollo_compiler::ast;
+use apollo_compiler::validation::Valid;
+use apollo_federation::query_plan::serializable_document::SerializableDocument;
+
+/// Returns the operation re-serialized in a canonical format, or `None` if it fails to parse.
+///
+/// Serialized operations are formatted however they were built (e.g. the legacy planner's strings
+/// and the native planner's unindented printing differ), so the same operation can have different
+/// serializations. Unlike `normalize_operation_str`, this only reformats: the canonical operation
+/// has the same syntax tree.
+pub fn canonical_operation_str(source: &str) -> Option<String> {
+    let document = ast::Document::parse(source, "operation.graphql").ok()?;
+    Some(document.serialize().no_indent().to_string())
+}
+
+/// Returns the normalized operation, or `None` if it fails to parse.
+pub fn normalize_operation_str(source: &str) -> Option<String> {
+    normalize_document(source, true, None)
+}
+
+/// Returns the operation normalized with the types of its subgraph schema, or `None` if it isn't
+/// valid against the schema.
+///
+/// With types, the type of every selection set is known, so inline fragments on the type of their
+/// enclosing field are flattened as well, and root types are named as in the schema.
+pub(crate) fn normalize_operation_with_schema(
+    source: &str,
+    schema: &Valid<Schema>,
+) -> Option<String> {
+    ExecutableDocument::parse_and_validate(schema, source, "operation.graphql").ok()?;
+    normalize_document(source, true, Some(&**schema))
+}
+
+/// Returns the normalized defer subselection (a selection set, like `{ a { b } }`), or `None` if
+/// it fails to parse.
+///
+/// Unlike operations, subselections are relative to the deferred path, so the type of their
+/// top-level selection set is unknown.
+pub(crate) fn normalize_subselection_str(source: &str) -> Option<String> {
+    normalize_document(source, false, None)
+}
+
+// `root_types`: whether top-level selection sets are typed after their operation type.
+// `schema`: the schema to resolve field types with, if known.
+fn normalize_document(source: &str, root_types: bool, schema: Option<&Schema>) -> Option<String> {
+    let mut document = ast::Document::parse(source, "operation.graphql").ok()?;
+    let fragments: HashMap<Name, Node<ast::FragmentDefinition>> = document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            ast::Definition::FragmentDefinition(fragment) => {
+                Some((fragment.name.clone(), fragment.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+    let mut expanded = true;
+    for definition in &mut document.definitions {
+        if let ast::Definition::OperationDefinition(operation) = definition {
+            let operation = operation.make_mut();
+            expanded &= expand_fragments(&mut operation.selection_set, &fragments, &mut Vec::new());
+            let root_type = match (schema, operation.operation_type) {
+                (Some(schema), operation_type) => schema
+                    .root_operation(operation_type)
+                    .map_or("", Name::as_str),
+                (None, ast::OperationType::Query) => "Query",
+                (None, ast::OperationType::Mutation) => "Mutation",
+                (None, ast::OperationType::Subscription) => "Subscription",
+            };
+            normalize_selection_set(
+                &mut operation.selection_set,
+                root_types.then_some(root_type),
+                schema,
+            );
+        }
+    }
+    // Fragments are only kept when some spread can't be expanded, i.e. for invalid operations.
+    if expanded {
+        document
+            .definitions
+            .retain(|definition| !matches!(definition, ast::Definition::FragmentDefinition(_)));
+    } else {
+        for definition in &mut document.definitions {
+            if let ast::Definition::FragmentDefinition(fragment) = definition {
+                let fragment = fragment.make_mut();
+                let type_condition = fragment.type_condition.clone();
+                normalize_selection_set(
+                    &mut fragment.selection_set,
+                    Some(type_condition.as_str()),
+                    schema,
+                );
+            }
+        }
+    }
+    Some(document.to_string())
+}
+
+// Replaces fragment spreads with inline fragments, recursively. Returns false if a spread names an
+// unknown fragment, or a fragment spreading itself (`expanding` holds the fragments being
+// expanded); those spreads are kept.
+fn expand_fragments(
+    selections: &mut [ast::Selection],
+    fragments: &HashMap<Name, Node<ast::FragmentDefinition>>,
+    expanding: &mut Vec<Name>,
+) -> bool {
+    let mut expanded = true;
+    for selection in selections {
+        match selection {
+            ast::Selection::Field(field) => {
+                expanded &=
+                    expand_fragments(&mut field.make_mut().selection_set, fragments, expanding);
+            }
+            ast::Selection::InlineFragment(fragment) => {
+                expanded &=
+                    expand_fragments(&mut fragment.make_mut().selection_set, fragments, expanding);
+            }
+            ast::Selection::FragmentSpread(spread) => {
+                let directives = spread.directives.clone();
+                let Some(fragment) = fragments
+                    .get(&spread.fragment_name)
+                    .filter(|fragment| !expanding.contains(&fragment.name))
+                else {
+                    expanded = false;
+                    continue;
+                };
+                let mut selection_set = fragment.selection_set.clone();
+                expanding.push(fragment.name.clone());
+                expanded &= expand_fragments(&mut selection_set, fragments, expanding);
+                expanding.pop();
+                *selection = ast::Selection::InlineFragment(Node::new(ast::InlineFragment {
+                    type_condition: Some(fragment.type_condition.clone()),
+                    directives,
+                    selection_set,
+                }));
+            }
+        }
+    }
+    expanded
+}
+
+// Documents failing to parse are returned unchanged, and compared as strings.
+pub(crate) fn normalize_operation(document: &SerializableDocument) -> SerializableDocument {
+    match normalize_operation_str(document.as_serialized()) {
+        Some(normalized) => serialized_document(normalized),
+        None => document.clone(),
+    }
+}
+
+// Documents failing to parse are returned unchanged.
+pub(crate) fn canonical_operation(document: &SerializableDocument) -> SerializableDocument {
+    match canonical_operation_str(document.as_serialized()) {
+        Some(canonical) => serialized_document(canonical),
+        None => document.clone(),
+    }
+}
+
+// Returns `None` for documents that aren't valid against the schema.
+pub(crate) fn normalize_operation_against(
+    document: &SerializableDocument,
+    schema: &Valid<Schema>,
+) -> Option<SerializableDocument> {
+    normalize_operation_with_schema(document.as_serialized(), schema).map(serialized_document)
+}
+
+fn serialized_document(source: String) -> SerializableDocument {
+    serde_json::from_value(serde_json::Value::String(source))
+        .expect("serialized documents deserialize from strings")
+}
+
+// `parent_type` is the type of the selection set, if known. With a schema, the types of fields are
+// resolved from it.
+fn normalize_selection_set(
+    selections: &mut Vec<ast::Selection>,
+    parent_type: Option<&str>,
+    schema: Option<&Schema>,
+) {
+    flatten_parent_type_fragments(selections, parent_type);
+    let mut merged: Vec<ast::Selection> = Vec::with_capacity(selections.len());
+    for selection in selections.drain(..) {
+        match merged
+            .iter_mut()
+            .find(|existing| same_merge_target(existing, &selection))
+        {
+            Some(existing) => merge_into(existing, selection),
+            None => merged.push(selection),
+        }
+    }
+    for selection in &mut merged {
+        match selection {
+            ast::Selection::Field(field) => {
+                let field_type = schema.zip(parent_type).and_then(|(schema, parent_type)| {
+                    let definition = schema.type_field(parent_type, &field.name).ok()?;
+                    Some(definition.ty.inner_named_type().clone())
+                });
+                normalize_selection_set(
+                    &mut field.make_mut().selection_set,
+                    field_type.as_ref().map(Name::as_str),
+                    schema,
+                );
+            }
+            ast::Selection::InlineFragment(fragment) => {
+                let fragment = fragment.make_mut();
+                let type_condition = fragment.type_condition.clone();
+                normalize_selection_set(
+                    &mut fragment.selection_set,
+                    type_condition.as_ref().map(Name::as_str).or(parent_type),
+                    schema,
+                );
+            }
+            ast::Selection::FragmentSpread(_) => {}
+        }
+    }
+    // Note: The sort is stable, so selections with the same key (e.g. differently aliased
+    //       fields) keep their relative order.
+    merged.sort_by(|x, y| sort_key(x).cmp(&sort_key(y)));
+    *selections = merged;
+}
+
+// Splices the selections of inline fragments on the parent type (or without a type condition) and
+// without directives into the parent selection set, recursively.
+fn flatten_parent_type_fragments(selections: &mut Vec<ast::Selection>, parent_type: Option<&str>) {
+    let is_flattened = |fragment: &ast::InlineFragment| {
+        fragment.directives.is_empty()
+            && fragment
+                .type_condition
+                .as_ref()
+                .is_none_or(|type_condition| Some(type_condition.as_str()) == parent_type)
+    };
+    if !selections.iter().any(|selection| {
+        matches!(selection, ast::Selection::InlineFragment(fragment) if is_flattened(fragment))
+    }) {
+        return;
+    }
+    let mut flattened = Vec::with_capacity(selections.len());
+    for selection in selections.drain(..) {
+        match selection {
+            ast::Selection::InlineFragment(fragment) if is_flattened(&fragment) => {
+                let mut nested = fragment.selection_set.clone();
+                flatten_parent_type_fragments(&mut nested, parent_type);
+                flattened.extend(nested);
+            }
+            selection => flattened.push(selection),
+        }
+    }
+    *selections = flattened;
+}
+
+// `selection` is a merge target of `existing` (see `same_merge_target`).
+fn merge_into(existing: &mut ast::Selection, selection: ast::Selection) {
+    match (existing, selection) {
+        (ast::Selection::Field(existing), ast::Selection::Field(field)) => existing
+            .make_mut()
+            .selection_set
+            .extend(field.selection_set.iter().cloned()),
+        (ast::Selection::InlineFragment(existing), ast::Selection::InlineFragment(fragment)) => {
+            existing
+                .make_mut()
+                .selection_set
+                .extend(fragment.selection_set.iter().cloned())
+        }
+        // A repeated fragment spread
+        _ => {}
+    }
+}
+
+fn same_merge_target(x: &ast::Selection, y: &ast::Selection) -> bool {
+    match (x, y) {
+        (ast::Selection::Field(x), ast::Selection::Field(y)) => {
+            x.alias == y.alias
+                && x.name == y.name
+                && x.arguments == y.arguments
+                && x.directives == y.directives
+        }
+        (ast::Selection::InlineFragment(x), ast::Selection::InlineFragment(y)) => {
+            x.type_condition == y.type_condition && x.directives == y.directives
+        }
+        (ast::Selection::FragmentSpread(x), ast::Selection::FragmentSpread(y)) => {
+            x.fragment_name == y.fragment_name && x.directives == y.directives
+        }
+        _ => false,
+    }
+}
+
+fn sort_key(selection: &ast::Selection) -> (u8, &str) {
+    match selection {
+        ast::Selection::Field(field) => (0, field.alias.as_ref().unwrap_or(&field.name).as_str()),
+        ast::Selection::InlineFragment(fragment) => (
+            1,
+            fragment
+                .type_condition
+                .as_ref()
+                .map_or("", |type_condition| type_condition.as_str()),
+        ),
+        ast::Selection::FragmentSpread(spread) => (2, spread.fragment_name.as_str()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn same_normalized(x: &str, y: &str) -> bool {
+        normalize_operation_str(x).unwrap() == normalize_operation_str(y).unwrap()
+    }
+
+    #[test]
+    fn test_duplicate_fields_are_merged() {
+        assert!(same_normalized(
+            "{ topProducts { upc } topProducts { name upc } }",
+            "{ topProducts { name upc } }",
+        ));
+        assert!(!same_normalized(
+            "{ topProducts(first: 1) { upc } topProducts { name } }",
+            "{ topProducts { name upc } }",
+        ));
+    }
+
+    #[test]
+    fn test_inline_fragments_are_merged_and_sorted_after_fields() {
+        assert!(same_normalized(
+            "query($representations: [_Any!]!) { _entities(representations: $representations) { \
+             ... on Product { reviews { id } } __typename ... on Product { reviews { body } } } }",
+            "query($representations: [_Any!]!) { _entities(representations: $representations) { \
+             __typename ... on Product { reviews { body id } } } }",
+        ));
+    }
+
+    #[test]
+    fn test_repeated_fragment_spreads_are_removed() {
+        assert!(same_normalized(
+            "{ me { ...UserFields id ...UserFields } } fragment UserFields on User { name }",
+            "{ me { id ...UserFields } } fragment UserFields on User { name }",
+        ));
+    }
+
+    #[test]
+    fn test_fragments_are_expanded() {
+        assert!(same_normalized(
+            "query($representations: [_Any!]!) { _entities(representations: $representations) { \
+             ...ProductFields } } fragment ProductFields on Product { upc name }",
+            "query($representations: [_Any!]!) { _entities(representations: $representations) { \
+             ... on Product { name upc } } }",
+        ));
+        assert!(same_normalized(
+            "{ ...Root } fragment Root on Query { me { id } }",
+            "{ me { id } }",
+        ));
+    }
+
+    #[test]
+    fn test_fragments_on_the_enclosing_type_are_flattened() {
+        assert!(same_normalized(
+            "query($representations: [_Any!]!) { _entities(representations: $representations) { \
+             ... on Product { ...Upc name } } } fragment Upc on Product { upc }",
+            "query($representations: [_Any!]!) { _entities(representations: $representations) { \
+             ... on Product { name upc } } }",
+        ));
+        // The type of `me` is unknown.
+        assert!(!same_normalized(
+            "{ me { ...UserFields } } fragment UserFields on User { id }",
+            "{ me { id } }",
+        ));
+    }
+
+    #[test]
+    fn test_recursive_fragments_are_kept() {
+        let normalized = normalize_operation_str("{ ...A } fragment A on Query { ...A }").unwrap();
+        assert!(normalized.contains("fragment A"), "{normalized}");
+    }
+
+    #[test]
+    fn test_fields_with_different_directives_are_kept() {
+        let normalized = normalize_operation_str("{ me { id @skip(if: $skip) id } }").unwrap();
+        assert_eq!(normalized.matches("id").count(), 2, "{normalized}");
+    }
+
+    #[test]
+    fn test_subselections_have_no_root_type() {
+        let normalize = |source| normalize_subselection_str(source).unwrap();
+        assert_eq!(
+            normalize("{ reviews { body } ... { reviews { id } } }"),
+            normalize("{\n  reviews {\n    id\n    body\n  }\n}")
+        );
+        assert_ne!(
+            normalize("{ ... on Query { reviews { id } } }"),
+            normalize("{ reviews { id } }")
+        );
+    }
+
+    #[test]
+    fn test_invalid_operations_are_not_normalized() {
+        assert_eq!(normalize_operation_str("{ me { id }"), None);
+    }
+}
@@ -0,0 +1,36 @@
+//! Plan fixtures and helpers shared by the unit tests of the `router` modules.
+
+use crate::router::ignore::apply_compare_options;
+use crate::router::plan::PlanNode;
+use crate::router::plan_compare::CompareOptions;
+use crate::router::plan_compare::opt_plan_node_matches;
+
+/// Parses a plan node, e.g. a fixture loaded with `include_str!`.
+pub(crate) fn parse_plan(json: &str) -> PlanNode {
+    serde_json::from_str(json).unwrap()
+}
+
+/// `Sequence [Fetch, Parallel [Flatten, Condition]]`
+pub(crate) fn parallel_plan() -> PlanNode {
+    parse_plan(include_str!("../../tests/fixtures/plans/parallel.json"))
+}
+
+/// The legacy plan of `tests/fixtures/operations/defer.graphql`, planned with defer enabled.
+pub(crate) fn defer_plan() -> PlanNode {
+    parse_plan(include_str!("../../tests/fixtures/plans/defer.json"))
+}
+
+/// The children of a `Sequence` or `Parallel` node.
+pub(crate) fn nodes_mut(node: &mut PlanNode) -> &mut Vec<PlanNode> {
+    match node {
+        PlanNode::Sequence { nodes } | PlanNode::Parallel { nodes } => nodes,
+        _ => panic!("expected a Sequence or Parallel node"),
+    }
+}
+
+/// Whether the plans match once `options` are applied to both.
+pub(crate) fn matches_with(this: &PlanNode, other: &PlanNode, options: &CompareOptions) -> bool {
+    let this = apply_compare_options(this, options);
+    let other = apply_compare_options(other, options);
+    opt_plan_node_matches(&Some(this), &Some(other)).is_ok()
+}
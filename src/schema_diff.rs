@@ -0,0 +1,145 @@
+//! Coarse differences between two versions of a schema, to relate a plan change to the schema
+//! changes that may cause it.
+//!
+//! Definitions are compared by name, after printing them with `apollo_compiler`, so formatting
+//! differences are ignored. Type extensions are compared together with the type they extend.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use apollo_compiler::ast;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// A top-level schema definition (type, directive, or schema definition) that differs between
+/// two schema versions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaChange {
+    /// The type name, `@name` for a directive, or `schema`.
+    pub name: String,
+    pub kind: ChangeKind,
+}
+
+impl SchemaChange {
+    /// Returns true if `text` (e.g. a rendered query plan) mentions the changed definition by
+    /// name, as a whole GraphQL name.
+    pub fn is_mentioned_in(&self, text: &str) -> bool {
+        let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        text.match_indices(&self.name).any(|(start, name)| {
+            !text[..start].ends_with(is_name_char)
+                && !text[start + name.len()..].starts_with(is_name_char)
+        })
+    }
+}
+
+impl fmt::Display for SchemaChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Modified => "modified",
+        };
+        write!(f, "{kind} `{}`", self.name)
+    }
+}
+
+/// Lists the definitions that differ between two schema versions, in name order.
+pub fn schema_changes(old_schema: &str, new_schema: &str) -> Result<Vec<SchemaChange>, String> {
+    let old = definitions(old_schema)?;
+    let new = definitions(new_schema)?;
+    let mut changes: Vec<SchemaChange> = old
+        .iter()
+        .filter_map(|(name, definition)| {
+            let kind = match new.get(name) {
+                None => ChangeKind::Removed,
+                Some(new_definition) if new_definition != definition => ChangeKind::Modified,
+                Some(_) => return None,
+            };
+            Some(SchemaChange {
+                name: name.clone(),
+                kind,
+            })
+        })
+        .chain(
+            new.keys()
+                .filter(|name| !old.contains_key(*name))
+                .map(|name| SchemaChange {
+                    name: name.clone(),
+                    kind: ChangeKind::Added,
+                }),
+        )
+        .collect();
+    changes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(changes)
+}
+
+// Printed definitions by name. A type's extensions are appended to its definition.
+fn definitions(schema: &str) -> Result<BTreeMap<String, String>, String> {
+    let document = ast::Document::parse(schema, "schema.graphql")
+        .map_err(|with_errors| format!("Invalid schema: {}", with_errors.errors))?;
+    let mut definitions: BTreeMap<String, String> = BTreeMap::new();
+    for definition in &document.definitions {
+        let name = match definition {
+            ast::Definition::DirectiveDefinition(directive) => format!("@{}", directive.name),
+            ast::Definition::SchemaDefinition(_) | ast::Definition::SchemaExtension(_) => {
+                "schema".to_string()
+            }
+            definition => match definition.name() {
+                Some(name) => name.to_string(),
+                // Executable definitions don't belong in a schema.
+                None => continue,
+            },
+        };
+        let printed = definitions.entry(name).or_default();
+        printed.push_str(&definition.to_string());
+    }
+    Ok(definitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_changes() {
+        let old = "type Query { a: A } type A { id: ID! } type B { id: ID! }";
+        let new = "
+            type Query { a: A }
+            type A { id: ID! name: String }
+            directive @key(fields: String!) on OBJECT
+            type C { id: ID! }
+        ";
+        let changes: Vec<String> = schema_changes(old, new)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            changes,
+            ["added `@key`", "modified `A`", "removed `B`", "added `C`"]
+        );
+    }
+
+    #[test]
+    fn test_is_mentioned_in() {
+        let change = SchemaChange {
+            name: "Product".to_string(),
+            kind: ChangeKind::Modified,
+        };
+        assert!(change.is_mentioned_in("... on Product { upc }"));
+        assert!(!change.is_mentioned_in("topProducts { upc }"));
+        assert!(!change.is_mentioned_in("... on ProductVariant { id }"));
+    }
+
+    #[test]
+    fn test_formatting_is_ignored() {
+        let old = "type Query { a: Int }";
+        let new = "type Query {\n  a: Int\n}\n";
+        assert_eq!(schema_changes(old, new), Ok(Vec::new()));
+    }
+}
@@ -0,0 +1,78 @@
+//! Consistency checks on the structure of plans, passed with `--validate-structure`.
+//!
+//! The comparison only checks that both plans are alike, so a malformed plan can go unnoticed when
+//! the other planner emits the same, or when the comparison ignores the malformed part (e.g. with
+//! `--ignore-identifiers`). These checks catch them in either plan: the `depends` of each deferred
+//! node must name fetches of the primary part of its `Defer` node, which it waits for.
+
+use std::collections::HashSet;
+
+use crate::metrics::for_each_node;
+use crate::plan::PlanNode;
+use crate::router::plan_compare::collect_fetches;
+
+/// Lists the dependencies of deferred nodes on fetch ids missing from the primary part of their
+/// `Defer` node, naming the deferred node (by label and query path) and the id of each.
+pub fn dangling_depends(node: Option<&PlanNode>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let Some(node) = node else {
+        return problems;
+    };
+    for_each_node(node, &mut |node| {
+        let PlanNode::Defer { primary, deferred } = node else {
+            return;
+        };
+        let mut fetches = Vec::new();
+        if let Some(node) = &primary.node {
+            collect_fetches(node, &mut fetches);
+        }
+        let ids: HashSet<&str> = fetches
+            .iter()
+            .filter_map(|fetch| fetch.id.as_deref())
+            .collect();
+        for deferred in deferred {
+            let label = match &deferred.label {
+                Some(label) => format!("`{label}`"),
+                None => "without label".to_string(),
+            };
+            for depends in &deferred.depends {
+                if !ids.contains(depends.id.as_str()) {
+                    problems.push(format!(
+                        "deferred node {label} at `{}` depends on fetch `{}`, \
+                         missing from its primary node",
+                        deferred.query_path, depends.id
+                    ));
+                }
+            }
+        }
+    });
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::testing::defer_plan;
+    use crate::router::testing::parse_plan;
+
+    #[test]
+    fn test_resolved_depends() {
+        let node = defer_plan();
+        assert!(dangling_depends(Some(&node)).is_empty());
+        assert!(dangling_depends(None).is_empty());
+    }
+
+    #[test]
+    fn test_dangling_depends() {
+        let node = parse_plan(include_str!(
+            "../tests/fixtures/plans/defer_dangling_depends.json"
+        ));
+        assert_eq!(
+            dangling_depends(Some(&node)),
+            [
+                "deferred node `reviews` at `/topProducts/@` depends on fetch `1`, missing from its \
+                 primary node"
+            ]
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! The operations a plan sends to each subgraph, for analysis tooling that only cares about what
+//! the subgraphs receive, not how the plan is structured, and the subgraph schemas to compare them
+//! with.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use apollo_compiler::Schema;
+use apollo_compiler::validation::Valid;
+
+use crate::FederationError;
+use crate::LegacyQueryPlanResult;
+use crate::NativeQueryPlan;
+use crate::plan::PlanNode;
+use crate::router::convert_native_plan;
+use crate::router::plan_compare::collect_fetches;
+
+/// Maps each service name to the operations sent to it, by the fetch nodes and the subscription
+/// primary of the plan.
+///
+/// Operations are listed in declaration order within the plan tree (depth first, the subscription
+/// primary first), which is not necessarily execution order. Identical operations sent several
+/// times are listed every time.
+pub fn subgraph_operations(node: Option<&PlanNode>) -> BTreeMap<String, Vec<String>> {
+    let mut operations: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let Some(node) = node else {
+        return operations;
+    };
+    let mut add = |service_name: &str, operation: &str| {
+        operations
+            .entry(service_name.to_string())
+            .or_default()
+            .push(operation.to_string());
+    };
+    if let PlanNode::Subscription { primary, .. } = node {
+        add(&primary.service_name, primary.operation.as_serialized());
+    }
+    let mut fetches = Vec::new();
+    collect_fetches(node, &mut fetches);
+    for fetch in fetches {
+        add(&fetch.service_name, fetch.operation.as_serialized());
+    }
+    operations
+}
+
+pub fn legacy_subgraph_operations(
+    js_plan: &LegacyQueryPlanResult,
+) -> BTreeMap<String, Vec<String>> {
+    subgraph_operations(js_plan.root_node())
+}
+
+pub fn native_subgraph_operations(rust_plan: &NativeQueryPlan) -> BTreeMap<String, Vec<String>> {
+    subgraph_operations(convert_native_plan(rust_plan).as_ref())
+}
+
+/// Subgraph schemas by service name, for comparing subgraph operations with their types (see
+/// `CompareOptions::subgraph_schemas`). Cheap to clone.
+#[derive(Clone, Default)]
+pub struct SubgraphSchemas(Arc<BTreeMap<String, Valid<Schema>>>);
+
+impl SubgraphSchemas {
+    /// Parses and validates the schema of a subgraph, expanded with the federation definitions
+    /// subgraphs implicitly have (e.g. `Query._entities`), replacing any schema of the same name.
+    pub fn insert(&mut self, name: &str, schema_str: &str) -> Result<(), FederationError> {
+        let subgraph = apollo_federation::subgraph::Subgraph::parse_and_expand(
+            name,
+            &format!("http://{name}"),
+            schema_str,
+        )?;
+        Arc::make_mut(&mut self.0).insert(name.to_string(), subgraph.schema);
+        Ok(())
+    }
+
+    pub fn get(&self, service_name: &str) -> Option<&Valid<Schema>> {
+        self.0.get(service_name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The service names with a schema, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+}
+
+// Note: Schemas are compared in their printed form, since `Schema` doesn't implement `Eq`.
+impl PartialEq for SubgraphSchemas {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+            || (self.0.len() == other.0.len()
+                && self
+                    .0
+                    .iter()
+                    .zip(other.0.iter())
+                    .all(|(x, y)| x.0 == y.0 && x.1.to_string() == y.1.to_string()))
+    }
+}
+
+impl Eq for SubgraphSchemas {}
+
+impl fmt::Debug for SubgraphSchemas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::testing::parallel_plan;
+
+    #[test]
+    fn test_fixture_subgraph_operations() {
+        let operations = subgraph_operations(Some(&parallel_plan()));
+        assert_eq!(
+            operations.keys().collect::<Vec<_>>(),
+            ["inventory", "products", "reviews"]
+        );
+        assert_eq!(operations["products"].len(), 1);
+        assert!(operations["products"][0].starts_with("query TopProducts__products__0"));
+        assert!(subgraph_operations(None).is_empty());
+    }
+
+    #[test]
+    fn test_subgraph_schemas() {
+        let mut schemas = SubgraphSchemas::default();
+        assert!(schemas.is_empty());
+        schemas
+            .insert(
+                "products",
+                "type Query { topProducts: [Product] } \
+                 type Product @key(fields: \"upc\") { upc: String! name: String }",
+            )
+            .unwrap();
+        let products = schemas.get("products").unwrap();
+        assert!(products.type_field("Query", "_entities").is_ok());
+        assert!(schemas.get("reviews").is_none());
+        assert_eq!(format!("{schemas:?}"), "[\"products\"]");
+        assert_eq!(schemas, schemas.clone());
+        assert_ne!(schemas, SubgraphSchemas::default());
+        assert!(schemas.insert("reviews", "type Query {").is_err());
+    }
+}
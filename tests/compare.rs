@@ -0,0 +1,177 @@
+//! End-to-end comparisons of both planners on the bundled supergraph.
+//!
+//! To reproduce a reported divergence, add its operation under `tests/fixtures/operations` (and
+//! its subgraphs to the supergraph fixture if needed), then a test asserting the expected outcome.
+
+use qp_compare::CompareOptions;
+use qp_compare::comparator::Comparator;
+use qp_compare::legacy_planner;
+use qp_compare::native_planner;
+use qp_compare::operation::apply_conditions;
+use qp_compare::operation::merge_fragments;
+use qp_compare::outcome::ComparisonOutcome;
+use qp_compare::outcome::compare_detailed;
+use qp_compare::outcome::compare_operation;
+
+const SUPERGRAPH: &str = include_str!("fixtures/supergraph.graphql");
+
+/// Planner configs with defer enabled, and otherwise as close as possible.
+fn planner_configs() -> (
+    native_planner::QueryPlannerConfig,
+    legacy_planner::QueryPlannerConfig,
+) {
+    let native_config = native_planner::QueryPlannerConfig {
+        incremental_delivery: native_planner::QueryPlanIncrementalDeliveryConfig {
+            enable_defer: true,
+        },
+        ..Default::default()
+    };
+    let legacy_config = legacy_planner::QueryPlannerConfig {
+        incremental_delivery: Some(legacy_planner::IncrementalDeliverySupport {
+            enable_defer: Some(true),
+        }),
+        reuse_query_fragments: Some(false),
+        generate_query_fragments: Some(native_config.generate_query_fragments),
+        ..Default::default()
+    };
+    (native_config, legacy_config)
+}
+
+/// Compares the plans of both planners for a single-operation document, with defer enabled.
+fn compare_str(schema: &str, query: &str) -> ComparisonOutcome {
+    let (native_config, legacy_config) = planner_configs();
+    compare_operation(
+        schema,
+        query,
+        None,
+        native_config,
+        legacy_config,
+        &CompareOptions::default(),
+    )
+}
+
+fn assert_match(query: &str) {
+    let outcome = compare_str(SUPERGRAPH, query);
+    assert!(outcome.is_match(), "{outcome:#?}");
+}
+
+#[test]
+fn test_simple_fetch() {
+    assert_match(include_str!("fixtures/operations/anonymous.graphql"));
+}
+
+#[test]
+fn test_entity_join() {
+    assert_match(include_str!("fixtures/operations/entity_join.graphql"));
+}
+
+#[test]
+fn test_defer() {
+    assert_match(include_str!("fixtures/operations/defer.graphql"));
+}
+
+#[test]
+fn test_condition() {
+    assert_match(include_str!("fixtures/operations/condition.graphql"));
+}
+
+#[test]
+fn test_shared_fragments() {
+    let query = merge_fragments(
+        include_str!("fixtures/shared_fragments/top_products.graphql"),
+        include_str!("fixtures/shared_fragments/fragments.graphql"),
+    )
+    .unwrap();
+    assert!(!query.contains("ProductStock"), "{query}");
+    assert_match(&query);
+}
+
+#[test]
+fn test_applied_conditions() {
+    let query = include_str!("fixtures/operations/include.graphql");
+    for with_reviews in [false, true] {
+        let variables = serde_json::json!({ "withReviews": with_reviews });
+        let query = apply_conditions(query, variables.as_object().unwrap()).unwrap();
+        assert_match(&query);
+    }
+}
+
+#[test]
+fn test_invalid_operation() {
+    let outcome = compare_str(SUPERGRAPH, "{ unknownField }");
+    let ComparisonOutcome::InvalidOperation(errors) = outcome else {
+        panic!("expected an invalid operation: {outcome:#?}");
+    };
+    assert!(errors.contains("`unknownField`"), "{errors}");
+    assert!(errors.contains("operation.graphql:1:3"), "{errors}");
+}
+
+#[test]
+fn test_comparator_reuses_planners() {
+    let (native_config, legacy_config) = planner_configs();
+    let comparator = Comparator::new(
+        SUPERGRAPH,
+        native_config,
+        legacy_config,
+        CompareOptions::default(),
+    )
+    .unwrap();
+    for query in [
+        include_str!("fixtures/operations/anonymous.graphql"),
+        include_str!("fixtures/operations/entity_join.graphql"),
+        include_str!("fixtures/operations/anonymous.graphql"),
+    ] {
+        let outcome = comparator.compare(query, None);
+        assert!(outcome.is_match(), "{outcome:#?}");
+    }
+    let outcome = comparator.compare("{ topProducts { unknownField } }", None);
+    assert!(
+        matches!(outcome, ComparisonOutcome::InvalidOperation(_)),
+        "{outcome:#?}"
+    );
+}
+
+#[test]
+fn test_compare_detailed() {
+    let (native_config, legacy_config) = planner_configs();
+    let detailed = compare_detailed(
+        SUPERGRAPH,
+        include_str!("fixtures/operations/entity_join.graphql"),
+        None,
+        native_config.clone(),
+        legacy_config.clone(),
+        &CompareOptions::default(),
+    );
+    assert!(detailed.outcome.is_match(), "{:#?}", detailed.outcome);
+    assert!(detailed.native_plan.is_some() && detailed.legacy_plan.is_some());
+    assert!(detailed.render_native_plan().contains("Fetch"));
+    assert!(detailed.render_legacy_plan().contains("Fetch"));
+    let json = detailed.plans_to_json();
+    assert_eq!(json["native_plan"]["kind"], json["legacy_plan"]["kind"]);
+
+    let detailed = compare_detailed(
+        SUPERGRAPH,
+        "{ unknownField }",
+        None,
+        native_config,
+        legacy_config,
+        &CompareOptions::default(),
+    );
+    assert!(matches!(
+        detailed.outcome,
+        ComparisonOutcome::InvalidOperation(_)
+    ));
+    assert!(detailed.native_plan.is_none() && detailed.legacy_plan.is_none());
+}
+
+#[test]
+fn test_comparator_rejects_invalid_schemas() {
+    let (native_config, legacy_config) = planner_configs();
+    let comparator = Comparator::new(
+        "type Query { a: Int }",
+        native_config,
+        legacy_config,
+        CompareOptions::default(),
+    );
+    assert!(comparator.is_err());
+}